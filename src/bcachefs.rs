@@ -1,7 +1,11 @@
 mod commands;
 mod key;
+mod mount;
+mod tpm2;
+mod keyslots;
 mod dump_stack;
 mod logging;
+mod metrics;
 mod util;
 mod wrappers;
 mod device_scan;
@@ -133,12 +137,20 @@ fn main() -> ExitCode {
             commands::completions(args[1..].to_vec());
             ExitCode::SUCCESS
         }
+        "browse" => commands::browse(args[1..].to_vec()).report(),
+        "catalog" => commands::catalog(args[1..].to_vec()).report(),
+        "explore" => commands::explore(args[1..].to_vec()).report(),
         "list" => commands::list(args[1..].to_vec()).report(),
+        "metadata-pack" => commands::metadata_pack(args[1..].to_vec()).report(),
+        "metadata-unpack" => commands::metadata_unpack(args[1..].to_vec()).report(),
         "mount" => commands::mount(args, symlink_cmd),
+        "rmap" => commands::rmap(args[1..].to_vec()).report(),
         "scrub" => commands::scrub(args[1..].to_vec()).report(),
         "subvolume" => commands::subvolume(args[1..].to_vec()).report(),
         "data" => match args.get(2).map(|s| s.as_str()) {
             Some("scrub") => commands::scrub(args[2..].to_vec()).report(),
+            Some("rereplicate") | Some("migrate") | Some("rewrite_old_nodes") | Some("drop_extra_replicas") =>
+                commands::data(args[1..].to_vec()).report(),
             _ => c_command(args, symlink_cmd),
         },
         "device" => match args.get(2).map(|s| s.as_str()) {
@@ -158,24 +170,31 @@ fn main() -> ExitCode {
                 Ok(false) => c_command(args, symlink_cmd),
                 Err(e) => { eprintln!("Error: {e:#}"); ExitCode::FAILURE }
             },
+            Some("image") => commands::cmd_device_image(args[2..].to_vec()).report(),
+            Some("discard") => commands::cmd_device_discard(args[2..].to_vec()).report(),
             _ => c_command(args, symlink_cmd),
         },
         "fs" => match args.get(2).map(|s| s.as_str()) {
             Some("timestats") => commands::timestats(args[2..].to_vec()).report(),
             Some("top") => commands::top(args[2..].to_vec()).report(),
             Some("usage") => commands::fs_usage::fs_usage(args[2..].to_vec()).report(),
+            Some("fsck") => commands::fsck_online(args[2..].to_vec()).report(),
+            Some("recovery-pass") => commands::recovery_pass(args[2..].to_vec()).report(),
             _ => {
                 println!("bcachefs fs - manage a running filesystem");
-                println!("Usage: bcachefs fs <usage|top|timestats> [OPTION]...\n");
+                println!("Usage: bcachefs fs <usage|top|timestats|fsck|recovery-pass> [OPTION]...\n");
                 println!("Commands:");
                 println!("  usage                        Display detailed filesystem usage");
                 println!("  top                          Show runtime performance information");
                 println!("  timestats                    Show filesystem time statistics");
+                println!("  fsck                         Check a mounted filesystem without unmounting");
+                println!("  recovery-pass                Run specific online recovery passes without unmounting");
                 ExitCode::from(1)
             }
         },
         "reset-counters" => commands::cmd_reset_counters(args[1..].to_vec()).report(),
         "set-file-option" => commands::cmd_setattr(args[1..].to_vec()).report(),
+        "get-file-option" => commands::cmd_getattr(args[1..].to_vec()).report(),
         "reflink-option-propagate" => commands::cmd_reflink_option_propagate(args[1..].to_vec()).report(),
         _ => c_command(args, symlink_cmd),
     }
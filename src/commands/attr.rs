@@ -5,7 +5,7 @@ use std::path::Path;
 
 use anyhow::{anyhow, Result};
 use bch_bindgen::c;
-use rustix::fs::{XattrFlags, setxattr, removexattr};
+use rustix::fs::{XattrFlags, setxattr, removexattr, getxattr};
 
 const BCHFS_IOC_REINHERIT_ATTRS: libc::c_ulong = 0x8008bc40;
 const BCHFS_IOC_SET_REFLINK_P_MAY_UPDATE_OPTS: libc::c_ulong = 0xbc41;
@@ -27,7 +27,7 @@ fn inode_opt_names() -> Vec<String> {
     names
 }
 
-fn propagate_recurse(dir_path: &Path) {
+fn propagate_recurse(dir_path: &Path, dry_run: bool, verbose: bool) {
     let dir = match std::fs::File::open(dir_path) {
         Ok(f) => f,
         Err(e) => { eprintln!("{}: {e}", dir_path.display()); return }
@@ -42,33 +42,57 @@ fn propagate_recurse(dir_path: &Path) {
         if ft.is_symlink() { continue }
         let Ok(name) = CString::new(entry.file_name().as_bytes().to_vec()) else { continue };
 
+        if dry_run {
+            println!("would re-inherit: {}", entry.path().display());
+            if ft.is_dir() {
+                propagate_recurse(&entry.path(), dry_run, verbose);
+            }
+            continue;
+        }
+
         let ret = unsafe { libc::ioctl(dir.as_raw_fd(), BCHFS_IOC_REINHERIT_ATTRS, name.as_ptr()) };
         if ret < 0 {
             eprintln!("{}: {}", entry.path().display(), std::io::Error::last_os_error());
             continue;
         }
+        if verbose {
+            println!("re-inherited: {}", entry.path().display());
+        }
         if ret == 0 || !ft.is_dir() { continue }
-        propagate_recurse(&entry.path());
+        propagate_recurse(&entry.path(), dry_run, verbose);
     }
 }
 
-fn remove_bcachefs_attr(path: &Path, attr_name: &str) {
+fn remove_bcachefs_attr(path: &Path, attr_name: &str, dry_run: bool, verbose: bool) {
+    if dry_run {
+        println!("would remove {} from {}", attr_name, path.display());
+        return;
+    }
+
     if let Err(e) = removexattr(path, attr_name) {
         if e != rustix::io::Errno::NODATA && e != rustix::io::Errno::INVAL {
             eprintln!("error removing attribute {} from {}: {}",
                 attr_name, path.display(), e);
         }
+    } else if verbose {
+        println!("removed {} from {}", attr_name, path.display());
     }
 }
 
-fn do_setattr(path: &str, opts: &[(String, String)], remove_all: bool) -> Result<()> {
+fn do_setattr(
+    path: &str,
+    opts: &[(String, String)],
+    remove_all: bool,
+    dry_run: bool,
+    verbose: bool,
+) -> Result<()> {
     let path = Path::new(path);
 
     if remove_all {
         for name in inode_opt_names() {
             // casefold only works on empty directories
             if name == "casefold" { continue }
-            remove_bcachefs_attr(path, &format!("bcachefs.{}", name));
+            remove_bcachefs_attr(path, &format!("bcachefs.{}", name), dry_run, verbose);
         }
     }
 
@@ -76,10 +100,15 @@ fn do_setattr(path: &str, opts: &[(String, String)], remove_all: bool) -> Result
         let attr = format!("bcachefs.{}", name);
 
         if value == "-" {
-            remove_bcachefs_attr(path, &attr);
+            remove_bcachefs_attr(path, &attr, dry_run, verbose);
+        } else if dry_run {
+            println!("would set {}={} on {}", attr, value, path.display());
         } else {
             setxattr(path, &attr, value.as_bytes(), XattrFlags::empty())
                 .map_err(|e| anyhow!("setxattr error on {}: {}", path.display(), e))?;
+            if verbose {
+                println!("set {}={} on {}", attr, value, path.display());
+            }
         }
     }
 
@@ -87,7 +116,7 @@ fn do_setattr(path: &str, opts: &[(String, String)], remove_all: bool) -> Result
         .map_err(|e| anyhow!("stat error on {}: {}", path.display(), e))?
         .is_dir()
     {
-        propagate_recurse(path);
+        propagate_recurse(path, dry_run, verbose);
     }
     Ok(())
 }
@@ -99,13 +128,20 @@ fn setattr_usage() {
     unsafe { c::bch2_opts_usage(OPT_INODE) };
     println!("      --remove-all             Remove all file options");
     println!("                               To remove specific options, use: --option=-");
+    println!("      --dry-run                Report what would change, without changing it");
+    println!("  -v, --verbose                Report each change as it's made");
     println!("  -h, --help                   Display this help and exit");
 }
 
-/// Parse argv, extracting bcachefs inode options and returning (remove_all, opts, files).
-fn parse_setattr_args(argv: Vec<String>) -> Result<(bool, Vec<(String, String)>, Vec<String>)> {
+/// Parse argv, extracting bcachefs inode options and returning
+/// (remove_all, opts, files, dry_run, verbose).
+fn parse_setattr_args(
+    argv: Vec<String>,
+) -> Result<(bool, Vec<(String, String)>, Vec<String>, bool, bool)> {
     let valid_opts = inode_opt_names();
     let mut remove_all = false;
+    let mut dry_run = false;
+    let mut verbose = false;
     let mut opts = Vec::new();
     let mut files = Vec::new();
 
@@ -122,6 +158,16 @@ fn parse_setattr_args(argv: Vec<String>) -> Result<(bool, Vec<(String, String)>,
             i += 1;
             continue;
         }
+        if arg == "--dry-run" {
+            dry_run = true;
+            i += 1;
+            continue;
+        }
+        if arg == "-v" || arg == "--verbose" {
+            verbose = true;
+            i += 1;
+            continue;
+        }
         if arg.starts_with("--") {
             let rest = &arg[2..];
             let (name, value) = if let Some(eq) = rest.find('=') {
@@ -152,22 +198,119 @@ fn parse_setattr_args(argv: Vec<String>) -> Result<(bool, Vec<(String, String)>,
         i += 1;
     }
 
-    Ok((remove_all, opts, files))
+    Ok((remove_all, opts, files, dry_run, verbose))
 }
 
 pub fn cmd_setattr(argv: Vec<String>) -> Result<()> {
-    let (remove_all, opts, files) = parse_setattr_args(argv)?;
+    let (remove_all, opts, files, dry_run, verbose) = parse_setattr_args(argv)?;
 
     if files.is_empty() {
         return Err(anyhow!("Please supply one or more files"));
     }
 
     for path in &files {
-        do_setattr(path, &opts, remove_all)?;
+        do_setattr(path, &opts, remove_all, dry_run, verbose)?;
     }
     Ok(())
 }
 
+/// One option as read back from a file: its effective/explicit value, if any.
+struct OptValue {
+    name:       String,
+    value:      Option<String>,
+}
+
+fn get_opt_value(path: &Path, name: &str) -> OptValue {
+    let attr = format!("bcachefs.{}", name);
+    let mut buf = [0u8; 256];
+
+    let value = match getxattr(path, &attr, &mut buf) {
+        Ok(len) => Some(String::from_utf8_lossy(&buf[..len]).into_owned()),
+        Err(_) => None,
+    };
+
+    OptValue { name: name.to_string(), value }
+}
+
+fn getattr_usage() {
+    println!("bcachefs get-file-option - show bcachefs option values on files");
+    println!("Usage: bcachefs get-file-option [OPTION]... <files>\n");
+    println!("Options:");
+    println!("      --format json            Output machine-readable JSON");
+    println!("  -h, --help                   Display this help and exit");
+}
+
+fn parse_getattr_args(argv: Vec<String>) -> Result<(bool, Vec<String>)> {
+    let mut json = false;
+    let mut files = Vec::new();
+
+    let mut i = 1;
+    while i < argv.len() {
+        let arg = &argv[i];
+
+        if arg == "-h" || arg == "--help" {
+            getattr_usage();
+            std::process::exit(0);
+        }
+        if arg == "--format" {
+            let Some(fmt) = argv.get(i + 1) else {
+                return Err(anyhow!("--format requires an argument"));
+            };
+            if fmt != "json" {
+                return Err(anyhow!("unknown format '{}'", fmt));
+            }
+            json = true;
+            i += 2;
+            continue;
+        }
+        if arg.starts_with('-') && arg != "-" {
+            return Err(anyhow!("invalid option {}", arg));
+        }
+
+        files.push(arg.clone());
+        i += 1;
+    }
+
+    Ok((json, files))
+}
+
+pub fn cmd_getattr(argv: Vec<String>) -> Result<()> {
+    let (json, files) = parse_getattr_args(argv)?;
+
+    if files.is_empty() {
+        return Err(anyhow!("Please supply one or more files"));
+    }
+
+    let names = inode_opt_names();
+
+    for path in &files {
+        let path_obj = Path::new(path);
+        let values: Vec<OptValue> = names.iter().map(|n| get_opt_value(path_obj, n)).collect();
+
+        if json {
+            print!("{{\"path\":{:?},\"options\":{{", path);
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 { print!(","); }
+                match &v.value {
+                    Some(val) => print!("{:?}:{:?}", v.name, val),
+                    None => print!("{:?}:null", v.name),
+                }
+            }
+            println!("}}}}");
+        } else {
+            println!("{}:", path);
+            for v in &values {
+                match &v.value {
+                    Some(val) => println!("  {:<20} {} (explicit)", v.name, val),
+                    None => println!("  {:<20} - (inherited)", v.name),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn cmd_reflink_option_propagate(argv: Vec<String>) -> Result<()> {
     let has_help = argv.iter().any(|a| a == "-h" || a == "--help");
     let set_may_update = argv.iter().any(|a| a == "--set-may-update");
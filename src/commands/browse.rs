@@ -0,0 +1,404 @@
+//! Read-only FUSE view of the reconstructed directory tree, for exploring a
+//! filesystem the kernel module refuses to mount cleanly.
+//!
+//! Opens devices directly with the same safe, non-mutating options as
+//! `inode_opts_device::cmd_inode_opts_inner` (`nochanges`, `read_only`,
+//! `norecovery`, `degraded=very`, `errors=continue`), then builds the whole
+//! inode/dirent namespace into memory once at mount time. `getattr`,
+//! `readdir` and `readlink` are served directly from that in-memory index,
+//! with real bcachefs inums used as FUSE inode numbers (the bcachefs root
+//! directory, inum [`BCH_ROOT_INO`], is remapped to FUSE's required ino 1).
+//! Each inode's non-default options (the same set `inode_opts_device`
+//! decodes) are exposed as a synthetic `bcachefs.opts` xattr.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIter, BtreeIterFlags, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::Parser;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyXattr, Request,
+};
+use libc::{ENOENT, ERANGE};
+use std::path::PathBuf;
+
+use crate::commands::inode_opts_device::{get_dirent_name, resolve_devices};
+use crate::logging;
+
+const TTL: Duration = Duration::from_secs(1);
+
+/// The bcachefs root directory's inode number, for the default subvolume.
+const BCH_ROOT_INO: u64 = 4096;
+
+const OPT_NAMES: [&str; 9] = [
+    "data_checksum",
+    "compression",
+    "background_compression",
+    "data_replicas",
+    "promote_target",
+    "foreground_target",
+    "background_target",
+    "erasure_code",
+    "project",
+];
+
+const XATTR_NAME: &str = "bcachefs.opts";
+
+fn to_bch_inum(fuse_ino: u64) -> u64 {
+    if fuse_ino == fuser::FUSE_ROOT_ID { BCH_ROOT_INO } else { fuse_ino }
+}
+
+fn to_fuse_ino(bch_inum: u64) -> u64 {
+    if bch_inum == BCH_ROOT_INO { fuser::FUSE_ROOT_ID } else { bch_inum }
+}
+
+struct Inode {
+    mode: u32,
+    size: u64,
+    opts: Vec<(&'static str, u64)>,
+}
+
+/// The whole namespace, scanned once at mount time.
+struct Namespace {
+    inodes: HashMap<u64, Inode>,
+    /// inum -> sorted (child inum, name) pairs, for `readdir`.
+    children: HashMap<u64, Vec<(u64, String)>>,
+    /// inum -> parent inum, for resolving `..` in `readdir`.
+    parent_of: HashMap<u64, u64>,
+    symlinks: HashMap<u64, Vec<u8>>,
+    uid: u32,
+    gid: u32,
+}
+
+fn scan_namespace(fs: &Fs) -> Result<Namespace> {
+    let trans = BtreeTrans::new(fs);
+    let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_inodes, bch_bindgen::POS_MIN, flags);
+
+    let mut inodes = HashMap::new();
+    // child inum -> (parent, parent_offset), resolved into `children` once
+    // dirent names are known.
+    let mut parents: HashMap<u64, (u64, u64)> = HashMap::new();
+    let mut symlink_inums = Vec::new();
+    let mut last_inum: Option<u64> = None;
+
+    while let Some(k) = iter.peek_and_restart()? {
+        let inum = k.k.p.inode;
+        if last_inum == Some(inum) {
+            iter.advance();
+            continue;
+        }
+        last_inum = Some(inum);
+
+        if k.k.type_ != bcachefs::bch_bkey_type::KEY_TYPE_inode_v3 as u8 {
+            iter.advance();
+            continue;
+        }
+
+        let mut unpacked: c::bch_inode_unpacked = unsafe { std::mem::zeroed() };
+        let bkey_s_c = c::bkey_s_c { k: k.k, v: k.v };
+        if unsafe { c::bch2_inode_unpack(bkey_s_c, &mut unpacked) } != 0 {
+            iter.advance();
+            continue;
+        }
+
+        let mut opts = Vec::new();
+        macro_rules! check_opt {
+            ($field:ident, $name:expr) => {
+                if unpacked.$field != 0 {
+                    opts.push(($name, unpacked.$field as u64));
+                }
+            };
+        }
+        check_opt!(bi_data_checksum, "data_checksum");
+        check_opt!(bi_compression, "compression");
+        check_opt!(bi_background_compression, "background_compression");
+        check_opt!(bi_data_replicas, "data_replicas");
+        check_opt!(bi_promote_target, "promote_target");
+        check_opt!(bi_foreground_target, "foreground_target");
+        check_opt!(bi_background_target, "background_target");
+        check_opt!(bi_erasure_code, "erasure_code");
+        check_opt!(bi_project, "project");
+
+        if unpacked.bi_dir != 0 {
+            parents.insert(inum, (unpacked.bi_dir, unpacked.bi_dir_offset));
+        }
+        if (unpacked.bi_mode & 0o170000) == 0o120000 {
+            symlink_inums.push(inum);
+        }
+
+        inodes.insert(inum, Inode { mode: unpacked.bi_mode as u32, size: unpacked.bi_size, opts });
+        iter.advance();
+    }
+
+    let needed_dirents: std::collections::HashSet<(u64, u64)> = parents.values().copied().collect();
+    let dirent_names = scan_dirent_names(fs, &needed_dirents)?;
+
+    let mut children: HashMap<u64, Vec<(u64, String)>> = HashMap::new();
+    let mut parent_of: HashMap<u64, u64> = HashMap::new();
+    for (&inum, &(parent, offset)) in &parents {
+        if let Some(name) = dirent_names.get(&(parent, offset)) {
+            children.entry(parent).or_default().push((inum, name.clone()));
+            parent_of.insert(inum, parent);
+        }
+    }
+    for list in children.values_mut() {
+        list.sort();
+    }
+
+    let symlinks = if symlink_inums.is_empty() {
+        HashMap::new()
+    } else {
+        let needed: std::collections::HashSet<u64> = symlink_inums.into_iter().collect();
+        scan_symlink_targets(fs, &needed)?
+    };
+
+    Ok(Namespace {
+        inodes,
+        children,
+        parent_of,
+        symlinks,
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    })
+}
+
+fn scan_dirent_names(
+    fs: &Fs,
+    needed: &std::collections::HashSet<(u64, u64)>,
+) -> Result<HashMap<(u64, u64), String>> {
+    let trans = BtreeTrans::new(fs);
+    let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_dirents, bch_bindgen::POS_MIN, flags);
+
+    let mut names = HashMap::new();
+    while let Some(k) = iter.peek_and_restart()? {
+        let key = (k.k.p.inode, k.k.p.offset);
+        if needed.contains(&key) && k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_dirent as u8 {
+            if let Some(name) = get_dirent_name(k.v, k.k) {
+                names.insert(key, name);
+            }
+        }
+        iter.advance();
+    }
+    Ok(names)
+}
+
+/// Read short symlink targets out of `BTREE_ID_extents`' inline-data keys,
+/// the same way `get_dirent_name` splits a name out of its key's value
+/// buffer.
+fn scan_symlink_targets(fs: &Fs, needed: &std::collections::HashSet<u64>) -> Result<HashMap<u64, Vec<u8>>> {
+    let trans = BtreeTrans::new(fs);
+    let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_extents, bch_bindgen::POS_MIN, flags);
+
+    let mut targets = HashMap::new();
+    while let Some(k) = iter.peek_and_restart()? {
+        let inum = k.k.p.inode;
+        if needed.contains(&inum) && k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_inline_data as u8 {
+            unsafe {
+                let inline = k.v as *const c::bch_val as *const c::bch_inline_data;
+                let base_size = std::mem::size_of::<c::bch_inline_data>();
+                let val_bytes = (k.k.u64s as usize) * 8;
+                let data_len = val_bytes.saturating_sub(base_size);
+                if data_len > 0 && data_len < 4096 {
+                    let data_ptr = &(*inline).data as *const _ as *const u8;
+                    let data = std::slice::from_raw_parts(data_ptr, data_len).to_vec();
+                    targets.entry(inum).or_insert(data);
+                }
+            }
+        }
+        iter.advance();
+    }
+    Ok(targets)
+}
+
+impl Namespace {
+    fn attr(&self, inum: u64) -> Option<FileAttr> {
+        let inode = self.inodes.get(&inum)?;
+        let kind = match inode.mode & 0o170000 {
+            0o040000 => FileType::Directory,
+            0o120000 => FileType::Symlink,
+            _ => FileType::RegularFile,
+        };
+        let nlink = if kind == FileType::Directory { 2 } else { 1 };
+
+        Some(FileAttr {
+            ino: to_fuse_ino(inum),
+            size: inode.size,
+            blocks: inode.size.div_ceil(512),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm: (inode.mode & 0o7777) as u16,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+
+    fn xattr_value(&self, inum: u64) -> Option<Vec<u8>> {
+        let inode = self.inodes.get(&inum)?;
+        if inode.opts.is_empty() {
+            return None;
+        }
+        let s: Vec<String> = inode.opts.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        Some(s.join(" ").into_bytes())
+    }
+}
+
+struct BrowseFs {
+    ns: Namespace,
+}
+
+impl Filesystem for BrowseFs {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let Some(name) = name.to_str() else {
+            reply.error(ENOENT);
+            return;
+        };
+        let parent = to_bch_inum(parent);
+
+        let found = self.ns.children.get(&parent).and_then(|c| c.iter().find(|(_, n)| n == name));
+        match found.and_then(|&(inum, _)| self.ns.attr(inum)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.ns.attr(to_bch_inum(ino)) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        match self.ns.symlinks.get(&to_bch_inum(ino)) {
+            Some(target) => reply.data(target),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let inum = to_bch_inum(ino);
+        if !self.ns.inodes.contains_key(&inum) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let parent_ino = self.ns.parent_of.get(&inum).map(|&p| to_fuse_ino(p)).unwrap_or(ino);
+        let mut entries = vec![(ino, FileType::Directory, ".".to_string()), (parent_ino, FileType::Directory, "..".to_string())];
+        if let Some(children) = self.ns.children.get(&inum) {
+            for &(child_inum, ref name) in children {
+                if let Some(attr) = self.ns.attr(child_inum) {
+                    entries.push((to_fuse_ino(child_inum), attr.kind, name.clone()));
+                }
+            }
+        }
+
+        for (i, (child_ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(child_ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        if name != XATTR_NAME {
+            reply.error(ENOENT);
+            return;
+        }
+        match self.ns.xattr_value(to_bch_inum(ino)) {
+            None => reply.error(ENOENT),
+            Some(data) if size == 0 => reply.size(data.len() as u32),
+            Some(data) if data.len() > size as usize => reply.error(ERANGE),
+            Some(data) => reply.data(&data),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let names = if self.ns.xattr_value(to_bch_inum(ino)).is_some() {
+            let mut buf = XATTR_NAME.as_bytes().to_vec();
+            buf.push(0);
+            buf
+        } else {
+            Vec::new()
+        };
+
+        if size == 0 {
+            reply.size(names.len() as u32);
+        } else if names.len() > size as usize {
+            reply.error(ERANGE);
+        } else {
+            reply.data(&names);
+        }
+    }
+}
+
+/// Mount a read-only FUSE view of a filesystem's reconstructed directory
+/// tree, opened directly from its devices (no kernel mount required).
+#[derive(Parser, Debug)]
+pub struct Cli {
+    /// Devices, or a mounted directory to resolve devices from
+    #[arg(required = true)]
+    devices: Vec<PathBuf>,
+
+    /// Where to mount the read-only view
+    mountpoint: PathBuf,
+
+    /// Quiet mode (no progress output)
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Verbose mode
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+pub fn browse(argv: Vec<String>) -> Result<()> {
+    let opt = Cli::parse_from(argv);
+    logging::setup(opt.verbose, false);
+
+    let mut devices = Vec::new();
+    for path in &opt.devices {
+        devices.extend(resolve_devices(path)?);
+    }
+
+    let mut fs_opts = bcachefs::bch_opts::default();
+    opt_set!(fs_opts, noexcl, 1);
+    opt_set!(fs_opts, nochanges, 1);
+    opt_set!(fs_opts, read_only, 1);
+    opt_set!(fs_opts, norecovery, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+    opt_set!(fs_opts, errors, bcachefs::bch_error_actions::BCH_ON_ERROR_continue as u8);
+
+    let fs = Fs::open(&devices, fs_opts)?;
+    let ns = scan_namespace(&fs)?;
+    if !opt.quiet {
+        eprintln!("indexed {} inodes", ns.inodes.len());
+    }
+
+    let browse_fs = BrowseFs { ns };
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("bcachefs-browse".to_string()),
+        MountOption::AutoUnmount,
+    ];
+    fuser::mount2(browse_fs, &opt.mountpoint, &options)?;
+    Ok(())
+}
@@ -0,0 +1,475 @@
+//! Offline namespace snapshot for a bcachefs filesystem: `catalog create`
+//! walks `BTREE_ID_inodes` and `BTREE_ID_dirents` once each and writes every
+//! inode's parent, name, mode, size and option set to a single flat file;
+//! `catalog lookup` then answers path<->inum and directory-listing queries
+//! straight from that file, with no further btree scans. This generalizes
+//! the private parent/dirent-map machinery `inode_opts_device` rebuilds on
+//! every `--resolve-paths` run into a reusable, cacheable index, which
+//! matters most for filesystems too degraded or too large to rescan
+//! on every query.
+//!
+//! On-disk format (all integers little-endian):
+//!   magic, version
+//!   nr_records (u64), name_arena_len (u64)
+//!   records: nr_records entries, sorted by (parent, name), each:
+//!     inum(u64) parent(u64) mode(u32) size(u64) opts[NUM_OPTS](u64) name_off(u32) name_len(u16)
+//!   inum_index: nr_records entries sorted by inum, each: inum(u64) record_idx(u32)
+//!   name arena: name_arena_len bytes, referenced by record name_off/name_len
+//!
+//! Records are sorted by (parent, name) so listing a directory's children is
+//! a contiguous range scan; `inum_index` is sorted separately by inum so a
+//! single lookup is a binary search rather than a linear scan of `records`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIter, BtreeIterFlags, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::{Parser, Subcommand};
+
+use crate::commands::inode_opts_device::{get_dirent_name, resolve_devices};
+use crate::logging;
+
+const MAGIC: u32 = 0x62634354; // "bcCT"
+const VERSION: u32 = 1;
+
+/// The root directory's inode number is fixed for the default subvolume.
+const ROOT_INO: u64 = 4096;
+
+const OPT_NAMES: [&str; 9] = [
+    "data_checksum",
+    "compression",
+    "background_compression",
+    "data_replicas",
+    "promote_target",
+    "foreground_target",
+    "background_target",
+    "erasure_code",
+    "project",
+];
+
+#[derive(Clone)]
+struct Record {
+    inum: u64,
+    parent: u64,
+    mode: u32,
+    size: u64,
+    opts: [u64; OPT_NAMES.len()],
+    name_off: u32,
+    name_len: u16,
+}
+
+const RECORD_LEN: usize = 8 + 8 + 4 + 8 + 8 * OPT_NAMES.len() + 4 + 2;
+const INDEX_ENTRY_LEN: usize = 8 + 4;
+
+/// The catalog, loaded fully into memory: sorted records (for range scans by
+/// parent) plus the secondary inum index (for binary search by inum).
+struct Catalog {
+    records: Vec<Record>,
+    /// Sorted by inum: (inum, index into `records`).
+    inum_index: Vec<(u64, u32)>,
+    arena: Vec<u8>,
+}
+
+impl Catalog {
+    fn name(&self, r: &Record) -> &str {
+        let start = r.name_off as usize;
+        let end = start + r.name_len as usize;
+        std::str::from_utf8(&self.arena[start..end]).unwrap_or("?")
+    }
+
+    fn by_inum(&self, inum: u64) -> Option<&Record> {
+        let idx = self.inum_index.binary_search_by_key(&inum, |&(i, _)| i).ok()?;
+        Some(&self.records[self.inum_index[idx].1 as usize])
+    }
+
+    /// Children of `parent`, in sort order (a contiguous range of `records`).
+    fn children(&self, parent: u64) -> &[Record] {
+        let start = self.records.partition_point(|r| r.parent < parent);
+        let end = self.records[start..].partition_point(|r| r.parent == parent) + start;
+        &self.records[start..end]
+    }
+
+    fn full_path(&self, inum: u64) -> String {
+        if inum == ROOT_INO {
+            return "/".to_string();
+        }
+
+        let mut parts = Vec::new();
+        let mut current = inum;
+        let mut seen = HashSet::new();
+
+        while current != ROOT_INO && !seen.contains(&current) {
+            seen.insert(current);
+            match self.by_inum(current) {
+                Some(r) => {
+                    parts.push(self.name(r).to_string());
+                    current = r.parent;
+                }
+                None => {
+                    parts.push("?".to_string());
+                    break;
+                }
+            }
+        }
+
+        parts.reverse();
+        format!("/{}", parts.join("/"))
+    }
+
+    /// Resolve a `/`-separated path to an inum by walking down from the root.
+    fn resolve_path(&self, path: &str) -> Option<u64> {
+        let mut current = ROOT_INO;
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            let child = self.children(current).iter().find(|r| self.name(r) == component)?;
+            current = child.inum;
+        }
+
+        Some(current)
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self> {
+        let off = std::cell::Cell::new(0usize);
+
+        let rd_u16 = || -> Result<u16> {
+            let v = u16::from_le_bytes(buf.get(off.get()..off.get() + 2).context("truncated catalog")?.try_into().unwrap());
+            off.set(off.get() + 2);
+            Ok(v)
+        };
+        let rd_u32 = || -> Result<u32> {
+            let v = u32::from_le_bytes(buf.get(off.get()..off.get() + 4).context("truncated catalog")?.try_into().unwrap());
+            off.set(off.get() + 4);
+            Ok(v)
+        };
+        let rd_u64 = || -> Result<u64> {
+            let v = u64::from_le_bytes(buf.get(off.get()..off.get() + 8).context("truncated catalog")?.try_into().unwrap());
+            off.set(off.get() + 8);
+            Ok(v)
+        };
+
+        if rd_u32()? != MAGIC {
+            bail!("not a catalog file (bad magic)");
+        }
+        let version = rd_u32()?;
+        if version != VERSION {
+            bail!("unsupported catalog version {version}");
+        }
+
+        let nr_records = rd_u64()? as usize;
+        let name_arena_len = rd_u64()? as usize;
+
+        let mut records = Vec::with_capacity(nr_records);
+        for _ in 0..nr_records {
+            let inum = rd_u64()?;
+            let parent = rd_u64()?;
+            let mode = rd_u32()?;
+            let size = rd_u64()?;
+            let mut opts = [0u64; OPT_NAMES.len()];
+            for o in opts.iter_mut() {
+                *o = rd_u64()?;
+            }
+            let name_off = rd_u32()?;
+            let name_len = rd_u16()?;
+            records.push(Record { inum, parent, mode, size, opts, name_off, name_len });
+        }
+
+        let mut inum_index = Vec::with_capacity(nr_records);
+        for _ in 0..nr_records {
+            let inum = rd_u64()?;
+            let idx = rd_u32()?;
+            inum_index.push((inum, idx));
+        }
+
+        let arena = buf.get(off.get()..off.get() + name_arena_len).context("truncated catalog")?.to_vec();
+
+        Ok(Self { records, inum_index, arena })
+    }
+
+    fn load(path: &PathBuf) -> Result<Self> {
+        let buf = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::decode(&buf)
+    }
+}
+
+/// One inode visited while scanning `BTREE_ID_inodes`: everything a
+/// `Record` needs except its resolved name, which requires the later
+/// `BTREE_ID_dirents` pass.
+struct RawInode {
+    inum: u64,
+    parent: u64,
+    parent_offset: u64,
+    mode: u32,
+    size: u64,
+    opts: [u64; OPT_NAMES.len()],
+}
+
+fn scan_inodes(fs: &Fs) -> Result<Vec<RawInode>> {
+    let trans = BtreeTrans::new(fs);
+    let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_inodes, bch_bindgen::POS_MIN, flags);
+
+    let mut out = Vec::new();
+    let mut last_inum: Option<u64> = None;
+    let mut count = 0u64;
+
+    while let Some(k) = iter.peek_and_restart()? {
+        count += 1;
+        if count % 100_000 == 0 {
+            eprint!("\rscanned {} inodes...", count);
+            std::io::Write::flush(&mut std::io::stderr()).ok();
+        }
+
+        let inum = k.k.p.inode;
+        if last_inum == Some(inum) {
+            iter.advance();
+            continue;
+        }
+        last_inum = Some(inum);
+
+        if k.k.type_ != bcachefs::bch_bkey_type::KEY_TYPE_inode_v3 as u8 {
+            iter.advance();
+            continue;
+        }
+
+        let mut unpacked: c::bch_inode_unpacked = unsafe { std::mem::zeroed() };
+        let bkey_s_c = c::bkey_s_c { k: k.k, v: k.v };
+        if unsafe { c::bch2_inode_unpack(bkey_s_c, &mut unpacked) } != 0 {
+            iter.advance();
+            continue;
+        }
+
+        let opts = [
+            unpacked.bi_data_checksum as u64,
+            unpacked.bi_compression as u64,
+            unpacked.bi_background_compression as u64,
+            unpacked.bi_data_replicas as u64,
+            unpacked.bi_promote_target as u64,
+            unpacked.bi_foreground_target as u64,
+            unpacked.bi_background_target as u64,
+            unpacked.bi_erasure_code as u64,
+            unpacked.bi_project as u64,
+        ];
+
+        out.push(RawInode {
+            inum,
+            parent: unpacked.bi_dir,
+            parent_offset: unpacked.bi_dir_offset,
+            mode: unpacked.bi_mode as u32,
+            size: unpacked.bi_size,
+            opts,
+        });
+
+        iter.advance();
+    }
+
+    eprintln!("\rscanned {} inodes, {} inode_v3 records", count, out.len());
+    Ok(out)
+}
+
+fn scan_dirent_names(fs: &Fs, needed: &HashSet<(u64, u64)>) -> Result<HashMap<(u64, u64), String>> {
+    let trans = BtreeTrans::new(fs);
+    let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_dirents, bch_bindgen::POS_MIN, flags);
+
+    let mut names = HashMap::new();
+
+    while let Some(k) = iter.peek_and_restart()? {
+        let key = (k.k.p.inode, k.k.p.offset);
+        if needed.contains(&key) && k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_dirent as u8 {
+            if let Some(name) = get_dirent_name(k.v, k.k) {
+                names.insert(key, name);
+            }
+        }
+        iter.advance();
+    }
+
+    Ok(names)
+}
+
+fn build_catalog(fs: &Fs) -> Result<(Vec<Record>, Vec<u8>)> {
+    let raw = scan_inodes(fs)?;
+
+    let needed: HashSet<(u64, u64)> =
+        raw.iter().filter(|r| r.parent != 0).map(|r| (r.parent, r.parent_offset)).collect();
+    let names = scan_dirent_names(fs, &needed)?;
+
+    let mut arena = Vec::new();
+    let mut records: Vec<Record> = raw
+        .into_iter()
+        .map(|r| {
+            let name = if r.inum == ROOT_INO {
+                String::new()
+            } else {
+                names.get(&(r.parent, r.parent_offset)).cloned().unwrap_or_else(|| "?".to_string())
+            };
+            let name_off = arena.len() as u32;
+            arena.extend_from_slice(name.as_bytes());
+            Record {
+                inum: r.inum,
+                parent: r.parent,
+                mode: r.mode,
+                size: r.size,
+                opts: r.opts,
+                name_off,
+                name_len: name.len() as u16,
+            }
+        })
+        .collect();
+
+    records.sort_by(|a, b| {
+        let (na, nb) = (a.name_off as usize, b.name_off as usize);
+        let name_a = &arena[na..na + a.name_len as usize];
+        let name_b = &arena[nb..nb + b.name_len as usize];
+        (a.parent, name_a).cmp(&(b.parent, name_b))
+    });
+    Ok((records, arena))
+}
+
+fn write_catalog(out: &PathBuf, records: &[Record], arena: &[u8]) -> Result<()> {
+    let mut buf = Vec::with_capacity(16 + records.len() * (RECORD_LEN + INDEX_ENTRY_LEN) + arena.len());
+    buf.extend_from_slice(&MAGIC.to_le_bytes());
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.extend_from_slice(&(records.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&(arena.len() as u64).to_le_bytes());
+
+    for r in records {
+        buf.extend_from_slice(&r.inum.to_le_bytes());
+        buf.extend_from_slice(&r.parent.to_le_bytes());
+        buf.extend_from_slice(&r.mode.to_le_bytes());
+        buf.extend_from_slice(&r.size.to_le_bytes());
+        for v in r.opts {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.extend_from_slice(&r.name_off.to_le_bytes());
+        buf.extend_from_slice(&r.name_len.to_le_bytes());
+    }
+
+    let mut inum_index: Vec<(u64, u32)> = records.iter().enumerate().map(|(i, r)| (r.inum, i as u32)).collect();
+    inum_index.sort_by_key(|&(inum, _)| inum);
+    for (inum, idx) in inum_index {
+        buf.extend_from_slice(&inum.to_le_bytes());
+        buf.extend_from_slice(&idx.to_le_bytes());
+    }
+
+    buf.extend_from_slice(arena);
+
+    // Write to a temp file first so a run killed mid-write can't leave a
+    // catalog that fails to decode on the next lookup.
+    let tmp_path = out.with_extension("tmp");
+    fs::write(&tmp_path, &buf)?;
+    fs::rename(&tmp_path, out)?;
+    Ok(())
+}
+
+fn print_record(catalog: &Catalog, r: &Record) {
+    let opts_str: Vec<String> = OPT_NAMES
+        .iter()
+        .zip(r.opts)
+        .filter(|(_, v)| *v != 0)
+        .map(|(name, v)| format!("{}={}", name, v))
+        .collect();
+
+    println!("inum\t{}", r.inum);
+    println!("path\t{}", catalog.full_path(r.inum));
+    println!("mode\t{:o}", r.mode);
+    println!("size\t{}", r.size);
+    println!("opts\t{}", opts_str.join(" "));
+
+    let is_dir = (r.mode & 0o170000) == 0o040000;
+    if is_dir {
+        for child in catalog.children(r.inum) {
+            println!("child\t{}\t{}", child.inum, catalog.name(child));
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct Cli {
+    /// Verbose mode
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Force color on/off
+    #[arg(short, long, global = true, action = clap::ArgAction::Set, default_value_t = std::io::stdout().is_terminal())]
+    colorize: bool,
+
+    #[command(subcommand)]
+    subcommands: Subcommands,
+}
+
+/// Snapshot and query a filesystem's inode/dirent namespace offline
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+    /// Scan devices and write a catalog file
+    Create {
+        /// Devices, or a mounted directory to resolve devices from
+        #[arg(required = true)]
+        devices: Vec<PathBuf>,
+
+        /// Output catalog file
+        out: PathBuf,
+    },
+
+    /// Look up an inum or path in a catalog file
+    Lookup {
+        /// Catalog file written by `catalog create`
+        catalog: PathBuf,
+
+        /// Inum or `/`-rooted path to resolve
+        query: String,
+    },
+}
+
+fn cmd_create(devices: &[PathBuf], out: &PathBuf) -> Result<()> {
+    let mut resolved = Vec::new();
+    for path in devices {
+        resolved.extend(resolve_devices(path)?);
+    }
+
+    let mut fs_opts = bcachefs::bch_opts::default();
+    opt_set!(fs_opts, noexcl, 1);
+    opt_set!(fs_opts, nochanges, 1);
+    opt_set!(fs_opts, read_only, 1);
+    opt_set!(fs_opts, norecovery, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+    opt_set!(fs_opts, errors, bcachefs::bch_error_actions::BCH_ON_ERROR_continue as u8);
+
+    let fs = Fs::open(&resolved, fs_opts)?;
+    let (records, arena) = build_catalog(&fs)?;
+    write_catalog(out, &records, &arena)?;
+
+    eprintln!("wrote {} records to {}", records.len(), out.display());
+    Ok(())
+}
+
+fn cmd_lookup(catalog_path: &PathBuf, query: &str) -> Result<()> {
+    let catalog = Catalog::load(catalog_path)?;
+
+    let inum = if let Ok(inum) = query.parse::<u64>() {
+        inum
+    } else {
+        catalog.resolve_path(query).with_context(|| format!("path not found: {query}"))?
+    };
+
+    let record = catalog.by_inum(inum).with_context(|| format!("inum not found: {inum}"))?;
+    print_record(&catalog, record);
+    Ok(())
+}
+
+pub fn catalog(argv: Vec<String>) -> Result<()> {
+    let cli = Cli::parse_from(argv);
+    logging::setup(cli.verbose, cli.colorize);
+
+    match cli.subcommands {
+        Subcommands::Create { devices, out } => cmd_create(&devices, &out),
+        Subcommands::Lookup { catalog, query } => cmd_lookup(&catalog, &query),
+    }
+}
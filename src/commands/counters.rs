@@ -52,6 +52,8 @@ pub fn cmd_reset_counters(argv: Vec<String>) -> Result<()> {
     let sbs = crate::device_scan::scan_sbs(&cli.device, &scan_opts)?;
     let devs: Vec<PathBuf> = sbs.into_iter().map(|(p, _)| p).collect();
 
+    crate::mount::ensure_unmounted(&devs)?;
+
     // open fs in nostart mode
     let mut fs_opts = c::bch_opts::default();
     opt_set!(fs_opts, nostart, 1);
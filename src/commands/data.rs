@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+
+use anyhow::{bail, Context, Result};
+use bch_bindgen::bcachefs::btree_id;
+use bch_bindgen::c::bch_data_ops;
+use clap::{Parser, Subcommand};
+
+use crate::util::fmt_bytes_human;
+use crate::wrappers::handle::BcachefsHandle;
+
+#[derive(Parser, Debug)]
+pub struct Cli {
+    #[command(subcommand)]
+    subcommands: Subcommands,
+}
+
+/// Whole-filesystem data jobs driven by `BCH_IOCTL_DATA`.
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+    /// Bring data up to its target replication
+    Rereplicate {
+        /// Filesystem path or device
+        filesystem: String,
+    },
+
+    /// Move data off devices being evacuated or removed
+    Migrate {
+        /// Filesystem path or device
+        filesystem: String,
+    },
+
+    /// Rewrite btree nodes written by older kernel versions
+    #[command(name = "rewrite_old_nodes")]
+    RewriteOldNodes {
+        /// Filesystem path or device
+        filesystem: String,
+    },
+
+    /// Drop replicas in excess of a key's target replication
+    #[command(name = "drop_extra_replicas")]
+    DropExtraReplicas {
+        /// Filesystem path or device
+        filesystem: String,
+    },
+}
+
+pub fn data(argv: Vec<String>) -> Result<()> {
+    let cli = Cli::parse_from(argv);
+
+    let (op, filesystem) = match cli.subcommands {
+        Subcommands::Rereplicate { filesystem } => (bch_data_ops::BCH_DATA_OP_rereplicate, filesystem),
+        Subcommands::Migrate { filesystem } => (bch_data_ops::BCH_DATA_OP_migrate, filesystem),
+        Subcommands::RewriteOldNodes { filesystem } => (bch_data_ops::BCH_DATA_OP_rewrite_old_nodes, filesystem),
+        Subcommands::DropExtraReplicas { filesystem } => (bch_data_ops::BCH_DATA_OP_drop_extra_replicas, filesystem),
+    };
+
+    let handle = BcachefsHandle::open(&filesystem)
+        .with_context(|| format!("opening filesystem '{}'", filesystem))?;
+
+    let mut job = handle.start_data_job(
+        op,
+        btree_id::BTREE_ID_NR,
+        bch_bindgen::POS_MIN,
+        btree_id::BTREE_ID_NR,
+        bch_bindgen::POS_MAX,
+    ).context("starting data job")?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut printed = false;
+
+    while let Some(p) = job.poll_progress() {
+        let pct = if p.sectors_total > 0 {
+            p.sectors_done * 100 / p.sectors_total
+        } else {
+            0
+        };
+
+        if printed {
+            write!(out, "\r")?;
+        }
+        write!(out, "{:>3}%  {:>10} / {:<10}",
+            pct, fmt_bytes_human(p.sectors_done << 9), fmt_bytes_human(p.sectors_total << 9))?;
+        out.flush()?;
+        printed = true;
+    }
+
+    if printed {
+        writeln!(out)?;
+    }
+
+    match job.exit_code() {
+        Some(code) if code != 0 => bail!("data job failed with exit code {}", code),
+        _ => Ok(()),
+    }
+}
@@ -5,6 +5,7 @@ use std::thread;
 use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
+use bch_bindgen::bcachefs::btree_id;
 use bch_bindgen::c::{
     bch_data_type::*,
     bch_member_state::*,
@@ -52,7 +53,7 @@ fn resolve_dev(handle: &BcachefsHandle, dev_str: &str) -> Result<u32> {
 
 /// Open a device by path or numeric index, with optional filesystem path.
 /// When device is a numeric index, fs_path is required.
-fn open_dev_by_path_or_index(device: &str, fs_path: Option<&str>) -> Result<(BcachefsHandle, u32)> {
+pub(crate) fn open_dev_by_path_or_index(device: &str, fs_path: Option<&str>) -> Result<(BcachefsHandle, u32)> {
     if let Some(fs_path) = fs_path {
         let handle = BcachefsHandle::open(fs_path)
             .with_context(|| format!("opening filesystem '{}'", fs_path))?;
@@ -239,7 +240,7 @@ pub fn cmd_device_resize(argv: Vec<String>) -> Result<bool> {
     let nbuckets = size_sectors / usage.bucket_size as u64;
 
     if nbuckets < usage.nr_buckets {
-        return Err(anyhow!("Shrinking not supported yet"));
+        shrink_device(&handle, dev_idx, &cli.device, &usage, nbuckets)?;
     }
 
     println!("resizing {} to {} buckets", cli.device, nbuckets);
@@ -248,6 +249,68 @@ pub fn cmd_device_resize(argv: Vec<String>) -> Result<bool> {
     Ok(true)
 }
 
+/// Move data off the device before a shrinking resize, so `disk_resize`
+/// isn't asked to drop buckets still holding live data. Runs a
+/// `BCH_IOCTL_DATA` migrate job for this device across all btrees; the job
+/// is not limited to the tail bucket range `[target_nbuckets,
+/// usage.nr_buckets)`, so it migrates all of the device's data, not only
+/// what sits in the tail. After the job ends, the device's live data
+/// footprint is checked once and the shrink fails if it does not fit in
+/// the smaller size.
+fn shrink_device(
+    handle: &BcachefsHandle,
+    dev_idx: u32,
+    device: &str,
+    usage: &crate::wrappers::handle::DevUsage,
+    target_nbuckets: u64,
+) -> Result<()> {
+    let target_sectors = target_nbuckets * usage.bucket_size as u64;
+
+    println!("{} is shrinking; migrating data off the device first", device);
+
+    let mut job = handle.start_migrate_dev_job(
+        dev_idx,
+        btree_id::BTREE_ID_NR,
+        bch_bindgen::POS_MIN,
+        btree_id::BTREE_ID_NR,
+        bch_bindgen::POS_MAX,
+    ).context("starting migrate job")?;
+
+    while job.poll_progress().is_some() {
+        let usage = handle.dev_usage(dev_idx).context("querying device usage")?;
+        let data_sectors = live_data_sectors(&usage);
+        print!("\x1b[2K\r{}", fmt_bytes_human(data_sectors << 9));
+        io::stdout().flush().ok();
+    }
+    println!();
+
+    if let Some(code) = job.exit_code() {
+        if code != 0 {
+            return Err(anyhow!("migrate job failed with exit code {}", code));
+        }
+    }
+
+    let usage = handle.dev_usage(dev_idx).context("querying device usage")?;
+    let data_sectors = live_data_sectors(&usage);
+    if data_sectors > target_sectors {
+        return Err(anyhow!(
+            "cannot shrink {}: {} of live data remains, which doesn't fit in the requested {}",
+            device,
+            fmt_bytes_human(data_sectors << 9),
+            fmt_bytes_human(target_sectors << 9),
+        ));
+    }
+
+    Ok(())
+}
+
+fn live_data_sectors(usage: &crate::wrappers::handle::DevUsage) -> u64 {
+    usage.data_types.iter().enumerate()
+        .filter(|(i, _)| !data_type_is_empty(*i as u32) && !data_type_is_hidden(*i as u32))
+        .map(|(_, dt)| dt.sectors)
+        .sum()
+}
+
 #[derive(Parser, Debug)]
 #[command(about = "Resize the journal on a device")]
 pub struct ResizeJournalCli {
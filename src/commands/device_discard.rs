@@ -0,0 +1,125 @@
+//! `bcachefs device discard`: issue `BLKDISCARD` over a device's free
+//! buckets, so thin-provisioned LVM or loopback-file backends can actually
+//! reclaim the space bcachefs itself considers unused.
+
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIter, BtreeIterFlags, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::Parser;
+
+use crate::util::fmt_bytes_human;
+use crate::wrappers::handle::BcachefsHandle;
+
+// BLKDISCARD = _IO(0x12, 119)
+const BLKDISCARD: libc::c_ulong = 0x1277;
+
+fn data_type_is_empty(t: u8) -> bool {
+    t == bcachefs::bch_data_type::BCH_DATA_free as u8
+        || t == bcachefs::bch_data_type::BCH_DATA_need_gc_gens as u8
+        || t == bcachefs::bch_data_type::BCH_DATA_need_discard as u8
+}
+
+/// Scan `BTREE_ID_alloc` for `dev_idx`, returning the byte ranges (start,
+/// length) of buckets classified by [`data_type_is_empty`], coalescing
+/// adjacent free buckets into a single run to minimize the number of
+/// `BLKDISCARD` ioctls issued.
+fn free_sector_ranges(fs: &Fs, dev_idx: u32, bucket_size: u64) -> Result<Vec<(u64, u64)>> {
+    let trans = BtreeTrans::new(fs);
+    let pos = bch_bindgen::spos(dev_idx as u64, 0, 0);
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_alloc, pos, BtreeIterFlags::empty());
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    while let Some(k) = iter.peek_and_restart()? {
+        if k.k.p.inode != dev_idx as u64 {
+            break;
+        }
+        if k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_alloc_v4 as u8 {
+            let alloc = unsafe { &*(k.v as *const c::bch_val as *const c::bch_alloc_v4) };
+            if data_type_is_empty(alloc.data_type) {
+                let start = k.k.p.offset * bucket_size * 512;
+                let len = bucket_size * 512;
+                match ranges.last_mut() {
+                    Some((s, l)) if *s + *l == start => *l += len,
+                    _ => ranges.push((start, len)),
+                }
+            }
+        }
+        iter.advance();
+    }
+    Ok(ranges)
+}
+
+#[derive(Parser, Debug)]
+pub struct Cli {
+    /// Device to discard free space on
+    device: PathBuf,
+
+    /// Report how much space would be discarded without issuing BLKDISCARD
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub fn cmd_device_discard(argv: Vec<String>) -> Result<()> {
+    let cli = Cli::parse_from(argv);
+
+    let handle = BcachefsHandle::open(&cli.device)
+        .with_context(|| format!("opening '{}'", cli.device.display()))?;
+    let dev_idx = handle.dev_idx();
+    anyhow::ensure!(dev_idx >= 0, "'{}' does not appear to be a block device member", cli.device.display());
+    let dev_idx = dev_idx as u32;
+
+    let usage = handle.dev_usage(dev_idx).context("querying device usage")?;
+
+    crate::mount::ensure_unmounted(&[cli.device.clone()])?;
+
+    let mut fs_opts = c::bch_opts::default();
+    opt_set!(fs_opts, nostart, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+    let fs = Fs::open(&[cli.device.clone()], fs_opts)
+        .map_err(|e| anyhow::anyhow!("Error opening filesystem: {}", e))?;
+
+    let ranges = free_sector_ranges(&fs, dev_idx, usage.bucket_size as u64)?;
+    let total: u64 = ranges.iter().map(|(_, len)| len).sum();
+
+    if cli.dry_run {
+        println!(
+            "would discard {} across {} extents on {}",
+            fmt_bytes_human(total),
+            ranges.len(),
+            cli.device.display(),
+        );
+        return Ok(());
+    }
+
+    let dev = std::fs::File::open(&cli.device)
+        .with_context(|| format!("opening '{}' for discard", cli.device.display()))?;
+
+    for (start, len) in &ranges {
+        let range: [u64; 2] = [*start, *len];
+        let ret = unsafe { libc::ioctl(dev.as_raw_fd(), BLKDISCARD, range.as_ptr()) };
+        if ret < 0 {
+            return Err(anyhow!(
+                "BLKDISCARD failed on {} at offset {}, length {}: {}",
+                cli.device.display(),
+                start,
+                len,
+                std::io::Error::last_os_error(),
+            ));
+        }
+    }
+
+    println!(
+        "discarded {} across {} extents on {}",
+        fmt_bytes_human(total),
+        ranges.len(),
+        cli.device.display(),
+    );
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! `bcachefs device image dump`/`restore`: a sparse, zstd-compressed backup
+//! of a single member device, in the spirit of the "only serialize used
+//! groups, compress each group" strategy disc-image tools (WIA/RVZ) use —
+//! buckets whose `data_type_is_empty` are skipped entirely, so backing up a
+//! mostly-empty multi-terabyte device costs a fraction of its raw size.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIter, BtreeIterFlags, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::{Parser, Subcommand};
+
+use crate::wrappers::accounting::{data_type_is_empty, data_type_is_hidden};
+use crate::wrappers::handle::BcachefsHandle;
+
+const MAGIC: &[u8; 8] = b"BCHIMAGE";
+const FORMAT_VERSION: u8 = 1;
+const SECTOR_BYTES: u32 = 512;
+
+/// Scan `BTREE_ID_alloc` for `dev_idx`'s buckets, returning each bucket
+/// index that isn't free/need-gc/need-discard/superblock/journal — the set
+/// of buckets that actually need to be backed up.
+fn present_buckets(fs: &Fs, dev_idx: u32) -> Result<Vec<u64>> {
+    let trans = BtreeTrans::new(fs);
+    let pos = bch_bindgen::spos(dev_idx as u64, 0, 0);
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_alloc, pos, BtreeIterFlags::empty());
+
+    let mut out = Vec::new();
+    while let Some(k) = iter.peek_and_restart()? {
+        if k.k.p.inode != dev_idx as u64 {
+            break;
+        }
+        if k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_alloc_v4 as u8 {
+            let alloc = unsafe { &*(k.v as *const c::bch_val as *const c::bch_alloc_v4) };
+            let data_type: bch_bindgen::bcachefs::bch_data_type =
+                unsafe { std::mem::transmute(alloc.data_type as u32) };
+            if !data_type_is_empty(data_type) && !data_type_is_hidden(data_type) {
+                out.push(k.k.p.offset);
+            }
+        }
+        iter.advance();
+    }
+    out.sort_unstable();
+    Ok(out)
+}
+
+#[derive(Parser, Debug)]
+pub struct DumpCli {
+    /// Device to back up
+    device: PathBuf,
+
+    /// Output image file
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+pub fn dump(cli: &DumpCli) -> Result<()> {
+    let handle = BcachefsHandle::open(&cli.device)
+        .with_context(|| format!("opening '{}'", cli.device.display()))?;
+    let dev_idx = handle.dev_idx();
+    anyhow::ensure!(dev_idx >= 0, "'{}' does not appear to be a block device member", cli.device.display());
+    let dev_idx = dev_idx as u32;
+
+    let usage = handle.dev_usage(dev_idx).context("querying device usage")?;
+    let bucket_size = usage.bucket_size; // sectors
+
+    crate::mount::ensure_unmounted(&[cli.device.clone()])?;
+
+    let mut fs_opts = c::bch_opts::default();
+    opt_set!(fs_opts, nostart, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+    let fs = Fs::open(&[cli.device.clone()], fs_opts)
+        .map_err(|e| anyhow::anyhow!("Error opening filesystem: {}", e))?;
+
+    let buckets = present_buckets(&fs, dev_idx)?;
+
+    let mut dev = File::open(&cli.device)
+        .with_context(|| format!("opening '{}' for reading", cli.device.display()))?;
+
+    let out_file = File::create(&cli.output)
+        .with_context(|| format!("creating '{}'", cli.output.display()))?;
+    let mut out = BufWriter::new(out_file);
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[FORMAT_VERSION])?;
+    out.write_all(&handle.uuid())?;
+    out.write_all(&dev_idx.to_le_bytes())?;
+    out.write_all(&bucket_size.to_le_bytes())?;
+    out.write_all(&SECTOR_BYTES.to_le_bytes())?;
+    out.write_all(&usage.nr_buckets.to_le_bytes())?;
+    out.write_all(&(buckets.len() as u64).to_le_bytes())?;
+
+    let mut buf = vec![0u8; bucket_size as usize * SECTOR_BYTES as usize];
+    for bucket in &buckets {
+        dev.seek(SeekFrom::Start(bucket * bucket_size as u64 * SECTOR_BYTES as u64))?;
+        dev.read_exact(&mut buf)?;
+
+        let compressed = zstd::stream::encode_all(&buf[..], 0).context("compressing bucket")?;
+        let crc = crc32fast::hash(&compressed);
+
+        out.write_all(&bucket.to_le_bytes())?;
+        out.write_all(&(compressed.len() as u64).to_le_bytes())?;
+        out.write_all(&crc.to_le_bytes())?;
+        out.write_all(&compressed)?;
+    }
+    out.flush()?;
+
+    eprintln!(
+        "wrote {} of {} buckets ({} each) to {}",
+        buckets.len(),
+        usage.nr_buckets,
+        crate::util::fmt_bytes_human((bucket_size as u64) << 9),
+        cli.output.display(),
+    );
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+pub struct RestoreCli {
+    /// Image file produced by `device image dump`
+    input: PathBuf,
+
+    /// Device to restore onto (must be at least as large as the image's
+    /// `nr_buckets * bucket_size`)
+    device: PathBuf,
+}
+
+pub fn restore(cli: &RestoreCli) -> Result<()> {
+    let in_file = File::open(&cli.input).with_context(|| format!("opening '{}'", cli.input.display()))?;
+    let mut input = BufReader::new(in_file);
+
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).context("reading image header")?;
+    anyhow::ensure!(&magic == MAGIC, "'{}' is not a device-image archive", cli.input.display());
+    let mut version = [0u8; 1];
+    input.read_exact(&mut version)?;
+    anyhow::ensure!(version[0] == FORMAT_VERSION, "unsupported image format version {}", version[0]);
+
+    // fs uuid, dev_idx: not needed to restore, but kept in the header so the
+    // image is self-describing
+    let mut skip = [0u8; 16 + 4];
+    input.read_exact(&mut skip)?;
+    let mut bucket_size = [0u8; 4];
+    input.read_exact(&mut bucket_size)?;
+    let bucket_size = u32::from_le_bytes(bucket_size);
+    let mut block_size = [0u8; 4]; // block size is always 512 for now
+    input.read_exact(&mut block_size)?;
+    let mut nr_buckets = [0u8; 8];
+    input.read_exact(&mut nr_buckets)?;
+    let nr_buckets = u64::from_le_bytes(nr_buckets);
+    let mut bucket_count = [0u8; 8];
+    input.read_exact(&mut bucket_count)?;
+    let bucket_count = u64::from_le_bytes(bucket_count);
+
+    crate::mount::ensure_unmounted(&[cli.device.clone()])?;
+
+    let mut dev = OpenOptions::new().write(true).open(&cli.device)
+        .with_context(|| format!("opening '{}' for writing", cli.device.display()))?;
+
+    let min_bytes = nr_buckets * bucket_size as u64 * SECTOR_BYTES as u64;
+    if let Ok(len) = dev.seek(SeekFrom::End(0)) {
+        anyhow::ensure!(len >= min_bytes, "'{}' ({} bytes) is too small for this image ({} bytes)", cli.device.display(), len, min_bytes);
+    }
+
+    let mut restored = 0u64;
+    for _ in 0..bucket_count {
+        let mut bucket = [0u8; 8];
+        input.read_exact(&mut bucket)?;
+        let bucket = u64::from_le_bytes(bucket);
+        let mut compressed_len = [0u8; 8];
+        input.read_exact(&mut compressed_len)?;
+        let compressed_len = u64::from_le_bytes(compressed_len) as usize;
+        let mut crc = [0u8; 4];
+        input.read_exact(&mut crc)?;
+        let expect_crc = u32::from_le_bytes(crc);
+
+        let mut compressed = vec![0u8; compressed_len];
+        input.read_exact(&mut compressed)?;
+
+        let got_crc = crc32fast::hash(&compressed);
+        anyhow::ensure!(got_crc == expect_crc, "checksum mismatch for bucket {} in image", bucket);
+
+        let data = zstd::stream::decode_all(&compressed[..]).context("decompressing bucket")?;
+
+        dev.seek(SeekFrom::Start(bucket * bucket_size as u64 * SECTOR_BYTES as u64))?;
+        dev.write_all(&data)?;
+        restored += 1;
+    }
+    dev.flush()?;
+
+    eprintln!("restored {} buckets onto {} (absent buckets left untouched)", restored, cli.device.display());
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "image")]
+pub struct Cli {
+    #[command(subcommand)]
+    subcommands: Subcommands,
+}
+
+/// Sparse, compressed device backup/restore
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+    /// Back up a device's used buckets to a compressed image
+    Dump(DumpCli),
+    /// Restore a device's used buckets from a compressed image
+    Restore(RestoreCli),
+}
+
+pub fn cmd_device_image(argv: Vec<String>) -> Result<()> {
+    let cli = Cli::parse_from(argv);
+    match cli.subcommands {
+        Subcommands::Dump(c) => dump(&c),
+        Subcommands::Restore(c) => restore(&c),
+    }
+}
@@ -0,0 +1,332 @@
+//! Interactive, ncurses-style browser for a filesystem's on-disk btrees.
+//!
+//! Built on `run_tui` and the `BtreeNodeIter`/`BtreeIter` wrappers: a top
+//! pane lists the known btree IDs, and selecting one opens a node iterator
+//! at the tree's root and renders the current node via `c::btree::to_text`.
+//! Left/Right step between sibling nodes at the current level (`peek`/
+//! `next`), Enter descends into the next level down from the current
+//! node's position, Backspace pops a navigation stack back to the parent
+//! cursor, and `o` toggles between `to_text` and `ondisk_to_text`. Every
+//! node fetch goes through `peek_and_restart`, so a transaction restart
+//! during lock contention just re-renders the same node instead of
+//! crashing the UI.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIterFlags, BtreeNodeIter, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::Parser;
+use crossterm::cursor;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, ClearType};
+
+use crate::commands::inode_opts_device::resolve_devices;
+use crate::logging;
+use crate::util::run_tui;
+use crate::wrappers::accounting::btree_id_str;
+
+/// Depth to request when opening a node iterator "at the root": real
+/// btrees are only a few levels deep, so asking for a depth past any
+/// actual root just clamps to it.
+const ROOT_DEPTH: u32 = 4;
+
+fn to_btree_id(id: u32) -> bcachefs::btree_id {
+    unsafe { std::mem::transmute(id) }
+}
+
+fn btree_list() -> Vec<(u32, String)> {
+    let nr = bcachefs::btree_id::BTREE_ID_NR as u32;
+    (0..nr).map(|id| (id, btree_id_str(id))).collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TextMode {
+    Packed,
+    Ondisk,
+}
+
+/// Where the node iterator for the currently-displayed node is positioned:
+/// which btree and level, the position its sibling walk starts from, how
+/// many `next()` calls in we are, and the actual node's own position (used
+/// as the starting position when descending a level).
+#[derive(Clone)]
+struct Cursor {
+    btree: u32,
+    level: u32,
+    start_pos: c::bpos,
+    sibling_idx: usize,
+    cur_pos: c::bpos,
+}
+
+/// Fetch the node at `sibling_idx` hops past `start_pos` at `depth` in
+/// `btree`, rendered as text. Returns `None` if there's no such node (an
+/// empty btree, or stepping past the last sibling).
+fn fetch_node(
+    trans: &BtreeTrans,
+    fs: &Fs,
+    btree: u32,
+    start_pos: c::bpos,
+    depth: u32,
+    sibling_idx: usize,
+    mode: TextMode,
+) -> Result<Option<(String, u32, c::bpos)>> {
+    let mut iter = BtreeNodeIter::new(trans, to_btree_id(btree), start_pos, 0, depth, BtreeIterFlags::PREFETCH);
+
+    let mut node = iter.peek_and_restart()?;
+    for _ in 0..sibling_idx {
+        if node.is_none() {
+            break;
+        }
+        node = iter.next()?;
+    }
+
+    Ok(node.map(|b| {
+        let text = match mode {
+            TextMode::Packed => format!("{}", b.to_text(fs)),
+            TextMode::Ondisk => format!("{}", b.ondisk_to_text(fs)),
+        };
+        (text, b.c.level as u32, b.key.k.p)
+    }))
+}
+
+struct ExploreState {
+    btrees: Vec<(u32, String)>,
+    btree_sel: usize,
+    cursor: Option<Cursor>,
+    stack: Vec<Cursor>,
+    mode: TextMode,
+    scroll: u16,
+    content: Vec<String>,
+    message: Option<String>,
+}
+
+impl ExploreState {
+    fn new(btrees: Vec<(u32, String)>) -> Self {
+        Self {
+            btrees,
+            btree_sel: 0,
+            cursor: None,
+            stack: Vec::new(),
+            mode: TextMode::Packed,
+            scroll: 0,
+            content: Vec::new(),
+            message: None,
+        }
+    }
+
+    fn set_content(&mut self, text: Option<String>) {
+        self.scroll = 0;
+        self.content = text.map(|t| t.lines().map(str::to_string).collect()).unwrap_or_default();
+    }
+
+    /// Open the selected btree at its root.
+    fn open_root(&mut self, trans: &BtreeTrans, fs: &Fs) -> Result<()> {
+        let btree = self.btrees[self.btree_sel].0;
+        match fetch_node(trans, fs, btree, bch_bindgen::POS_MIN, ROOT_DEPTH, 0, self.mode)? {
+            Some((text, level, pos)) => {
+                self.stack.clear();
+                self.cursor = Some(Cursor { btree, level, start_pos: bch_bindgen::POS_MIN, sibling_idx: 0, cur_pos: pos });
+                self.message = None;
+                self.set_content(Some(text));
+            }
+            None => self.message = Some(format!("{} is empty", self.btrees[self.btree_sel].1)),
+        }
+        Ok(())
+    }
+
+    /// Step to the next/previous sibling node at the current level.
+    fn move_sibling(&mut self, trans: &BtreeTrans, fs: &Fs, delta: i32) -> Result<()> {
+        let Some(cur) = self.cursor.clone() else { return Ok(()) };
+
+        let new_idx = if delta < 0 {
+            match cur.sibling_idx.checked_sub(1) {
+                Some(v) => v,
+                None => return Ok(()),
+            }
+        } else {
+            cur.sibling_idx + 1
+        };
+
+        match fetch_node(trans, fs, cur.btree, cur.start_pos, cur.level, new_idx, self.mode)? {
+            Some((text, level, pos)) => {
+                self.cursor = Some(Cursor { sibling_idx: new_idx, level, cur_pos: pos, ..cur });
+                self.message = None;
+                self.set_content(Some(text));
+            }
+            None => self.message = Some("no further siblings".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Descend into the level below the current node, starting from its
+    /// own position.
+    fn descend(&mut self, trans: &BtreeTrans, fs: &Fs) -> Result<()> {
+        let Some(cur) = self.cursor.clone() else { return Ok(()) };
+        if cur.level == 0 {
+            self.message = Some("already at a leaf node".to_string());
+            return Ok(());
+        }
+
+        match fetch_node(trans, fs, cur.btree, cur.cur_pos, cur.level - 1, 0, self.mode)? {
+            Some((text, level, pos)) => {
+                self.stack.push(cur.clone());
+                self.cursor = Some(Cursor { level, start_pos: cur.cur_pos, sibling_idx: 0, cur_pos: pos, ..cur });
+                self.message = None;
+                self.set_content(Some(text));
+            }
+            None => self.message = Some("no child nodes here".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Pop back to the parent node, or out to the btree list if already at
+    /// the root.
+    fn ascend(&mut self, trans: &BtreeTrans, fs: &Fs) -> Result<()> {
+        match self.stack.pop() {
+            Some(cur) => {
+                let text = fetch_node(trans, fs, cur.btree, cur.start_pos, cur.level, cur.sibling_idx, self.mode)?
+                    .map(|(text, _, _)| text);
+                self.cursor = Some(cur);
+                self.message = None;
+                self.set_content(text);
+            }
+            None => {
+                self.cursor = None;
+                self.message = None;
+                self.content.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn toggle_mode(&mut self, trans: &BtreeTrans, fs: &Fs) -> Result<()> {
+        self.mode = match self.mode {
+            TextMode::Packed => TextMode::Ondisk,
+            TextMode::Ondisk => TextMode::Packed,
+        };
+        if let Some(cur) = self.cursor.clone() {
+            let text = fetch_node(trans, fs, cur.btree, cur.start_pos, cur.level, cur.sibling_idx, self.mode)?
+                .map(|(text, _, _)| text);
+            self.set_content(text);
+        }
+        Ok(())
+    }
+}
+
+fn render(stdout: &mut io::Stdout, state: &ExploreState, rows: u16) -> io::Result<()> {
+    execute!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+
+    let names: Vec<String> = state
+        .btrees
+        .iter()
+        .enumerate()
+        .map(|(i, (_, name))| {
+            let selected = match &state.cursor {
+                Some(cur) => cur.btree == state.btrees[i].0,
+                None => i == state.btree_sel,
+            };
+            if selected { format!("[{}]", name) } else { name.clone() }
+        })
+        .collect();
+    write!(stdout, "Btrees: {}\r\n", names.join(" "))?;
+
+    match &state.cursor {
+        Some(cur) => {
+            let mode = match state.mode { TextMode::Packed => "packed", TextMode::Ondisk => "ondisk" };
+            write!(
+                stdout,
+                "{}  level {}  sibling #{}  {} mode\r\n",
+                state.btrees[state.btree_sel].1, cur.level, cur.sibling_idx, mode
+            )?;
+        }
+        None => write!(stdout, "select a btree, Enter to open\r\n")?,
+    }
+
+    if let Some(msg) = &state.message {
+        write!(stdout, "{}\r\n", msg)?;
+    } else {
+        write!(stdout, "\r\n")?;
+    }
+
+    let content_rows = rows.saturating_sub(5) as usize;
+    for line in state.content.iter().skip(state.scroll as usize).take(content_rows) {
+        write!(stdout, "{}\r\n", line)?;
+    }
+
+    execute!(stdout, cursor::MoveTo(0, rows.saturating_sub(1)))?;
+    write!(stdout, "\u{2190}\u{2192}:sibling  \u{2191}\u{2193}:scroll  Enter:descend  Backspace:ascend  o:mode  q:quit")?;
+    stdout.flush()
+}
+
+fn interactive_loop(stdout: &mut io::Stdout, trans: &BtreeTrans, fs: &Fs, mut state: ExploreState) -> Result<()> {
+    loop {
+        let (_, rows) = terminal::size().context("reading terminal size")?;
+        render(stdout, &state, rows)?;
+
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Enter => {
+                    if state.cursor.is_none() {
+                        state.open_root(trans, fs)?;
+                    } else {
+                        state.descend(trans, fs)?;
+                    }
+                }
+                KeyCode::Backspace => state.ascend(trans, fs)?,
+                KeyCode::Char('o') => state.toggle_mode(trans, fs)?,
+                KeyCode::Left if state.cursor.is_some() => state.move_sibling(trans, fs, -1)?,
+                KeyCode::Right if state.cursor.is_some() => state.move_sibling(trans, fs, 1)?,
+                KeyCode::Up if state.cursor.is_some() => state.scroll = state.scroll.saturating_sub(1),
+                KeyCode::Down if state.cursor.is_some() => state.scroll = state.scroll.saturating_add(1),
+                KeyCode::Up => state.btree_sel = state.btree_sel.saturating_sub(1),
+                KeyCode::Down => state.btree_sel = (state.btree_sel + 1).min(state.btrees.len() - 1),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Interactively browse a filesystem's on-disk btrees
+#[derive(Parser, Debug)]
+pub struct Cli {
+    /// Devices, or a mounted directory to resolve devices from
+    #[arg(required = true)]
+    devices: Vec<PathBuf>,
+
+    /// Verbose mode
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+pub fn explore(argv: Vec<String>) -> Result<()> {
+    let opt = Cli::parse_from(argv);
+    logging::setup(opt.verbose, false);
+
+    let mut devices = Vec::new();
+    for path in &opt.devices {
+        devices.extend(resolve_devices(path)?);
+    }
+
+    let mut fs_opts = bcachefs::bch_opts::default();
+    opt_set!(fs_opts, noexcl, 1);
+    opt_set!(fs_opts, nochanges, 1);
+    opt_set!(fs_opts, read_only, 1);
+    opt_set!(fs_opts, norecovery, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+    opt_set!(fs_opts, errors, bcachefs::bch_error_actions::BCH_ON_ERROR_continue as u8);
+
+    let fs = Fs::open(&devices, fs_opts)?;
+    let trans = BtreeTrans::new(&fs);
+    let state = ExploreState::new(btree_list());
+
+    run_tui(|stdout| interactive_loop(stdout, &trans, &fs, state))
+}
@@ -1,9 +1,29 @@
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
+
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write as FmtWrite;
+use std::fs as stdfs;
+use std::io::{self, BufRead, Write as IoWrite};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use clap::Parser;
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIter, BtreeIterFlags, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::{Parser, ValueEnum};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyModifiers},
+    execute,
+    terminal::{self, ClearType},
+};
+use serde::{Deserialize, Serialize};
 
-use crate::util::fmt_bytes_human;
 use crate::wrappers::accounting::{self, AccountingEntry, DiskAccountingPos};
 use crate::wrappers::handle::{BcachefsHandle, DevUsage};
 use crate::wrappers::sysfs::{self, DevInfo, bcachefs_kernel_version};
@@ -14,6 +34,7 @@ const FIELD_BTREE: u32          = 1 << 1;
 const FIELD_COMPRESSION: u32    = 1 << 2;
 const FIELD_REBALANCE_WORK: u32 = 1 << 3;
 const FIELD_DEVICES: u32        = 1 << 4;
+const FIELD_SNAPSHOTS: u32      = 1 << 5;
 
 const FIELD_NAMES: &[(&str, u32)] = &[
     ("replicas",       FIELD_REPLICAS),
@@ -21,8 +42,12 @@ const FIELD_NAMES: &[(&str, u32)] = &[
     ("compression",    FIELD_COMPRESSION),
     ("rebalance_work", FIELD_REBALANCE_WORK),
     ("devices",        FIELD_DEVICES),
+    ("snapshots",      FIELD_SNAPSHOTS),
 ];
 
+/// BCH_DISK_ACCOUNTING_snapshot
+const ACCOUNTING_SNAPSHOT: u32 = 1 << 5;
+
 /// Version at which reconcile replaced rebalance_work accounting.
 const VERSION_RECONCILE: u64 = (1 << 10) | 33; // BCH_VERSION(1, 33) = 1057
 
@@ -42,6 +67,24 @@ const DATA_NEED_GC_GENS: u8 = 8;
 /// BCH_DATA_need_discard
 const DATA_NEED_DISCARD: u8 = 9;
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+/// Unit system for size columns: raw byte counts, SI (base-1000, kB/MB/GB),
+/// or IEC (base-1024, KiB/MiB/GiB).
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Units {
+    #[default]
+    Raw,
+    Si,
+    Iec,
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "usage", about = "Display detailed filesystem usage")]
@@ -54,9 +97,27 @@ pub struct Cli {
     #[arg(short = 'a', long = "all")]
     all: bool,
 
-    /// Human-readable units
-    #[arg(short = 'h', long = "human-readable")]
-    human_readable: bool,
+    /// Unit system for size output: raw, si (kB/MB/GB), or iec (KiB/MiB/GiB)
+    #[arg(short = 'u', long = "units", value_enum, default_value_t = Units::Raw)]
+    units: Units,
+
+    /// Output format
+    #[arg(long = "format", value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Save the gathered accounting state to this file, for later --diff
+    #[arg(long = "save", conflicts_with = "diff")]
+    save: Option<PathBuf>,
+
+    /// Print the change in accounting state since the state saved at this
+    /// path by a previous --save, instead of absolute values
+    #[arg(long = "diff", conflicts_with = "save")]
+    diff: Option<PathBuf>,
+
+    /// Refresh every SECS seconds, showing the live drain rate and ETA of
+    /// any pending reconcile work and device evacuation alongside usage
+    #[arg(long = "watch", value_name = "SECS", conflicts_with_all = ["save", "diff"])]
+    watch: Option<u64>,
 
     /// Filesystem mountpoints
     #[arg(default_value = ".")]
@@ -90,32 +151,353 @@ pub fn fs_usage(argv: Vec<String>) -> Result<()> {
         fields = FIELD_REBALANCE_WORK;
     }
 
+    if let Some(interval_secs) = cli.watch {
+        let path = cli.mountpoints.first().map(String::as_str).unwrap_or(".");
+        return run_watch(path, fields, interval_secs, cli.units);
+    }
+
     for path in &cli.mountpoints {
-        let mut out = String::new();
-        fs_usage_to_text(&mut out, path, fields, cli.human_readable)?;
-        print!("{}", out);
+        let model = collect_usage(path, fields)?;
+
+        if let Some(save_path) = &cli.save {
+            stdfs::write(save_path, serde_json::to_string(&model)?)
+                .map_err(|e| anyhow!("saving state to '{}': {}", save_path.display(), e))?;
+        }
+
+        if let Some(diff_path) = &cli.diff {
+            let prev: UsageModel = serde_json::from_str(
+                &stdfs::read_to_string(diff_path)
+                    .map_err(|e| anyhow!("reading saved state '{}': {}", diff_path.display(), e))?,
+            )?;
+            let delta = diff_usage(&prev, &model);
+
+            match cli.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&delta)?),
+                #[cfg(feature = "yaml")]
+                OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&delta)?),
+                OutputFormat::Text => print!("{}", render_diff_text(&delta, cli.units)),
+            }
+            continue;
+        }
+
+        match cli.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string(&model)?),
+            #[cfg(feature = "yaml")]
+            OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&model)?),
+            OutputFormat::Text => print!("{}", render_text(&model, fields, cli.units)),
+        }
     }
 
     Ok(())
 }
 
-fn fmt_size(out: &mut String, sectors: u64, human_readable: bool) {
-    let bytes = sectors << 9;
-    if human_readable {
-        write!(out, "{}", fmt_bytes_human(bytes)).unwrap();
+/// Resolve the block devices backing a mounted bcachefs filesystem, by
+/// parsing `/proc/self/mountinfo` (same approach as the inode-opts
+/// mount-path resolver).
+fn devices_from_mountpoint(path: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(path);
+    let meta = stdfs::metadata(path)?;
+
+    if !meta.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mount_path = path.canonicalize()?;
+    let mount_str = mount_path.to_string_lossy();
+
+    let file = stdfs::File::open("/proc/self/mountinfo")?;
+    let reader = std::io::BufReader::new(file);
+
+    for line in reader.lines() {
+        let line = line?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 10 || parts[4] != mount_str {
+            continue;
+        }
+
+        let Some(sep_idx) = parts.iter().position(|&p| p == "-") else { continue };
+        if sep_idx + 2 >= parts.len() {
+            continue;
+        }
+
+        let source = parts[sep_idx + 2];
+        return Ok(source.split(':').map(PathBuf::from).collect());
+    }
+
+    Err(anyhow!("mount point not found: {}", mount_str))
+}
+
+/// Open `path`'s backing devices read-only and non-exclusively, alongside
+/// whatever live `BcachefsHandle` the caller already holds on the mounted
+/// filesystem. Used by scans that need to walk a btree the ioctl interface
+/// doesn't expose (e.g. subvolumes, alloc).
+fn open_offline_ro(path: &str) -> Result<Fs> {
+    let devices = devices_from_mountpoint(path)?;
+
+    let mut fs_opts = bcachefs::bch_opts::default();
+    opt_set!(fs_opts, noexcl, 1);
+    opt_set!(fs_opts, nochanges, 1);
+    opt_set!(fs_opts, read_only, 1);
+    opt_set!(fs_opts, norecovery, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+
+    Fs::open(&devices, fs_opts).map_err(|e| anyhow!("opening filesystem '{}': {}", path, e))
+}
+
+/// Build a snapshot ID -> subvolume ID map by scanning BTREE_ID_subvolumes.
+/// Requires (read-only, non-exclusive) native access to the filesystem, on
+/// top of the sysfs/ioctl access everything else in this module uses.
+fn resolve_snapshot_subvols(path: &str) -> Result<HashMap<u32, u32>> {
+    let fs = open_offline_ro(path)?;
+
+    let trans = BtreeTrans::new(&fs);
+    let mut iter = BtreeIter::new(
+        &trans,
+        bcachefs::btree_id::BTREE_ID_subvolumes,
+        bch_bindgen::POS_MIN,
+        BtreeIterFlags::empty(),
+    );
+
+    let mut map = HashMap::new();
+    while let Some(k) = iter.peek_and_restart()? {
+        if k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_subvolume as u8 {
+            let subvol = unsafe { &*(k.v as *const c::bch_val as *const c::bch_subvolume) };
+            map.insert(subvol.snapshot, k.k.p.offset as u32);
+        }
+        iter.advance();
+    }
+
+    Ok(map)
+}
+
+/// Build each device's real per-bucket fill histogram by scanning
+/// `BTREE_ID_alloc`, the same offline-scan-alongside-the-live-handle trick
+/// [`resolve_snapshot_subvols`] uses for subvolumes. `bucket_sizes` maps
+/// device index to its bucket size (in sectors); devices missing from it are
+/// skipped. Returns, per device index, a `BUCKET_HISTOGRAM_BINS`-length
+/// histogram of bucket counts by fullness.
+fn scan_bucket_histograms(
+    path: &str,
+    bucket_sizes: &HashMap<u32, u64>,
+) -> Result<HashMap<u32, Vec<u64>>> {
+    let fs = open_offline_ro(path)?;
+
+    let trans = BtreeTrans::new(&fs);
+    let mut iter = BtreeIter::new(
+        &trans,
+        bcachefs::btree_id::BTREE_ID_alloc,
+        bch_bindgen::POS_MIN,
+        BtreeIterFlags::empty(),
+    );
+
+    let mut histograms: HashMap<u32, Vec<u64>> = HashMap::new();
+
+    while let Some(k) = iter.peek_and_restart()? {
+        if k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_alloc_v4 as u8 {
+            let dev_idx = k.k.p.inode as u32;
+            if let Some(&bucket_size) = bucket_sizes.get(&dev_idx) {
+                let alloc = unsafe { &*(k.v as *const c::bch_val as *const c::bch_alloc_v4) };
+                let used = alloc.dirty_sectors as u64
+                    + alloc.cached_sectors as u64
+                    + alloc.stripe_sectors as u64;
+                let fill = used as f64 / bucket_size as f64;
+                let bin = fill_to_bin(fill);
+                histograms
+                    .entry(dev_idx)
+                    .or_insert_with(|| vec![0u64; BUCKET_HISTOGRAM_BINS])[bin] += 1;
+            }
+        }
+        iter.advance();
+    }
+
+    Ok(histograms)
+}
+
+// ──────────────────────────── Serializable usage model ──────────────────────
+
+/// One row of the durability x degraded matrix: how much data is stored at
+/// `durability`, with `degraded` worth of that durability currently missing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DurabilityRow {
+    durability: u32,
+    degraded: u32,
+    sectors: u64,
+}
+
+/// One non-reserved, non-cached replicas entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ReplicasRow {
+    data_type: String,
+    nr_required: u8,
+    nr_devs: u8,
+    durability: u32,
+    devices: Vec<String>,
+    sectors: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CompressionRow {
+    compression_type: String,
+    nr_extents: u64,
+    sectors_compressed: u64,
+    sectors_uncompressed: u64,
+    ratio: f64,
+    #[serde(skip)]
+    incompressible: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct BtreeRow {
+    btree: String,
+    sectors: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SnapshotRow {
+    snapshot: u32,
+    subvol: Option<u32>,
+    sectors: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ReconcileRow {
+    work_type: String,
+    data_sectors: u64,
+    metadata_sectors: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DeviceDataTypeRow {
+    data_type: String,
+    sectors: u64,
+    buckets: u64,
+    fragmented: u64,
+    /// Estimated logical (pre-compression) size, derived by applying the
+    /// filesystem-wide compression ratio (see `collect_compression`) to this
+    /// data type's on-disk `sectors`. Only meaningful for compressible data
+    /// types; equal to `sectors` (ratio 1.0) otherwise.
+    sectors_uncompressed: u64,
+    compression_ratio: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DeviceUsageRow {
+    idx: u32,
+    dev: String,
+    label: Option<String>,
+    state: String,
+    capacity: u64,
+    used: u64,
+    use_percent: u64,
+    bucket_size: u64,
+    nr_buckets: u64,
+    leaving: u64,
+    /// Sum of `fragmented` across this device's non-empty data types: space
+    /// copygc/compaction could recover.
+    reclaimable: u64,
+    data_types: Vec<DeviceDataTypeRow>,
+    /// Bucket count by fullness, in ten equal-width bins (0: 0-10% full, ...,
+    /// 9: 90-100% full). Built from real per-bucket fill read off
+    /// `BTREE_ID_alloc` (see [`scan_bucket_histograms`]); drives
+    /// copygc/compaction cost estimates. Falls back to an approximation from
+    /// each data type's average fill (`sectors` / (`buckets` * `bucket_size`))
+    /// if the offline alloc-btree scan isn't available (e.g. insufficient
+    /// permission to open the backing devices read-only alongside the mount).
+    bucket_histogram: Vec<u64>,
+}
+
+const BUCKET_HISTOGRAM_BINS: usize = 10;
+
+/// Map a fullness ratio (0.0-1.0, or above if a caller's sums exceed what
+/// should be possible) to one of `BUCKET_HISTOGRAM_BINS` equal-width bins.
+fn fill_to_bin(fill: f64) -> usize {
+    ((fill * BUCKET_HISTOGRAM_BINS as f64) as usize).min(BUCKET_HISTOGRAM_BINS - 1)
+}
+
+/// Everything `bcachefs usage` can report, gathered once and rendered either
+/// as text or JSON. Fields not requested via `--fields`/`--all` are left at
+/// their empty/zero default rather than omitted, so the v1 (`query_accounting`)
+/// and v0 (legacy ioctl) collectors produce the same schema.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct UsageModel {
+    filesystem: String,
+    capacity: u64,
+    used: u64,
+    online_reserved: u64,
+    cached: u64,
+    reserved: u64,
+    durability: Vec<DurabilityRow>,
+    replicas: Vec<ReplicasRow>,
+    compression: Vec<CompressionRow>,
+    btree: Vec<BtreeRow>,
+    snapshots: Vec<SnapshotRow>,
+    rebalance_work: Option<u64>,
+    reconcile_work: Vec<ReconcileRow>,
+    devices: Vec<DeviceUsageRow>,
+}
+
+fn fmt_size(out: &mut String, sectors: u64, units: Units) {
+    write!(out, "{}", fmt_bytes_units(sectors << 9, units)).unwrap();
+}
+
+fn fmt_size_bytes(out: &mut String, bytes: u64, units: Units) {
+    write!(out, "{}", fmt_bytes_units(bytes, units)).unwrap();
+}
+
+/// Render `bytes` per the selected unit system: `Raw` is a plain byte
+/// count, `Si`/`Iec` scale to base-1000/base-1024 units with one decimal
+/// place so size columns line up regardless of magnitude.
+fn fmt_bytes_units(bytes: u64, units: Units) -> String {
+    match units {
+        Units::Raw => bytes.to_string(),
+        Units::Si => fmt_bytes_scaled(bytes, 1000.0, &["B", "kB", "MB", "GB", "TB", "PB"]),
+        Units::Iec => fmt_bytes_scaled(bytes, 1024.0, &["B", "KiB", "MiB", "GiB", "TiB", "PiB"]),
+    }
+}
+
+fn fmt_bytes_scaled(bytes: u64, base: f64, suffixes: &[&str]) -> String {
+    if bytes == 0 {
+        return format!("0{}", suffixes[0]);
+    }
+
+    let mut val = bytes as f64;
+    let mut unit = suffixes[0];
+    for &s in &suffixes[1..] {
+        if val < base {
+            break;
+        }
+        val /= base;
+        unit = s;
+    }
+
+    if unit == suffixes[0] {
+        format!("{}{}", bytes, unit)
     } else {
-        write!(out, "{}", bytes).unwrap();
+        format!("{:.1}{}", val, unit)
     }
 }
 
-fn fmt_size_bytes(out: &mut String, bytes: u64, human_readable: bool) {
-    if human_readable {
-        write!(out, "{}", fmt_bytes_human(bytes)).unwrap();
+fn compression_ratio(sectors_uncompressed: u64, sectors_compressed: u64) -> f64 {
+    if sectors_compressed == 0 {
+        0.0
     } else {
-        write!(out, "{}", bytes).unwrap();
+        sectors_uncompressed as f64 / sectors_compressed as f64
     }
 }
 
+/// Filesystem-wide compression ratio across genuinely compressed types
+/// (excluding `incompressible`), used to estimate each device's per-data-type
+/// logical size from its on-disk `sectors` since device usage is only
+/// tracked physically. 1.0 (no-op) if compression accounting wasn't
+/// collected or nothing compressible has been written yet.
+fn overall_compression_ratio(compression: &[CompressionRow]) -> f64 {
+    let (uncompressed, compressed): (u64, u64) = compression.iter()
+        .filter(|r| !r.incompressible)
+        .fold((0, 0), |(u, c), r| (u + r.sectors_uncompressed, c + r.sectors_compressed));
+
+    if compressed == 0 { 1.0 } else { uncompressed as f64 / compressed as f64 }
+}
+
 fn fmt_uuid(uuid: &[u8; 16]) -> String {
     format!("{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
         uuid[0], uuid[1], uuid[2], uuid[3],
@@ -129,44 +511,79 @@ fn data_type_is_empty(t: u8) -> bool {
     matches!(t, DATA_FREE | DATA_NEED_GC_GENS | DATA_NEED_DISCARD)
 }
 
+fn dev_name(devs: &[DevInfo], dev_idx: u8) -> String {
+    if dev_idx == SB_MEMBER_INVALID {
+        "none".to_string()
+    } else if let Some(d) = devs.iter().find(|d| d.idx == dev_idx as u32) {
+        d.dev.clone()
+    } else {
+        dev_idx.to_string()
+    }
+}
+
 struct DevContext {
     info: DevInfo,
     usage: DevUsage,
     leaving: u64,
 }
 
-fn fs_usage_to_text(out: &mut String, path: &str, fields: u32, human_readable: bool) -> Result<()> {
+/// Devices whose state makes data on them unreliable for durability
+/// purposes: explicitly ro/evacuating/failed, or mid-evacuation (nonzero
+/// dev_leaving sectors) even while still nominally rw.
+fn degraded_devs(handle: &BcachefsHandle, devs: &[DevInfo]) -> HashSet<u32> {
+    let dev_leaving_map = match handle.query_accounting(1 << 10) {
+        Ok(result) => result.entries,
+        Err(_) => Vec::new(),
+    };
+
+    devs.iter()
+        .filter(|d| {
+            let state = handle.dev_usage(d.idx).map(|u| u.state).unwrap_or(0);
+            matches!(accounting::member_state_str(state), "ro" | "evacuating" | "failed")
+                || dev_leaving_sectors(&dev_leaving_map, d.idx) > 0
+        })
+        .map(|d| d.idx)
+        .collect()
+}
+
+/// Open `path` and gather everything `UsageModel` holds: accounting via the
+/// v1 `query_accounting` ioctl, falling back to the v0 legacy ioctl on
+/// ENOTTY, plus the per-device breakdown both paths share.
+fn collect_usage(path: &str, fields: u32) -> Result<UsageModel> {
     let handle = BcachefsHandle::open(path)
         .map_err(|e| anyhow!("opening filesystem '{}': {}", path, e))?;
 
     let sysfs_path = sysfs::sysfs_path_from_fd(handle.sysfs_fd())?;
     let devs = sysfs::fs_get_devices(&sysfs_path)?;
+    let degraded = degraded_devs(&handle, &devs);
 
-    // Try v1 (query_accounting), fall back to v0 on ENOTTY
-    let v1_ok = match fs_usage_v1_to_text(out, &handle, &devs, fields, human_readable) {
-        Ok(()) => true,
-        Err(e) if e.0 == libc::ENOTTY => false,
-        Err(e) => return Err(anyhow!("query_accounting failed: {}", e)),
+    let snapshot_subvols = if fields & FIELD_SNAPSHOTS != 0 {
+        resolve_snapshot_subvols(path)?
+    } else {
+        HashMap::new()
     };
 
-    if !v1_ok {
-        fs_usage_v0_to_text(out, &handle, &devs, fields, human_readable)?;
-    }
+    let mut model = match collect_usage_v1(&handle, &devs, &degraded, fields, &snapshot_subvols) {
+        Ok(model) => model,
+        Err(e) if e.0 == libc::ENOTTY => collect_usage_v0(&handle, &devs, &degraded, fields)?,
+        Err(e) => return Err(anyhow!("query_accounting failed: {}", e)),
+    };
 
-    devs_usage_to_text(out, &handle, &devs, fields, human_readable)?;
+    let compression_ratio = overall_compression_ratio(&model.compression);
+    model.devices = collect_device_usage(path, &handle, &devs, compression_ratio)?;
 
-    Ok(())
+    Ok(model)
 }
 
 // ──────────────────────────── v1 path (query_accounting) ────────────────────
 
-fn fs_usage_v1_to_text(
-    out: &mut String,
+fn collect_usage_v1(
     handle: &BcachefsHandle,
     devs: &[DevInfo],
+    degraded: &HashSet<u32>,
     fields: u32,
-    human_readable: bool,
-) -> Result<(), errno::Errno> {
+    snapshot_subvols: &HashMap<u32, u32>,
+) -> Result<UsageModel, errno::Errno> {
     let mut accounting_types: u32 =
         (1 << 2) |  // BCH_DISK_ACCOUNTING_replicas
         (1 << 1);   // BCH_DISK_ACCOUNTING_persistent_reserved
@@ -177,6 +594,9 @@ fn fs_usage_v1_to_text(
     if fields & FIELD_BTREE != 0 {
         accounting_types |= 1 << 6; // btree
     }
+    if fields & FIELD_SNAPSHOTS != 0 {
+        accounting_types |= ACCOUNTING_SNAPSHOT;
+    }
     if fields & FIELD_REBALANCE_WORK != 0 {
         if bcachefs_kernel_version() < VERSION_RECONCILE {
             accounting_types |= 1 << 7; // rebalance_work
@@ -188,153 +608,31 @@ fn fs_usage_v1_to_text(
 
     let result = handle.query_accounting(accounting_types)?;
 
-    // Sort entries by bpos
     let mut sorted: Vec<&AccountingEntry> = result.entries.iter().collect();
     sorted.sort_by(|a, b| a.bpos.cmp(&b.bpos));
 
-    // Header
-    writeln!(out, "Filesystem: {}", fmt_uuid(&handle.uuid())).unwrap();
-    write!(out, "Size:                ").unwrap();
-    fmt_size(out, result.capacity, human_readable);
-    writeln!(out).unwrap();
-    write!(out, "Used:                ").unwrap();
-    fmt_size(out, result.used, human_readable);
-    writeln!(out).unwrap();
-    write!(out, "Online reserved:     ").unwrap();
-    fmt_size(out, result.online_reserved, human_readable);
-    writeln!(out).unwrap();
+    let mut model = UsageModel {
+        filesystem: fmt_uuid(&handle.uuid()),
+        capacity: result.capacity,
+        used: result.used,
+        online_reserved: result.online_reserved,
+        ..Default::default()
+    };
 
-    // Replicas summary
-    replicas_summary_to_text(out, &sorted, devs, human_readable);
+    collect_durability(&sorted, devs, degraded, &mut model);
 
-    // Detailed replicas
     if fields & FIELD_REPLICAS != 0 {
-        writeln!(out, "\n{:<16}{:<16}{:<14}{:<14}",
-            "Data type", "Required/total", "Durability", "Devices").unwrap();
-
-        for entry in &sorted {
-            match &entry.pos {
-                DiskAccountingPos::PersistentReserved { nr_replicas } => {
-                    let sectors = entry.counters.first().copied().unwrap_or(0) as i64;
-                    if sectors == 0 { continue; }
-                    write!(out, "reserved:       1/{:<13}", nr_replicas).unwrap();
-                    write!(out, "[] ").unwrap();
-                    fmt_size(out, sectors as u64, human_readable);
-                    writeln!(out).unwrap();
-                }
-                DiskAccountingPos::Replicas { data_type, nr_devs, nr_required, devs: dev_list } => {
-                    let sectors = entry.counters.first().copied().unwrap_or(0) as i64;
-                    if sectors == 0 { continue; }
-
-                    let dur = replicas_durability(*data_type, *nr_devs, *nr_required, dev_list, devs);
-
-                    write!(out, "{:<16}", format!("{}:", accounting::data_type_str(*data_type))).unwrap();
-                    write!(out, "{:<16}", format!("{}/{}", nr_required, nr_devs)).unwrap();
-                    write!(out, "{:<14}", dur.durability).unwrap();
-
-                    write!(out, "[").unwrap();
-                    for (i, &dev_idx) in dev_list.iter().enumerate() {
-                        if i > 0 { write!(out, " ").unwrap(); }
-                        if dev_idx == SB_MEMBER_INVALID {
-                            write!(out, "none").unwrap();
-                        } else if let Some(d) = devs.iter().find(|d| d.idx == dev_idx as u32) {
-                            write!(out, "{}", d.dev).unwrap();
-                        } else {
-                            write!(out, "{}", dev_idx).unwrap();
-                        }
-                    }
-                    write!(out, "] ").unwrap();
-                    fmt_size(out, sectors as u64, human_readable);
-                    writeln!(out).unwrap();
-                }
-                _ => {}
-            }
-        }
-    }
-
-    // Compression
-    let mut first_compression = true;
-    for entry in &sorted {
-        if let DiskAccountingPos::Compression { compression_type } = &entry.pos {
-            if first_compression {
-                writeln!(out, "\nCompression:").unwrap();
-                writeln!(out, "{:<12}{:>16}{:>16}{:>24}", "type", "compressed", "uncompressed", "average extent size").unwrap();
-                first_compression = false;
-            }
-
-            let nr_extents = entry.counters.first().copied().unwrap_or(0);
-            let sectors_uncompressed = entry.counters.get(1).copied().unwrap_or(0);
-            let sectors_compressed = entry.counters.get(2).copied().unwrap_or(0);
-
-            write!(out, "{:<12}", accounting::compression_type_str(*compression_type)).unwrap();
-            let mut s = String::new();
-            fmt_size(&mut s, sectors_compressed, human_readable);
-            write!(out, "{:>16}", s).unwrap();
-            s.clear();
-            fmt_size(&mut s, sectors_uncompressed, human_readable);
-            write!(out, "{:>16}", s).unwrap();
-            s.clear();
-            let avg = if nr_extents > 0 {
-                (sectors_uncompressed << 9) / nr_extents
-            } else { 0 };
-            fmt_size_bytes(&mut s, avg, human_readable);
-            write!(out, "{:>24}", s).unwrap();
-            writeln!(out).unwrap();
-        }
-    }
-
-    // Btree usage
-    let mut first_btree = true;
-    for entry in &sorted {
-        if let DiskAccountingPos::Btree { id } = &entry.pos {
-            if first_btree {
-                writeln!(out, "\nBtree usage:").unwrap();
-                first_btree = false;
-            }
-            write!(out, "{:<12} ", format!("{}:", accounting::btree_id_str(*id))).unwrap();
-            fmt_size(out, entry.counters.first().copied().unwrap_or(0), human_readable);
-            writeln!(out).unwrap();
-        }
+        collect_replicas(&sorted, devs, degraded, &mut model);
     }
 
-    // Rebalance / reconcile work
-    let mut first_rebalance = true;
-    let mut first_reconcile = true;
-    for entry in &sorted {
-        match &entry.pos {
-            DiskAccountingPos::RebalanceWork => {
-                if first_rebalance {
-                    writeln!(out, "\nPending rebalance work:").unwrap();
-                    first_rebalance = false;
-                }
-                fmt_size(out, entry.counters.first().copied().unwrap_or(0), human_readable);
-                writeln!(out).unwrap();
-            }
-            DiskAccountingPos::ReconcileWork { work_type } => {
-                if first_reconcile {
-                    writeln!(out, "\n{:<32}{:>12}{:>12}", "Pending reconcile:", "data", "metadata").unwrap();
-                    first_reconcile = false;
-                }
-                write!(out, "{}:", accounting::reconcile_type_str(*work_type)).unwrap();
-                let pad = 32usize.saturating_sub(accounting::reconcile_type_str(*work_type).len() + 1);
-                write!(out, "{:width$}", "", width = pad).unwrap();
-                let mut s = String::new();
-                fmt_size(&mut s, entry.counters.first().copied().unwrap_or(0), human_readable);
-                write!(out, "{:>12}", s).unwrap();
-                s.clear();
-                fmt_size(&mut s, entry.counters.get(1).copied().unwrap_or(0), human_readable);
-                write!(out, "{:>12}", s).unwrap();
-                writeln!(out).unwrap();
-            }
-            _ => {}
-        }
-    }
+    collect_compression(&sorted, &mut model);
+    collect_btree(&sorted, &mut model);
+    collect_snapshots(&sorted, snapshot_subvols, &mut model);
+    collect_rebalance_reconcile(&sorted, &mut model);
 
-    Ok(())
+    Ok(model)
 }
 
-// ──────────────────────────── Replicas summary ──────────────────────────────
-
 struct DurabilityDegraded {
     durability: u32,
     minus_degraded: u32,
@@ -346,6 +644,7 @@ fn replicas_durability(
     nr_required: u8,
     dev_list: &[u8],
     devs: &[DevInfo],
+    degraded_devs: &HashSet<u32>,
 ) -> DurabilityDegraded {
     let mut durability: u32 = 0;
     let mut degraded: u32 = 0;
@@ -354,11 +653,9 @@ fn replicas_durability(
         let dev = devs.iter().find(|d| d.idx == dev_idx as u32);
         let dev_durability = dev.map_or(1, |d| d.durability);
 
-        if dev.is_none() {
+        if dev.is_none() || degraded_devs.contains(&(dev_idx as u32)) {
             degraded += dev_durability;
         }
-        // TODO: check for evacuating state (requires reading superblock or
-        // passing dev_usage state through to this function)
         durability += dev_durability;
     }
 
@@ -371,87 +668,145 @@ fn replicas_durability(
     DurabilityDegraded { durability, minus_degraded }
 }
 
-fn replicas_summary_to_text(
-    out: &mut String,
+/// Durability x degraded matrix, plus cached/reserved totals.
+fn collect_durability(
     sorted: &[&AccountingEntry],
     devs: &[DevInfo],
-    human_readable: bool,
+    degraded_devs: &HashSet<u32>,
+    model: &mut UsageModel,
 ) {
-    // Build durability × degraded matrix
-    let mut matrix: Vec<Vec<u64>> = Vec::new(); // [durability][degraded] = sectors
-    let mut cached: u64 = 0;
-    let mut reserved: u64 = 0;
+    let mut matrix: HashMap<(u32, u32), u64> = HashMap::new();
 
     for entry in sorted {
         match &entry.pos {
             DiskAccountingPos::PersistentReserved { .. } => {
-                reserved += entry.counters.first().copied().unwrap_or(0);
+                model.reserved += entry.counters.first().copied().unwrap_or(0);
             }
             DiskAccountingPos::Replicas { data_type, nr_devs, nr_required, devs: dev_list } => {
                 if *data_type == DATA_CACHED {
-                    cached += entry.counters.first().copied().unwrap_or(0);
+                    model.cached += entry.counters.first().copied().unwrap_or(0);
                     continue;
                 }
 
-                let d = replicas_durability(*data_type, *nr_devs, *nr_required, dev_list, devs);
+                let d = replicas_durability(*data_type, *nr_devs, *nr_required, dev_list, devs, degraded_devs);
                 let degraded = d.durability - d.minus_degraded;
-
-                while matrix.len() <= d.durability as usize {
-                    matrix.push(Vec::new());
-                }
-                let row = &mut matrix[d.durability as usize];
-                while row.len() <= degraded as usize {
-                    row.push(0);
-                }
-                row[degraded as usize] += entry.counters.first().copied().unwrap_or(0);
+                *matrix.entry((d.durability, degraded)).or_insert(0) +=
+                    entry.counters.first().copied().unwrap_or(0);
             }
             _ => {}
         }
     }
 
-    writeln!(out, "\nData by durability desired and amount degraded:").unwrap();
-
-    let max_degraded = matrix.iter().map(|r| r.len()).max().unwrap_or(0);
+    let mut rows: Vec<DurabilityRow> = matrix.into_iter()
+        .map(|((durability, degraded), sectors)| DurabilityRow { durability, degraded, sectors })
+        .collect();
+    rows.sort_by(|a, b| a.durability.cmp(&b.durability).then(a.degraded.cmp(&b.degraded)));
+    model.durability = rows;
+}
 
-    if max_degraded > 0 {
-        // Header
-        write!(out, "        ").unwrap();
-        for i in 0..max_degraded {
-            if i == 0 {
-                write!(out, "{:>12}", "undegraded").unwrap();
-            } else {
-                write!(out, "{:>12}", format!("-{}x", i)).unwrap();
+fn collect_replicas(
+    sorted: &[&AccountingEntry],
+    devs: &[DevInfo],
+    degraded_devs: &HashSet<u32>,
+    model: &mut UsageModel,
+) {
+    for entry in sorted {
+        match &entry.pos {
+            DiskAccountingPos::PersistentReserved { nr_replicas } => {
+                let sectors = entry.counters.first().copied().unwrap_or(0);
+                if sectors == 0 { continue; }
+                model.replicas.push(ReplicasRow {
+                    data_type: "reserved".to_string(),
+                    nr_required: 1,
+                    nr_devs: *nr_replicas,
+                    durability: 0,
+                    devices: Vec::new(),
+                    sectors,
+                });
             }
+            DiskAccountingPos::Replicas { data_type, nr_devs, nr_required, devs: dev_list } => {
+                let sectors = entry.counters.first().copied().unwrap_or(0);
+                if sectors == 0 { continue; }
+
+                let dur = replicas_durability(*data_type, *nr_devs, *nr_required, dev_list, devs, degraded_devs);
+
+                model.replicas.push(ReplicasRow {
+                    data_type: accounting::data_type_str(*data_type as u8),
+                    nr_required: *nr_required,
+                    nr_devs: *nr_devs,
+                    durability: dur.durability,
+                    devices: dev_list.iter().map(|&d| dev_name(devs, d)).collect(),
+                    sectors,
+                });
+            }
+            _ => {}
         }
-        writeln!(out).unwrap();
+    }
+}
 
-        // Rows
-        for (dur, row) in matrix.iter().enumerate() {
-            if row.is_empty() { continue; }
+fn collect_compression(sorted: &[&AccountingEntry], model: &mut UsageModel) {
+    for entry in sorted {
+        if let DiskAccountingPos::Compression { compression_type } = &entry.pos {
+            let nr_extents = entry.counter(0);
+            let sectors_uncompressed = entry.counter(1);
+            let sectors_compressed = entry.counter(2);
+
+            model.compression.push(CompressionRow {
+                compression_type: accounting::compression_type_str(*compression_type),
+                nr_extents,
+                sectors_compressed,
+                sectors_uncompressed,
+                ratio: compression_ratio(sectors_uncompressed, sectors_compressed),
+                incompressible: *compression_type
+                    == accounting::bch_compression_type::BCH_COMPRESSION_TYPE_incompressible,
+            });
+        }
+    }
+}
 
-            write!(out, "{}x:     ", dur).unwrap();
-            for val in row {
-                if *val != 0 {
-                    let mut s = String::new();
-                    fmt_size(&mut s, *val, human_readable);
-                    write!(out, "{:>12}", s).unwrap();
-                } else {
-                    write!(out, "{:>12}", "").unwrap();
-                }
-            }
-            writeln!(out).unwrap();
+fn collect_btree(sorted: &[&AccountingEntry], model: &mut UsageModel) {
+    for entry in sorted {
+        if let DiskAccountingPos::Btree { id } = &entry.pos {
+            model.btree.push(BtreeRow {
+                btree: accounting::btree_id_str(*id),
+                sectors: entry.counters.first().copied().unwrap_or(0),
+            });
         }
     }
+}
 
-    if cached > 0 {
-        write!(out, "cached: ").unwrap();
-        fmt_size(out, cached, human_readable);
-        writeln!(out).unwrap();
+fn collect_snapshots(
+    sorted: &[&AccountingEntry],
+    snapshot_subvols: &HashMap<u32, u32>,
+    model: &mut UsageModel,
+) {
+    for entry in sorted {
+        if let DiskAccountingPos::Snapshot { id } = &entry.pos {
+            model.snapshots.push(SnapshotRow {
+                snapshot: *id,
+                subvol: snapshot_subvols.get(id).copied(),
+                sectors: entry.counters.first().copied().unwrap_or(0),
+            });
+        }
     }
-    if reserved > 0 {
-        write!(out, "reserved: ").unwrap();
-        fmt_size(out, reserved, human_readable);
-        writeln!(out).unwrap();
+}
+
+fn collect_rebalance_reconcile(sorted: &[&AccountingEntry], model: &mut UsageModel) {
+    for entry in sorted {
+        match &entry.pos {
+            DiskAccountingPos::RebalanceWork => {
+                let sectors = entry.counters.first().copied().unwrap_or(0);
+                model.rebalance_work = Some(model.rebalance_work.unwrap_or(0) + sectors);
+            }
+            DiskAccountingPos::ReconcileWork { work_type } => {
+                model.reconcile_work.push(ReconcileRow {
+                    work_type: accounting::reconcile_type_str(*work_type),
+                    data_sectors: entry.counters.first().copied().unwrap_or(0),
+                    metadata_sectors: entry.counters.get(1).copied().unwrap_or(0),
+                });
+            }
+            _ => {}
+        }
     }
 }
 
@@ -468,13 +823,12 @@ struct FsUsageHeader {
     pad: u32,
 }
 
-fn fs_usage_v0_to_text(
-    out: &mut String,
+fn collect_usage_v0(
     handle: &BcachefsHandle,
     devs: &[DevInfo],
+    degraded_devs: &HashSet<u32>,
     fields: u32,
-    human_readable: bool,
-) -> Result<()> {
+) -> Result<UsageModel> {
     let hdr_size = std::mem::size_of::<FsUsageHeader>();
     let mut replica_entries_bytes: u32 = 4096;
 
@@ -503,59 +857,85 @@ fn fs_usage_v0_to_text(
 
     let hdr = unsafe { &*(buf.as_ptr() as *const FsUsageHeader) };
 
-    writeln!(out, "Filesystem: {}", fmt_uuid(&handle.uuid())).unwrap();
-    write!(out, "Size:                ").unwrap();
-    fmt_size(out, hdr.capacity, human_readable);
-    writeln!(out).unwrap();
-    write!(out, "Used:                ").unwrap();
-    fmt_size(out, hdr.used, human_readable);
-    writeln!(out).unwrap();
-    write!(out, "Online reserved:     ").unwrap();
-    fmt_size(out, hdr.online_reserved, human_readable);
-    writeln!(out).unwrap();
-    writeln!(out).unwrap();
+    let mut model = UsageModel {
+        filesystem: fmt_uuid(&handle.uuid()),
+        capacity: hdr.capacity,
+        used: hdr.used,
+        online_reserved: hdr.online_reserved,
+        ..Default::default()
+    };
 
-    if fields & FIELD_REPLICAS != 0 {
-        writeln!(out, "{:<16}{:<16}{:<14}{:<14}",
-            "Data type", "Required/total", "Durability", "Devices").unwrap();
+    for (i, &sectors) in hdr.persistent_reserved.iter().enumerate() {
+        if sectors == 0 { continue; }
+        model.reserved += sectors;
+        if fields & FIELD_REPLICAS != 0 {
+            model.replicas.push(ReplicasRow {
+                data_type: "reserved".to_string(),
+                nr_required: 1,
+                nr_devs: i as u8,
+                durability: 0,
+                devices: Vec::new(),
+                sectors,
+            });
+        }
+    }
 
-        for i in 0..4 {
-            let sectors = hdr.persistent_reserved[i] as i64;
-            if sectors == 0 { continue; }
-            write!(out, "reserved:       1/{:<13}", i).unwrap();
-            write!(out, "[] ").unwrap();
-            fmt_size(out, sectors as u64, human_readable);
-            writeln!(out).unwrap();
+    // Parse variable-length replicas entries
+    let entries_data = &buf[hdr_size..hdr_size + hdr.replica_entries_bytes as usize];
+    let replica_entries = parse_replica_entries(entries_data);
+
+    let mut matrix: HashMap<(u32, u32), u64> = HashMap::new();
+    let mut replica_row = |r: &ReplicaEntry| -> ReplicasRow {
+        let dur = replicas_durability(r.data_type, r.nr_devs, r.nr_required, &r.devs, devs, degraded_devs);
+        ReplicasRow {
+            data_type: accounting::data_type_str(r.data_type),
+            nr_required: r.nr_required,
+            nr_devs: r.nr_devs,
+            durability: dur.durability,
+            devices: r.devs.iter().map(|&d| dev_name(devs, d)).collect(),
+            sectors: r.sectors as u64,
         }
+    };
 
-        // Parse variable-length replicas entries
-        let entries_data = &buf[hdr_size..hdr_size + hdr.replica_entries_bytes as usize];
-        let replica_entries = parse_replica_entries(entries_data);
+    for r in &replica_entries {
+        if r.sectors == 0 { continue; }
 
-        // Print in order: metadata, user nr_required<=1, user nr_required>1, rest
-        for r in &replica_entries {
-            if r.data_type < DATA_USER {
-                print_replica_entry(out, r, devs, human_readable);
-            }
+        if r.data_type == DATA_CACHED {
+            model.cached += r.sectors as u64;
+            continue;
         }
-        for r in &replica_entries {
-            if r.data_type == DATA_USER && r.nr_required <= 1 {
-                print_replica_entry(out, r, devs, human_readable);
-            }
+
+        let dur = replicas_durability(r.data_type, r.nr_devs, r.nr_required, &r.devs, devs, degraded_devs);
+        let degraded = dur.durability - dur.minus_degraded;
+        *matrix.entry((dur.durability, degraded)).or_insert(0) += r.sectors as u64;
+    }
+
+    // Print order: metadata, user nr_required<=1, user nr_required>1, rest
+    if fields & FIELD_REPLICAS != 0 {
+        let nonzero = |r: &&ReplicaEntry| r.sectors != 0;
+        for r in replica_entries.iter().filter(nonzero).filter(|r| r.data_type < DATA_USER) {
+            model.replicas.push(replica_row(r));
         }
-        for r in &replica_entries {
-            if r.data_type == DATA_USER && r.nr_required > 1 {
-                print_replica_entry(out, r, devs, human_readable);
-            }
+        for r in replica_entries.iter().filter(nonzero)
+            .filter(|r| r.data_type == DATA_USER && r.nr_required <= 1) {
+            model.replicas.push(replica_row(r));
         }
-        for r in &replica_entries {
-            if r.data_type > DATA_USER {
-                print_replica_entry(out, r, devs, human_readable);
-            }
+        for r in replica_entries.iter().filter(nonzero)
+            .filter(|r| r.data_type == DATA_USER && r.nr_required > 1) {
+            model.replicas.push(replica_row(r));
+        }
+        for r in replica_entries.iter().filter(nonzero).filter(|r| r.data_type > DATA_USER) {
+            model.replicas.push(replica_row(r));
         }
     }
 
-    Ok(())
+    let mut rows: Vec<DurabilityRow> = matrix.into_iter()
+        .map(|((durability, degraded), sectors)| DurabilityRow { durability, degraded, sectors })
+        .collect();
+    rows.sort_by(|a, b| a.durability.cmp(&b.durability).then(a.degraded.cmp(&b.degraded)));
+    model.durability = rows;
+
+    Ok(model)
 }
 
 struct ReplicaEntry {
@@ -590,40 +970,14 @@ fn parse_replica_entries(data: &[u8]) -> Vec<ReplicaEntry> {
     entries
 }
 
-fn print_replica_entry(out: &mut String, r: &ReplicaEntry, devs: &[DevInfo], human_readable: bool) {
-    if r.sectors == 0 { return; }
-
-    let dur = replicas_durability(r.data_type, r.nr_devs, r.nr_required, &r.devs, devs);
-
-    write!(out, "{:<16}", format!("{}:", accounting::data_type_str(r.data_type))).unwrap();
-    write!(out, "{:<16}", format!("{}/{}", r.nr_required, r.nr_devs)).unwrap();
-    write!(out, "{:<14}", dur.durability).unwrap();
-
-    write!(out, "[").unwrap();
-    for (i, &dev_idx) in r.devs.iter().enumerate() {
-        if i > 0 { write!(out, " ").unwrap(); }
-        if dev_idx == SB_MEMBER_INVALID {
-            write!(out, "none").unwrap();
-        } else if let Some(d) = devs.iter().find(|d| d.idx == dev_idx as u32) {
-            write!(out, "{}", d.dev).unwrap();
-        } else {
-            write!(out, "{}", dev_idx).unwrap();
-        }
-    }
-    write!(out, "] ").unwrap();
-    fmt_size(out, r.sectors as u64, human_readable);
-    writeln!(out).unwrap();
-}
-
 // ──────────────────────────── Device usage ───────────────────────────────────
 
-fn devs_usage_to_text(
-    out: &mut String,
+fn collect_device_usage(
+    path: &str,
     handle: &BcachefsHandle,
     devs: &[DevInfo],
-    fields: u32,
-    human_readable: bool,
-) -> Result<()> {
+    compression_ratio: f64,
+) -> Result<Vec<DeviceUsageRow>> {
     // Query dev_leaving accounting if available
     let dev_leaving_map = match handle.query_accounting(1 << 10) {
         Ok(result) => result.entries,
@@ -656,127 +1010,1008 @@ fn devs_usage_to_text(
             .then(a.info.idx.cmp(&b.info.idx))
     });
 
-    let has_leaving = dev_ctxs.iter().any(|d| d.leaving != 0);
+    // Scan BTREE_ID_alloc for real per-bucket fill. Best-effort: if the
+    // offline open fails (e.g. no permission to read the backing devices
+    // directly), dev_context_to_row falls back to its per-data-type
+    // approximation instead of failing the whole `usage` query over it.
+    let bucket_sizes: HashMap<u32, u64> = dev_ctxs
+        .iter()
+        .map(|d| (d.info.idx, d.usage.bucket_size as u64))
+        .collect();
+    let real_histograms = scan_bucket_histograms(path, &bucket_sizes).unwrap_or_default();
+
+    Ok(dev_ctxs
+        .iter()
+        .map(|d| dev_context_to_row(d, compression_ratio, real_histograms.get(&d.info.idx)))
+        .collect())
+}
 
-    writeln!(out).unwrap();
+/// Data types compression actually applies to; everything else (metadata,
+/// reserved space, free/discard buckets) is reported at ratio 1.0.
+fn data_type_is_compressible(t: u8) -> bool {
+    matches!(t, DATA_USER | DATA_CACHED)
+}
 
-    if fields & FIELD_DEVICES != 0 {
-        // Full per-device breakdown
-        for d in &dev_ctxs {
-            dev_usage_full_to_text(out, d, human_readable);
-        }
+fn dev_context_to_row(
+    d: &DevContext,
+    compression_ratio: f64,
+    real_histogram: Option<&Vec<u64>>,
+) -> DeviceUsageRow {
+    let u = &d.usage;
+    let capacity = u.nr_buckets * u.bucket_size as u64;
+    let mut used: u64 = 0;
+    let mut reclaimable: u64 = 0;
+    let mut data_types = Vec::new();
+    let mut bucket_histogram = vec![0u64; BUCKET_HISTOGRAM_BINS];
+
+    for (i, dt) in u.data_types.iter().enumerate() {
+        let i = i as u8;
+        if i != DATA_UNSTRIPED {
+            used += dt.sectors;
+        }
+
+        let sectors = if data_type_is_empty(i) {
+            dt.buckets * u.bucket_size as u64
+        } else {
+            dt.sectors
+        };
+
+        if !data_type_is_empty(i) {
+            reclaimable += dt.fragmented;
+        }
+
+        if real_histogram.is_none() && dt.buckets > 0 {
+            let fill = dt.sectors as f64 / (dt.buckets as f64 * u.bucket_size as f64);
+            let bin = fill_to_bin(fill);
+            bucket_histogram[bin] += dt.buckets;
+        }
+
+        let ratio = if data_type_is_compressible(i) { compression_ratio } else { 1.0 };
+
+        data_types.push(DeviceDataTypeRow {
+            data_type: accounting::data_type_str(i),
+            sectors,
+            buckets: dt.buckets,
+            fragmented: dt.fragmented,
+            sectors_uncompressed: (sectors as f64 * ratio) as u64,
+            compression_ratio: ratio,
+        });
+    }
+
+    let use_percent = if capacity > 0 { used * 100 / capacity } else { 0 };
+    let bucket_histogram = real_histogram.cloned().unwrap_or(bucket_histogram);
+
+    DeviceUsageRow {
+        idx: d.info.idx,
+        dev: d.info.dev.clone(),
+        label: d.info.label.clone(),
+        state: accounting::member_state_str(u.state),
+        capacity,
+        used,
+        use_percent,
+        bucket_size: u.bucket_size as u64,
+        nr_buckets: u.nr_buckets,
+        leaving: d.leaving,
+        reclaimable,
+        data_types,
+        bucket_histogram,
+    }
+}
+
+fn dev_leaving_sectors(entries: &[AccountingEntry], dev_idx: u32) -> u64 {
+    for entry in entries {
+        if let DiskAccountingPos::DevLeaving { dev } = &entry.pos {
+            if *dev == dev_idx {
+                return entry.counters.first().copied().unwrap_or(0);
+            }
+        }
+    }
+    0
+}
+
+// ──────────────────────────── Diff mode (--save / --diff) ───────────────────
+
+#[derive(Serialize, Debug)]
+struct DurabilityDelta {
+    durability: u32,
+    degraded: u32,
+    sectors: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct ReplicasDelta {
+    data_type: String,
+    nr_required: u8,
+    nr_devs: u8,
+    devices: Vec<String>,
+    sectors: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct CompressionDelta {
+    compression_type: String,
+    nr_extents: i64,
+    sectors_compressed: i64,
+    sectors_uncompressed: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct BtreeDelta {
+    btree: String,
+    sectors: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct SnapshotDelta {
+    snapshot: u32,
+    sectors: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct ReconcileDelta {
+    work_type: String,
+    data_sectors: i64,
+    metadata_sectors: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct DeviceDelta {
+    idx: u32,
+    dev: String,
+    leaving: i64,
+    used: i64,
+}
+
+/// Everything that changed between two `UsageModel` snapshots, matched by
+/// each row's `DiskAccountingPos` key (durability×degraded, replicas tuple,
+/// compression type, btree id, snapshot id, reconcile work_type, device
+/// idx) rather than position, since accounting entries aren't guaranteed to
+/// come back in the same order across two separate queries.
+#[derive(Serialize, Debug)]
+struct UsageDelta {
+    capacity: i64,
+    used: i64,
+    online_reserved: i64,
+    cached: i64,
+    reserved: i64,
+    durability: Vec<DurabilityDelta>,
+    replicas: Vec<ReplicasDelta>,
+    compression: Vec<CompressionDelta>,
+    btree: Vec<BtreeDelta>,
+    snapshots: Vec<SnapshotDelta>,
+    rebalance_work: i64,
+    reconcile_work: Vec<ReconcileDelta>,
+    devices: Vec<DeviceDelta>,
+}
+
+fn diff_usage(prev: &UsageModel, cur: &UsageModel) -> UsageDelta {
+    let mut durability: HashMap<(u32, u32), i64> = HashMap::new();
+    for r in &prev.durability {
+        *durability.entry((r.durability, r.degraded)).or_insert(0) -= r.sectors as i64;
+    }
+    for r in &cur.durability {
+        *durability.entry((r.durability, r.degraded)).or_insert(0) += r.sectors as i64;
+    }
+    let mut durability: Vec<DurabilityDelta> = durability.into_iter()
+        .filter(|&(_, sectors)| sectors != 0)
+        .map(|((durability, degraded), sectors)| DurabilityDelta { durability, degraded, sectors })
+        .collect();
+    durability.sort_by(|a, b| a.durability.cmp(&b.durability).then(a.degraded.cmp(&b.degraded)));
+
+    let replicas_key = |r: &ReplicasRow| (r.data_type.clone(), r.nr_required, r.nr_devs, r.devices.join(","));
+    let mut replicas: HashMap<(String, u8, u8, String), i64> = HashMap::new();
+    for r in &prev.replicas {
+        *replicas.entry(replicas_key(r)).or_insert(0) -= r.sectors as i64;
+    }
+    for r in &cur.replicas {
+        *replicas.entry(replicas_key(r)).or_insert(0) += r.sectors as i64;
+    }
+    let replicas: Vec<ReplicasDelta> = replicas.into_iter()
+        .filter(|&(_, sectors)| sectors != 0)
+        .map(|((data_type, nr_required, nr_devs, devices), sectors)| ReplicasDelta {
+            data_type, nr_required, nr_devs,
+            devices: if devices.is_empty() { Vec::new() } else { devices.split(',').map(str::to_string).collect() },
+            sectors,
+        })
+        .collect();
+
+    let mut compression: HashMap<String, (i64, i64, i64)> = HashMap::new();
+    for r in &prev.compression {
+        let e = compression.entry(r.compression_type.clone()).or_insert((0, 0, 0));
+        e.0 -= r.nr_extents as i64;
+        e.1 -= r.sectors_compressed as i64;
+        e.2 -= r.sectors_uncompressed as i64;
+    }
+    for r in &cur.compression {
+        let e = compression.entry(r.compression_type.clone()).or_insert((0, 0, 0));
+        e.0 += r.nr_extents as i64;
+        e.1 += r.sectors_compressed as i64;
+        e.2 += r.sectors_uncompressed as i64;
+    }
+    let compression: Vec<CompressionDelta> = compression.into_iter()
+        .filter(|&(_, (a, b, c))| a != 0 || b != 0 || c != 0)
+        .map(|(compression_type, (nr_extents, sectors_compressed, sectors_uncompressed))| CompressionDelta {
+            compression_type, nr_extents, sectors_compressed, sectors_uncompressed,
+        })
+        .collect();
+
+    let mut btree: HashMap<String, i64> = HashMap::new();
+    for r in &prev.btree {
+        *btree.entry(r.btree.clone()).or_insert(0) -= r.sectors as i64;
+    }
+    for r in &cur.btree {
+        *btree.entry(r.btree.clone()).or_insert(0) += r.sectors as i64;
+    }
+    let btree: Vec<BtreeDelta> = btree.into_iter()
+        .filter(|&(_, sectors)| sectors != 0)
+        .map(|(btree, sectors)| BtreeDelta { btree, sectors })
+        .collect();
+
+    let mut snapshots: HashMap<u32, i64> = HashMap::new();
+    for r in &prev.snapshots {
+        *snapshots.entry(r.snapshot).or_insert(0) -= r.sectors as i64;
+    }
+    for r in &cur.snapshots {
+        *snapshots.entry(r.snapshot).or_insert(0) += r.sectors as i64;
+    }
+    let mut snapshots: Vec<SnapshotDelta> = snapshots.into_iter()
+        .filter(|&(_, sectors)| sectors != 0)
+        .map(|(snapshot, sectors)| SnapshotDelta { snapshot, sectors })
+        .collect();
+    snapshots.sort_by_key(|r| r.snapshot);
+
+    let rebalance_work =
+        cur.rebalance_work.unwrap_or(0) as i64 - prev.rebalance_work.unwrap_or(0) as i64;
+
+    let mut reconcile_work: HashMap<String, (i64, i64)> = HashMap::new();
+    for r in &prev.reconcile_work {
+        let e = reconcile_work.entry(r.work_type.clone()).or_insert((0, 0));
+        e.0 -= r.data_sectors as i64;
+        e.1 -= r.metadata_sectors as i64;
+    }
+    for r in &cur.reconcile_work {
+        let e = reconcile_work.entry(r.work_type.clone()).or_insert((0, 0));
+        e.0 += r.data_sectors as i64;
+        e.1 += r.metadata_sectors as i64;
+    }
+    let reconcile_work: Vec<ReconcileDelta> = reconcile_work.into_iter()
+        .filter(|&(_, (a, b))| a != 0 || b != 0)
+        .map(|(work_type, (data_sectors, metadata_sectors))| ReconcileDelta {
+            work_type, data_sectors, metadata_sectors,
+        })
+        .collect();
+
+    let mut devices: HashMap<u32, (String, i64, i64)> = HashMap::new();
+    for d in &prev.devices {
+        let e = devices.entry(d.idx).or_insert((d.dev.clone(), 0, 0));
+        e.1 -= d.leaving as i64;
+        e.2 -= d.used as i64;
+    }
+    for d in &cur.devices {
+        let e = devices.entry(d.idx).or_insert((d.dev.clone(), 0, 0));
+        e.0 = d.dev.clone();
+        e.1 += d.leaving as i64;
+        e.2 += d.used as i64;
+    }
+    let mut devices: Vec<DeviceDelta> = devices.into_iter()
+        .filter(|&(_, (_, leaving, used))| leaving != 0 || used != 0)
+        .map(|(idx, (dev, leaving, used))| DeviceDelta { idx, dev, leaving, used })
+        .collect();
+    devices.sort_by_key(|d| d.idx);
+
+    UsageDelta {
+        capacity: cur.capacity as i64 - prev.capacity as i64,
+        used: cur.used as i64 - prev.used as i64,
+        online_reserved: cur.online_reserved as i64 - prev.online_reserved as i64,
+        cached: cur.cached as i64 - prev.cached as i64,
+        reserved: cur.reserved as i64 - prev.reserved as i64,
+        durability,
+        replicas,
+        compression,
+        btree,
+        snapshots,
+        rebalance_work,
+        reconcile_work,
+        devices,
+    }
+}
+
+fn fmt_size_signed(out: &mut String, sectors: i64, units: Units) {
+    if sectors < 0 {
+        write!(out, "-").unwrap();
     } else {
-        // Summary table
-        write!(out, "{:<32}{:<12}{:<8}{:>10}{:>10}{:>6}",
-            "Device label", "Device", "State", "Size", "Used", "Use%").unwrap();
-        if has_leaving {
-            write!(out, "{:>10}", "Leaving").unwrap();
+        write!(out, "+").unwrap();
+    }
+    fmt_size(out, sectors.unsigned_abs(), units);
+}
+
+fn render_diff_text(delta: &UsageDelta, units: Units) -> String {
+    let mut out = String::new();
+
+    write!(out, "Size:                ").unwrap();
+    fmt_size_signed(&mut out, delta.capacity, units);
+    writeln!(out).unwrap();
+    write!(out, "Used:                ").unwrap();
+    fmt_size_signed(&mut out, delta.used, units);
+    writeln!(out).unwrap();
+    write!(out, "Online reserved:     ").unwrap();
+    fmt_size_signed(&mut out, delta.online_reserved, units);
+    writeln!(out).unwrap();
+    if delta.cached != 0 {
+        write!(out, "cached:              ").unwrap();
+        fmt_size_signed(&mut out, delta.cached, units);
+        writeln!(out).unwrap();
+    }
+    if delta.reserved != 0 {
+        write!(out, "reserved:            ").unwrap();
+        fmt_size_signed(&mut out, delta.reserved, units);
+        writeln!(out).unwrap();
+    }
+
+    if !delta.durability.is_empty() {
+        writeln!(out, "\nData by durability desired and amount degraded:").unwrap();
+        for r in &delta.durability {
+            write!(out, "{}x -{}x: ", r.durability, r.degraded).unwrap();
+            fmt_size_signed(&mut out, r.sectors, units);
+            writeln!(out).unwrap();
+        }
+    }
+
+    if !delta.replicas.is_empty() {
+        writeln!(out, "\n{:<16}{:<16}{:<14}", "Data type", "Required/total", "Devices").unwrap();
+        for r in &delta.replicas {
+            write!(out, "{:<16}", format!("{}:", r.data_type)).unwrap();
+            write!(out, "{:<16}", format!("{}/{}", r.nr_required, r.nr_devs)).unwrap();
+            write!(out, "[{}] ", r.devices.join(" ")).unwrap();
+            fmt_size_signed(&mut out, r.sectors, units);
+            writeln!(out).unwrap();
+        }
+    }
+
+    if !delta.compression.is_empty() {
+        writeln!(out, "\nCompression:").unwrap();
+        for r in &delta.compression {
+            write!(out, "{:<16}extents ", format!("{}:", r.compression_type)).unwrap();
+            write!(out, "{:+}, compressed ", r.nr_extents).unwrap();
+            fmt_size_signed(&mut out, r.sectors_compressed, units);
+            write!(out, ", uncompressed ").unwrap();
+            fmt_size_signed(&mut out, r.sectors_uncompressed, units);
+            writeln!(out).unwrap();
+        }
+    }
+
+    if !delta.btree.is_empty() {
+        writeln!(out, "\nBtree usage:").unwrap();
+        for r in &delta.btree {
+            write!(out, "{:<12} ", format!("{}:", r.btree)).unwrap();
+            fmt_size_signed(&mut out, r.sectors, units);
+            writeln!(out).unwrap();
+        }
+    }
+
+    if !delta.snapshots.is_empty() {
+        writeln!(out, "\n{:<12}{:>12}", "Snapshot", "Used").unwrap();
+        for r in &delta.snapshots {
+            write!(out, "{:<12}", format!("{}:", r.snapshot)).unwrap();
+            fmt_size_signed(&mut out, r.sectors, units);
+            writeln!(out).unwrap();
         }
+    }
+
+    if delta.rebalance_work != 0 {
+        writeln!(out, "\nPending rebalance work:").unwrap();
+        fmt_size_signed(&mut out, delta.rebalance_work, units);
         writeln!(out).unwrap();
+    }
 
-        for d in &dev_ctxs {
-            let u = &d.usage;
-            let capacity = u.nr_buckets * u.bucket_size as u64;
-            let mut used: u64 = 0;
-            for (i, dt) in u.data_types.iter().enumerate() {
-                if i as u8 != DATA_UNSTRIPED {
-                    used += dt.sectors;
+    if !delta.reconcile_work.is_empty() {
+        writeln!(out, "\n{:<32}{:>12}{:>12}", "Pending reconcile:", "data", "metadata").unwrap();
+        for r in &delta.reconcile_work {
+            write!(out, "{}:", r.work_type).unwrap();
+            let pad = 32usize.saturating_sub(r.work_type.len() + 1);
+            write!(out, "{:width$}", "", width = pad).unwrap();
+            let mut s = String::new();
+            fmt_size_signed(&mut s, r.data_sectors, units);
+            write!(out, "{:>12}", s).unwrap();
+            s.clear();
+            fmt_size_signed(&mut s, r.metadata_sectors, units);
+            write!(out, "{:>12}", s).unwrap();
+            writeln!(out).unwrap();
+        }
+    }
+
+    if !delta.devices.is_empty() {
+        writeln!(out, "\n{:<12}{:<12}{:>12}{:>12}", "Device", "", "Used", "Leaving").unwrap();
+        for d in &delta.devices {
+            write!(out, "{:<12}", format!("{} (device {}):", d.dev, d.idx)).unwrap();
+            write!(out, "{:<12}", "").unwrap();
+            let mut s = String::new();
+            fmt_size_signed(&mut s, d.used, units);
+            write!(out, "{:>12}", s).unwrap();
+            s.clear();
+            fmt_size_signed(&mut s, d.leaving, units);
+            write!(out, "{:>12}", s).unwrap();
+            writeln!(out).unwrap();
+        }
+    }
+
+    out
+}
+
+// ──────────────────────────── Watch mode (--watch) ───────────────────────────
+
+/// Exponential moving average of the drain rate (sectors/sec) for each
+/// pending reconcile work type and each evacuating device, tracked across
+/// ticks of `--watch` so the displayed rate and ETA smooth out noisy
+/// per-tick samples instead of jumping around.
+struct DrainState {
+    reconcile_ema: HashMap<String, (f64, f64)>,
+    device_ema: HashMap<u32, f64>,
+}
+
+impl DrainState {
+    fn new() -> Self {
+        Self { reconcile_ema: HashMap::new(), device_ema: HashMap::new() }
+    }
+
+    /// Fold in one tick's samples: `sample = (prev - cur) / elapsed_secs`,
+    /// `ema = 0.3 * sample + 0.7 * ema`. A device that's finished evacuating
+    /// (`leaving == 0`) drops out so a later evacuation starts from a fresh
+    /// average rather than the previous one's tail.
+    fn update(&mut self, prev: &UsageModel, cur: &UsageModel, elapsed_secs: f64) {
+        for r in &cur.reconcile_work {
+            let (prev_data, prev_meta) = prev.reconcile_work.iter()
+                .find(|p| p.work_type == r.work_type)
+                .map(|p| (p.data_sectors, p.metadata_sectors))
+                .unwrap_or((r.data_sectors, r.metadata_sectors));
+
+            let sample_data = (prev_data as f64 - r.data_sectors as f64) / elapsed_secs;
+            let sample_meta = (prev_meta as f64 - r.metadata_sectors as f64) / elapsed_secs;
+
+            let ema = self.reconcile_ema.entry(r.work_type.clone())
+                .or_insert((sample_data, sample_meta));
+            ema.0 = 0.3 * sample_data + 0.7 * ema.0;
+            ema.1 = 0.3 * sample_meta + 0.7 * ema.1;
+        }
+
+        for d in &cur.devices {
+            if d.leaving == 0 {
+                self.device_ema.remove(&d.idx);
+                continue;
+            }
+
+            let prev_leaving = prev.devices.iter()
+                .find(|p| p.idx == d.idx)
+                .map(|p| p.leaving)
+                .unwrap_or(d.leaving);
+
+            let sample = (prev_leaving as f64 - d.leaving as f64) / elapsed_secs;
+            let ema = self.device_ema.entry(d.idx).or_insert(sample);
+            *ema = 0.3 * sample + 0.7 * *ema;
+        }
+    }
+}
+
+/// "done" once nothing's left, "stalled"/"∞" when the rate isn't positive,
+/// otherwise `remaining / rate` formatted as a duration.
+fn fmt_eta(remaining: u64, rate: f64) -> String {
+    if remaining == 0 {
+        "done".to_string()
+    } else if rate == 0.0 {
+        "stalled".to_string()
+    } else if rate < 0.0 {
+        "\u{221e}".to_string()
+    } else {
+        fmt_duration_secs(remaining as f64 / rate)
+    }
+}
+
+fn fmt_duration_secs(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    let h = secs / 3600;
+    let m = (secs % 3600) / 60;
+    let s = secs % 60;
+
+    if h > 0 {
+        format!("{}h{:02}m", h, m)
+    } else if m > 0 {
+        format!("{}m{:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+fn render_drain_text(model: &UsageModel, drain: &DrainState) -> String {
+    let mut out = String::new();
+
+    if !model.reconcile_work.is_empty() {
+        writeln!(out, "\n{:<24}{:>12}{:>10}{:>12}{:>10}",
+            "Reconcile drain rate:", "data/s", "ETA", "meta/s", "ETA").unwrap();
+        for r in &model.reconcile_work {
+            let (data_rate, meta_rate) = drain.reconcile_ema.get(&r.work_type)
+                .copied().unwrap_or((0.0, 0.0));
+            write!(out, "{:<24}", format!("{}:", r.work_type)).unwrap();
+            write!(out, "{:>12.0}", data_rate).unwrap();
+            write!(out, "{:>10}", fmt_eta(r.data_sectors, data_rate)).unwrap();
+            write!(out, "{:>12.0}", meta_rate).unwrap();
+            write!(out, "{:>10}", fmt_eta(r.metadata_sectors, meta_rate)).unwrap();
+            writeln!(out).unwrap();
+        }
+    }
+
+    let evacuating: Vec<_> = model.devices.iter().filter(|d| d.leaving != 0).collect();
+    if !evacuating.is_empty() {
+        writeln!(out, "\n{:<24}{:>12}{:>10}", "Device evacuation rate:", "sectors/s", "ETA").unwrap();
+        for d in evacuating {
+            let rate = drain.device_ema.get(&d.idx).copied().unwrap_or(0.0);
+            write!(out, "{:<24}", format!("{} (device {}):", d.dev, d.idx)).unwrap();
+            write!(out, "{:>12.0}", rate).unwrap();
+            write!(out, "{:>10}", fmt_eta(d.leaving, rate)).unwrap();
+            writeln!(out).unwrap();
+        }
+    }
+
+    out
+}
+
+/// Re-render `fs usage` every `interval_secs`, tracking the drain rate of
+/// pending reconcile work and device evacuation via `DrainState`. Terminal
+/// handling mirrors `fs top`'s interactive loop: raw mode + alternate
+/// screen while running, restored on every exit path including Ctrl-C/q.
+fn run_watch(path: &str, fields: u32, interval_secs: u64, units: Units) -> Result<()> {
+    let mut prev: Option<UsageModel> = None;
+    let mut drain = DrainState::new();
+    let mut stdout = io::stdout();
+
+    terminal::enable_raw_mode()?;
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+    let result = (|| -> Result<()> {
+        loop {
+            let cur = collect_usage(path, fields)?;
+
+            if let Some(prev_model) = &prev {
+                drain.update(prev_model, &cur, interval_secs as f64);
+            }
+
+            execute!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+            for line in render_text(&cur, fields, units).lines() {
+                write!(stdout, "{}\r\n", line)?;
+            }
+            for line in render_drain_text(&cur, &drain).lines() {
+                write!(stdout, "{}\r\n", line)?;
+            }
+            write!(stdout, "\r\nq:quit\r\n")?;
+            stdout.flush()?;
+
+            prev = Some(cur);
+
+            if event::poll(Duration::from_secs(interval_secs))? {
+                if let Event::Key(key) = event::read()? {
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                        _ => {}
+                    }
+                }
+                while event::poll(Duration::ZERO)? {
+                    let _ = event::read()?;
                 }
             }
+        }
+    })();
 
-            let label = d.info.label.as_deref().unwrap_or("(no label)");
-            let state = accounting::member_state_str(u.state);
+    let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+    result
+}
 
-            write!(out, "{:<32}", format!("{} (device {}):", label, d.info.idx)).unwrap();
-            write!(out, "{:<12}", d.info.dev).unwrap();
-            write!(out, "{:<8}", state).unwrap();
+// ──────────────────────────── Text rendering ─────────────────────────────────
 
-            let mut s = String::new();
-            fmt_size(&mut s, capacity, human_readable);
-            write!(out, "{:>10}", s).unwrap();
-            s.clear();
-            fmt_size(&mut s, used, human_readable);
-            write!(out, "{:>10}", s).unwrap();
+fn render_text(model: &UsageModel, fields: u32, units: Units) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "Filesystem: {}", model.filesystem).unwrap();
+    write!(out, "Size:                ").unwrap();
+    fmt_size(&mut out, model.capacity, units);
+    writeln!(out).unwrap();
+    write!(out, "Used:                ").unwrap();
+    fmt_size(&mut out, model.used, units);
+    writeln!(out).unwrap();
+    write!(out, "Online reserved:     ").unwrap();
+    fmt_size(&mut out, model.online_reserved, units);
+    writeln!(out).unwrap();
 
-            let pct = if capacity > 0 { used * 100 / capacity } else { 0 };
-            write!(out, "{:>5}%", pct).unwrap();
+    render_durability_text(&mut out, model, units);
+
+    if fields & FIELD_REPLICAS != 0 {
+        render_replicas_text(&mut out, model, units);
+    }
+
+    render_compression_text(&mut out, model, units);
+    render_btree_text(&mut out, model, units);
+    render_snapshots_text(&mut out, model, units);
+    render_rebalance_reconcile_text(&mut out, model, units);
+    render_reclaimable_text(&mut out, model, units);
+    render_devices_text(&mut out, model, fields, units);
+
+    out
+}
+
+/// Whole-filesystem total of each device's `reclaimable` (fragmented,
+/// non-empty data), as a fraction of total capacity. Gives operators a
+/// single number for "is running copygc/compaction worthwhile right now".
+fn render_reclaimable_text(out: &mut String, model: &UsageModel, units: Units) {
+    let reclaimable: u64 = model.devices.iter().map(|d| d.reclaimable).sum();
+    if reclaimable == 0 {
+        return;
+    }
+
+    let capacity: u64 = model.devices.iter().map(|d| d.capacity).sum();
+    let percent = if capacity > 0 { reclaimable as f64 * 100.0 / capacity as f64 } else { 0.0 };
+
+    write!(out, "\nReclaimable (fragmented, all devices): ").unwrap();
+    fmt_size(out, reclaimable, units);
+    writeln!(out, " ({:.1}% of capacity)", percent).unwrap();
+}
 
-            if d.leaving > 0 {
-                s.clear();
-                fmt_size(&mut s, d.leaving, human_readable);
-                write!(out, "{:>10}", s).unwrap();
+fn render_durability_text(out: &mut String, model: &UsageModel, units: Units) {
+    writeln!(out, "\nData by durability desired and amount degraded:").unwrap();
+
+    let max_degraded = model.durability.iter().map(|r| r.degraded + 1).max().unwrap_or(0) as usize;
+
+    if max_degraded > 0 {
+        let max_durability = model.durability.iter().map(|r| r.durability).max().unwrap_or(0);
+
+        write!(out, "        ").unwrap();
+        for i in 0..max_degraded {
+            if i == 0 {
+                write!(out, "{:>12}", "undegraded").unwrap();
+            } else {
+                write!(out, "{:>12}", format!("-{}x", i)).unwrap();
+            }
+        }
+        writeln!(out).unwrap();
+
+        for dur in 0..=max_durability {
+            let row: Vec<u64> = (0..max_degraded as u32)
+                .map(|degraded| {
+                    model.durability.iter()
+                        .find(|r| r.durability == dur && r.degraded == degraded)
+                        .map(|r| r.sectors)
+                        .unwrap_or(0)
+                })
+                .collect();
+
+            if row.iter().all(|&v| v == 0) { continue; }
+
+            write!(out, "{}x:     ", dur).unwrap();
+            for val in row {
+                if val != 0 {
+                    let mut s = String::new();
+                    fmt_size(&mut s, val, units);
+                    write!(out, "{:>12}", s).unwrap();
+                } else {
+                    write!(out, "{:>12}", "").unwrap();
+                }
             }
             writeln!(out).unwrap();
         }
     }
 
-    Ok(())
+    if model.cached > 0 {
+        write!(out, "cached: ").unwrap();
+        fmt_size(out, model.cached, units);
+        writeln!(out).unwrap();
+    }
+    if model.reserved > 0 {
+        write!(out, "reserved: ").unwrap();
+        fmt_size(out, model.reserved, units);
+        writeln!(out).unwrap();
+    }
 }
 
-fn dev_usage_full_to_text(out: &mut String, d: &DevContext, human_readable: bool) {
-    let u = &d.usage;
-    let capacity = u.nr_buckets * u.bucket_size as u64;
-    let mut used: u64 = 0;
-    for (i, dt) in u.data_types.iter().enumerate() {
-        if i as u8 != DATA_UNSTRIPED {
-            used += dt.sectors;
+fn render_replicas_text(out: &mut String, model: &UsageModel, units: Units) {
+    writeln!(out, "\n{:<16}{:<16}{:<14}{:<14}",
+        "Data type", "Required/total", "Durability", "Devices").unwrap();
+
+    for r in &model.replicas {
+        if r.data_type == "reserved" {
+            write!(out, "reserved:       1/{:<13}", r.nr_devs).unwrap();
+            write!(out, "[] ").unwrap();
+            fmt_size(out, r.sectors, units);
+            writeln!(out).unwrap();
+            continue;
         }
+
+        write!(out, "{:<16}", format!("{}:", r.data_type)).unwrap();
+        write!(out, "{:<16}", format!("{}/{}", r.nr_required, r.nr_devs)).unwrap();
+        write!(out, "{:<14}", r.durability).unwrap();
+        write!(out, "[{}] ", r.devices.join(" ")).unwrap();
+        fmt_size(out, r.sectors, units);
+        writeln!(out).unwrap();
     }
+}
 
-    let label = d.info.label.as_deref().unwrap_or("(no label)");
-    let state = accounting::member_state_str(u.state);
-    let pct = if capacity > 0 { used * 100 / capacity } else { 0 };
+/// `nr_extents` of 0 suppresses the average-extent-size column (used for the
+/// aggregate "total" row, where an average across types isn't meaningful).
+fn write_compression_row(
+    out: &mut String,
+    label: &str,
+    nr_extents: u64,
+    sectors_uncompressed: u64,
+    sectors_compressed: u64,
+    ratio: f64,
+    units: Units,
+) {
+    write!(out, "{:<12}", label).unwrap();
+    write!(out, "{:>10}", nr_extents).unwrap();
 
-    writeln!(out, "{} (device {}):   {}   {}   {:02}%",
-        label, d.info.idx, d.info.dev, state, pct).unwrap();
+    let mut s = String::new();
+    fmt_size(&mut s, sectors_compressed, units);
+    write!(out, "{:>16}", s).unwrap();
+    s.clear();
+    fmt_size(&mut s, sectors_uncompressed, units);
+    write!(out, "{:>16}", s).unwrap();
 
-    writeln!(out, "  {:<16}{:>12}{:>12}{:>14}", "", "data", "buckets", "fragmented").unwrap();
+    s.clear();
+    if nr_extents > 0 {
+        let avg = (sectors_uncompressed << 9) / nr_extents;
+        fmt_size_bytes(&mut s, avg, units);
+    }
+    write!(out, "{:>24}", s).unwrap();
 
-    for (i, dt) in u.data_types.iter().enumerate() {
-        let type_name = accounting::data_type_str(i as u8);
-        let sectors = if data_type_is_empty(i as u8) {
-            dt.buckets * u.bucket_size as u64
-        } else {
-            dt.sectors
-        };
+    write!(out, "{:>9.2}x", ratio).unwrap();
+    writeln!(out).unwrap();
+}
+
+fn render_compression_text(out: &mut String, model: &UsageModel, units: Units) {
+    if model.compression.is_empty() {
+        return;
+    }
+
+    writeln!(out, "\nCompression:").unwrap();
+    writeln!(out, "{:<12}{:>10}{:>16}{:>16}{:>24}{:>10}",
+        "type", "extents", "compressed", "uncompressed", "average extent size", "ratio").unwrap();
+
+    let (incompressible, compressed_entries): (Vec<_>, Vec<_>) = model.compression.iter()
+        .partition(|r| r.incompressible);
+
+    let mut total_uncompressed: u64 = 0;
+    let mut total_compressed: u64 = 0;
+
+    for r in &compressed_entries {
+        total_uncompressed += r.sectors_uncompressed;
+        total_compressed += r.sectors_compressed;
+        write_compression_row(
+            out, &r.compression_type, r.nr_extents,
+            r.sectors_uncompressed, r.sectors_compressed, r.ratio,
+            units,
+        );
+    }
+
+    if compressed_entries.len() > 1 {
+        write_compression_row(
+            out, "total", 0, total_uncompressed, total_compressed,
+            compression_ratio(total_uncompressed, total_compressed),
+            units,
+        );
+    }
+
+    for r in &incompressible {
+        write_compression_row(
+            out, "incompressible", r.nr_extents,
+            r.sectors_uncompressed, r.sectors_compressed, r.ratio,
+            units,
+        );
+    }
+}
+
+fn render_btree_text(out: &mut String, model: &UsageModel, units: Units) {
+    if model.btree.is_empty() {
+        return;
+    }
+
+    writeln!(out, "\nBtree usage:").unwrap();
+    for r in &model.btree {
+        write!(out, "{:<12} ", format!("{}:", r.btree)).unwrap();
+        fmt_size(out, r.sectors, units);
+        writeln!(out).unwrap();
+    }
+}
 
-        write!(out, "  {:<16}", format!("{}:", type_name)).unwrap();
+fn render_snapshots_text(out: &mut String, model: &UsageModel, units: Units) {
+    if model.snapshots.is_empty() {
+        return;
+    }
 
+    writeln!(out, "\n{:<12}{:<12}{:>12}", "Snapshot", "Subvol", "Used").unwrap();
+    for r in &model.snapshots {
+        write!(out, "{:<12}", format!("{}:", r.snapshot)).unwrap();
+        match r.subvol {
+            Some(subvol) => write!(out, "{:<12}", subvol).unwrap(),
+            None => write!(out, "{:<12}", "-").unwrap(),
+        }
         let mut s = String::new();
-        fmt_size(&mut s, sectors, human_readable);
+        fmt_size(&mut s, r.sectors, units);
+        write!(out, "{:>12}", s).unwrap();
+        writeln!(out).unwrap();
+    }
+}
+
+fn render_rebalance_reconcile_text(out: &mut String, model: &UsageModel, units: Units) {
+    if let Some(sectors) = model.rebalance_work {
+        writeln!(out, "\nPending rebalance work:").unwrap();
+        fmt_size(out, sectors, units);
+        writeln!(out).unwrap();
+    }
+
+    if !model.reconcile_work.is_empty() {
+        writeln!(out, "\n{:<32}{:>12}{:>12}", "Pending reconcile:", "data", "metadata").unwrap();
+        for r in &model.reconcile_work {
+            write!(out, "{}:", r.work_type).unwrap();
+            let pad = 32usize.saturating_sub(r.work_type.len() + 1);
+            write!(out, "{:width$}", "", width = pad).unwrap();
+            let mut s = String::new();
+            fmt_size(&mut s, r.data_sectors, units);
+            write!(out, "{:>12}", s).unwrap();
+            s.clear();
+            fmt_size(&mut s, r.metadata_sectors, units);
+            write!(out, "{:>12}", s).unwrap();
+            writeln!(out).unwrap();
+        }
+    }
+}
+
+fn render_devices_text(out: &mut String, model: &UsageModel, fields: u32, units: Units) {
+    writeln!(out).unwrap();
+
+    if fields & FIELD_DEVICES != 0 {
+        for d in &model.devices {
+            render_device_full_text(out, d, units);
+        }
+        return;
+    }
+
+    let has_leaving = model.devices.iter().any(|d| d.leaving != 0);
+
+    write!(out, "{:<32}{:<12}{:<8}{:>10}{:>10}{:>6}",
+        "Device label", "Device", "State", "Size", "Used", "Use%").unwrap();
+    if has_leaving {
+        write!(out, "{:>10}", "Leaving").unwrap();
+    }
+    writeln!(out).unwrap();
+
+    for d in &model.devices {
+        let label = d.label.as_deref().unwrap_or("(no label)");
+
+        write!(out, "{:<32}", format!("{} (device {}):", label, d.idx)).unwrap();
+        write!(out, "{:<12}", d.dev).unwrap();
+        write!(out, "{:<8}", d.state).unwrap();
+
+        let mut s = String::new();
+        fmt_size(&mut s, d.capacity, units);
+        write!(out, "{:>10}", s).unwrap();
+        s.clear();
+        fmt_size(&mut s, d.used, units);
+        write!(out, "{:>10}", s).unwrap();
+
+        write!(out, "{:>5}%", d.use_percent).unwrap();
+
+        if d.leaving > 0 {
+            s.clear();
+            fmt_size(&mut s, d.leaving, units);
+            write!(out, "{:>10}", s).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+}
+
+fn render_device_full_text(out: &mut String, d: &DeviceUsageRow, units: Units) {
+    let label = d.label.as_deref().unwrap_or("(no label)");
+
+    writeln!(out, "{} (device {}):   {}   {}   {:02}%",
+        label, d.idx, d.dev, d.state, d.use_percent).unwrap();
+
+    writeln!(out, "  {:<16}{:>12}{:>12}{:>14}{:>14}{:>9}", "", "data", "buckets", "fragmented", "uncompressed", "ratio").unwrap();
+
+    for dt in &d.data_types {
+        write!(out, "  {:<16}", format!("{}:", dt.data_type)).unwrap();
+
+        let mut s = String::new();
+        fmt_size(&mut s, dt.sectors, units);
         write!(out, "{:>12}", s).unwrap();
 
         write!(out, "{:>12}", dt.buckets).unwrap();
 
         if dt.fragmented > 0 {
             s.clear();
-            fmt_size(&mut s, dt.fragmented, human_readable);
+            fmt_size(&mut s, dt.fragmented, units);
+            write!(out, "{:>14}", s).unwrap();
+        } else {
+            write!(out, "{:>14}", "").unwrap();
+        }
+
+        if dt.compression_ratio > 1.0 {
+            s.clear();
+            fmt_size(&mut s, dt.sectors_uncompressed, units);
             write!(out, "{:>14}", s).unwrap();
+            write!(out, "{:>8.2}x", dt.compression_ratio).unwrap();
         }
         writeln!(out).unwrap();
     }
 
     write!(out, "  {:<16}", "capacity:").unwrap();
     let mut s = String::new();
-    fmt_size(&mut s, capacity, human_readable);
+    fmt_size(&mut s, d.capacity, units);
     write!(out, "{:>12}", s).unwrap();
-    writeln!(out, "{:>12}", u.nr_buckets).unwrap();
+    writeln!(out, "{:>12}", d.nr_buckets).unwrap();
 
     write!(out, "  {:<16}", "bucket size:").unwrap();
     s.clear();
-    fmt_size(&mut s, u.bucket_size as u64, human_readable);
+    fmt_size(&mut s, d.bucket_size, units);
     writeln!(out, "{:>12}", s).unwrap();
 
+    if d.reclaimable > 0 {
+        write!(out, "  {:<16}", "reclaimable:").unwrap();
+        s.clear();
+        fmt_size(&mut s, d.reclaimable, units);
+        writeln!(out, "{:>12}", s).unwrap();
+    }
+
+    if d.leaving > 0 {
+        write!(out, "  {:<16}", "leaving:").unwrap();
+        s.clear();
+        fmt_size(&mut s, d.leaving, units);
+        writeln!(out, "{:>12}", s).unwrap();
+    }
+
+    render_bucket_histogram_text(out, d);
+
     writeln!(out).unwrap();
 }
 
-fn dev_leaving_sectors(entries: &[AccountingEntry], dev_idx: u32) -> u64 {
-    for entry in entries {
-        if let DiskAccountingPos::DevLeaving { dev } = &entry.pos {
-            if *dev == dev_idx {
-                return entry.counters.first().copied().unwrap_or(0);
-            }
+/// Bucket count by fullness (see `DeviceUsageRow::bucket_histogram`), shown
+/// as a one-line-per-bin table so operators can judge how much copygc/
+/// compaction work draining the fuller bins would take.
+fn render_bucket_histogram_text(out: &mut String, d: &DeviceUsageRow) {
+    if d.bucket_histogram.iter().all(|&n| n == 0) {
+        return;
+    }
+
+    writeln!(out, "  {:<16}{:>12}", "bucket fullness", "buckets").unwrap();
+    for (bin, &buckets) in d.bucket_histogram.iter().enumerate() {
+        if buckets == 0 {
+            continue;
         }
+        let lo = bin * 100 / BUCKET_HISTOGRAM_BINS;
+        let hi = (bin + 1) * 100 / BUCKET_HISTOGRAM_BINS;
+        write!(out, "  {:<16}", format!("{:>3}-{:<3}%:", lo, hi)).unwrap();
+        writeln!(out, "{:>12}", buckets).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_to_bin_empty_and_full() {
+        assert_eq!(fill_to_bin(0.0), 0);
+        assert_eq!(fill_to_bin(1.0), BUCKET_HISTOGRAM_BINS - 1);
+    }
+
+    #[test]
+    fn fill_to_bin_middle_values() {
+        assert_eq!(fill_to_bin(0.05), 0);
+        assert_eq!(fill_to_bin(0.35), 3);
+        assert_eq!(fill_to_bin(0.99), BUCKET_HISTOGRAM_BINS - 1);
+    }
+
+    #[test]
+    fn fill_to_bin_clamps_overfull_input() {
+        // Shouldn't happen in practice, but a bucket's sectors summing to
+        // more than its capacity must not index out of bounds.
+        assert_eq!(fill_to_bin(1.5), BUCKET_HISTOGRAM_BINS - 1);
     }
-    0
 }
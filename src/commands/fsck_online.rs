@@ -0,0 +1,61 @@
+use std::ffi::CString;
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+
+use crate::wrappers::handle::BcachefsHandle;
+
+#[derive(Parser, Debug)]
+#[command(about = "Run fsck against an already-mounted filesystem")]
+pub struct Cli {
+    /// fsck options, comma-separated (same syntax as the offline `fsck` command)
+    #[arg(short, long, default_value = "")]
+    opts: String,
+
+    /// Mounted filesystem path
+    filesystem: String,
+}
+
+/// Drive `BCH_IOCTL_FSCK_ONLINE`'s streamed log to completion: print each
+/// line as it arrives, then translate the trailing status integer into an
+/// exit code once the fd hits EOF.
+pub fn fsck_online(argv: Vec<String>) -> Result<()> {
+    let cli = Cli::parse_from(argv);
+
+    let handle = BcachefsHandle::open(&cli.filesystem)
+        .with_context(|| format!("opening filesystem '{}'", cli.filesystem))?;
+
+    let opts = CString::new(cli.opts).context("fsck options string contains a NUL byte")?;
+    let mut fd = handle.fsck_online(&opts, 0)
+        .map_err(|e| anyhow::anyhow!("BCH_IOCTL_FSCK_ONLINE failed: {}", e))?;
+
+    let mut pending = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let n = fd.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        pending.extend_from_slice(&chunk[..n]);
+
+        while let Some(pos) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            eprint!("{}", String::from_utf8_lossy(&line));
+        }
+    }
+
+    let exit_code = if pending.len() >= 4 {
+        let start = pending.len() - 4;
+        i32::from_le_bytes(pending[start..].try_into().unwrap())
+    } else {
+        0
+    };
+
+    if exit_code != 0 {
+        bail!("fsck found errors (exit code {})", exit_code);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! On-disk cache of the inode-parent index and resolved dirent names used by
+//! `inode-opts -P`, so repeat invocations don't have to rescan multi-GB
+//! `btrees/inodes/keys` and `btrees/dirents/keys` debugfs dumps just to
+//! resolve paths. Invalidated by the inode btree's size and the mtime of its
+//! debugfs keys file.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+const MAGIC: u32 = 0x62635052; // "bcPR"
+const VERSION: u32 = 1;
+
+/// Invalidation token: the inode btree's reported size plus the mtime of its
+/// debugfs keys file. A cache built under a different token is discarded.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CacheToken {
+    inode_btree_size: u64,
+    inode_keys_mtime: u64,
+}
+
+impl CacheToken {
+    pub(crate) fn current(debugfs: &Path, inode_btree_size: Option<u64>) -> Option<Self> {
+        let meta = fs::metadata(debugfs.join("btrees/inodes/keys")).ok()?;
+        let mtime = meta
+            .modified()
+            .ok()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        Some(Self { inode_btree_size: inode_btree_size.unwrap_or(0), inode_keys_mtime: mtime })
+    }
+}
+
+fn cache_path(uuid: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".cache/bcachefs").join(uuid).join("resolve.cache"))
+}
+
+/// The cached parent index (sorted by inum, as built by `ParentCache`) and
+/// resolved dirent names, loaded from or about to be written to disk.
+pub(crate) struct ResolutionCache {
+    /// Sorted by inum: (inum, bi_dir, bi_dir_offset).
+    pub parents: Vec<(u64, u64, u64)>,
+    pub dirents: HashMap<(u64, u64), String>,
+}
+
+impl ResolutionCache {
+    /// Binary-search the parent index for an inode's (bi_dir, bi_dir_offset).
+    pub(crate) fn lookup_parent(&self, inum: u64) -> Option<(u64, u64)> {
+        self.parents
+            .binary_search_by_key(&inum, |&(i, _, _)| i)
+            .ok()
+            .map(|idx| (self.parents[idx].1, self.parents[idx].2))
+    }
+
+    pub(crate) fn load(uuid: &str, token: CacheToken) -> Option<Self> {
+        let buf = fs::read(cache_path(uuid)?).ok()?;
+        Self::decode(&buf, token)
+    }
+
+    fn decode(buf: &[u8], token: CacheToken) -> Option<Self> {
+        let off = std::cell::Cell::new(0usize);
+
+        let rd_u32 = |buf: &[u8]| -> Option<u32> {
+            let v = u32::from_le_bytes(buf.get(off.get()..off.get() + 4)?.try_into().ok()?);
+            off.set(off.get() + 4);
+            Some(v)
+        };
+        if rd_u32(buf)? != MAGIC || rd_u32(buf)? != VERSION {
+            return None;
+        }
+
+        let rd_u64 = |buf: &[u8]| -> Option<u64> {
+            let v = u64::from_le_bytes(buf.get(off.get()..off.get() + 8)?.try_into().ok()?);
+            off.set(off.get() + 8);
+            Some(v)
+        };
+
+        let file_size = rd_u64(buf)?;
+        let file_mtime = rd_u64(buf)?;
+        if file_size != token.inode_btree_size || file_mtime != token.inode_keys_mtime {
+            return None;
+        }
+
+        let nr_parents = rd_u64(buf)? as usize;
+        let mut parents = Vec::with_capacity(nr_parents);
+        for _ in 0..nr_parents {
+            let inum = rd_u64(buf)?;
+            let bi_dir = rd_u64(buf)?;
+            let bi_dir_offset = rd_u64(buf)?;
+            parents.push((inum, bi_dir, bi_dir_offset));
+        }
+
+        let nr_dirents = rd_u64(buf)? as usize;
+        let mut dirents = HashMap::with_capacity(nr_dirents);
+        for _ in 0..nr_dirents {
+            let inode = rd_u64(buf)?;
+            let offset = rd_u64(buf)?;
+            let name_len = rd_u32(buf)? as usize;
+            let name_bytes = buf.get(off.get()..off.get() + name_len)?;
+            off.set(off.get() + name_len);
+            dirents.insert((inode, offset), String::from_utf8_lossy(name_bytes).into_owned());
+        }
+
+        Some(Self { parents, dirents })
+    }
+
+    /// Write the cache out, replacing any existing one for this UUID.
+    pub(crate) fn save(
+        uuid: &str,
+        token: CacheToken,
+        parents: &[(u64, u64, u64)],
+        dirents: &HashMap<(u64, u64), String>,
+    ) -> Result<()> {
+        let path = cache_path(uuid).context("could not determine cache directory (no $HOME)")?;
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MAGIC.to_le_bytes());
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&token.inode_btree_size.to_le_bytes());
+        buf.extend_from_slice(&token.inode_keys_mtime.to_le_bytes());
+
+        buf.extend_from_slice(&(parents.len() as u64).to_le_bytes());
+        for &(inum, bi_dir, bi_dir_offset) in parents {
+            buf.extend_from_slice(&inum.to_le_bytes());
+            buf.extend_from_slice(&bi_dir.to_le_bytes());
+            buf.extend_from_slice(&bi_dir_offset.to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(dirents.len() as u64).to_le_bytes());
+        for (&(inode, offset), name) in dirents {
+            buf.extend_from_slice(&inode.to_le_bytes());
+            buf.extend_from_slice(&offset.to_le_bytes());
+            let name_bytes = name.as_bytes();
+            buf.extend_from_slice(&(name_bytes.len() as u32).to_le_bytes());
+            buf.extend_from_slice(name_bytes);
+        }
+
+        // Write to a temp file first so a run killed mid-write can't leave a
+        // corrupt cache that then fails to decode on the next invocation.
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &buf)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{bail, Result};
+use anyhow::Result;
 use bch_bindgen::bcachefs;
 use bch_bindgen::btree::BtreeIter;
 use bch_bindgen::btree::BtreeIterFlags;
@@ -7,66 +7,25 @@ use bch_bindgen::c;
 use bch_bindgen::c::bch_degraded_actions;
 use bch_bindgen::fs::Fs;
 use bch_bindgen::opt_set;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{stdout, BufRead, IsTerminal, Write};
+use std::io::{stdout, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 
 use crate::logging;
+use crate::mount;
 
-/// Get bcachefs devices from a mount path by parsing /proc/self/mountinfo
-fn get_devices_from_mount(mount_path: &Path) -> Result<Vec<PathBuf>> {
-    let mount_path = mount_path.canonicalize()?;
-    let mount_str = mount_path.to_string_lossy();
-
-    let file = fs::File::open("/proc/self/mountinfo")?;
-    let reader = std::io::BufReader::new(file);
-
-    for line in reader.lines() {
-        let line = line?;
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 10 {
-            continue;
-        }
-
-        // mountinfo format: id parent major:minor root mount_point options ... - fstype source options
-        let mp = parts[4];
-        if mp != mount_str {
-            continue;
-        }
-
-        // Find the separator "-"
-        let sep_idx = parts.iter().position(|&p| p == "-");
-        if let Some(idx) = sep_idx {
-            if idx + 2 < parts.len() {
-                let fstype = parts[idx + 1];
-                if fstype != "bcachefs" {
-                    bail!("{} is not a bcachefs mount (found: {})", mount_str, fstype);
-                }
-                let source = parts[idx + 2];
-                // bcachefs source can be "dev1:dev2:dev3" for multi-device
-                let devices: Vec<PathBuf> = source
-                    .split(':')
-                    .map(PathBuf::from)
-                    .collect();
-                return Ok(devices);
-            }
-        }
-    }
-
-    bail!("mount point not found: {}", mount_str)
-}
-
-/// Check if path is a mount point or a device
-fn resolve_devices(path: &Path) -> Result<Vec<PathBuf>> {
+/// Resolve `path` to its backing device(s): a mount target is looked up in
+/// `/proc/mounts` (consistent with how the rest of the tool identifies
+/// mounted filesystems), anything else is treated as a device directly.
+pub(crate) fn resolve_devices(path: &Path) -> Result<Vec<PathBuf>> {
     let meta = fs::metadata(path)?;
 
     if meta.is_dir() {
-        // It's a directory, treat as mount point
-        get_devices_from_mount(path)
+        mount::devices_for_mount(path)
     } else {
-        // Assume it's a device
         Ok(vec![path.to_path_buf()])
     }
 }
@@ -80,7 +39,7 @@ struct InodeOpts {
 }
 
 /// Get dirent name from a bch_dirent
-fn get_dirent_name(v: &c::bch_val, k: &c::bkey) -> Option<String> {
+pub(crate) fn get_dirent_name(v: &c::bch_val, k: &c::bkey) -> Option<String> {
     unsafe {
         let dirent = v as *const c::bch_val as *const c::bch_dirent;
         let dirent_base_size = std::mem::size_of::<c::bch_dirent>();
@@ -99,7 +58,12 @@ fn get_dirent_name(v: &c::bch_val, k: &c::bkey) -> Option<String> {
     }
 }
 
-fn collect_inode_opts(fs: &Fs, dirs_only: bool, verbose: bool) -> Result<Vec<InodeOpts>> {
+/// Scan `BTREE_ID_inodes` once, returning both the matching inodes and a
+/// `(inum -> (bi_dir, bi_dir_offset))` map built from *every* inode_v3 this
+/// pass visits. Since that's the complete parent graph for the filesystem,
+/// `resolve_path` can later walk ancestor chains purely in memory instead of
+/// re-scanning the btree once per ancestor level.
+fn collect_inode_opts(fs: &Fs, dirs_only: bool, verbose: bool) -> Result<(Vec<InodeOpts>, HashMap<u64, (u64, u64)>)> {
     let trans = BtreeTrans::new(fs);
     let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
 
@@ -111,6 +75,7 @@ fn collect_inode_opts(fs: &Fs, dirs_only: bool, verbose: bool) -> Result<Vec<Ino
     );
 
     let mut matches = Vec::new();
+    let mut parent_map = HashMap::new();
     let mut last_inum: Option<u64> = None;
     let mut count = 0u64;
 
@@ -145,6 +110,10 @@ fn collect_inode_opts(fs: &Fs, dirs_only: bool, verbose: bool) -> Result<Vec<Ino
             continue;
         }
 
+        if unpacked.bi_dir != 0 {
+            parent_map.insert(inum, (unpacked.bi_dir, unpacked.bi_dir_offset));
+        }
+
         if dirs_only {
             let is_dir = (unpacked.bi_mode & 0o170000) == 0o040000;
             if !is_dir {
@@ -190,7 +159,7 @@ fn collect_inode_opts(fs: &Fs, dirs_only: bool, verbose: bool) -> Result<Vec<Ino
 
     eprintln!("\rprocessed {} inodes, {} matches", count, matches.len());
 
-    Ok(matches)
+    Ok((matches, parent_map))
 }
 
 fn collect_needed_parents(matches: &[InodeOpts]) -> HashSet<u64> {
@@ -318,6 +287,59 @@ fn build_dirent_map(fs: &Fs, needed: &HashSet<(u64, u64)>) -> Result<HashMap<(u6
     Ok(dirent_map)
 }
 
+/// Split a `bch_xattr` key's value buffer into its name and value, the same
+/// way `get_dirent_name` splits a dirent's name out of its buffer.
+fn get_xattr_kv(v: &c::bch_val) -> Option<(String, String)> {
+    unsafe {
+        let xattr = v as *const c::bch_val as *const c::bch_xattr;
+        let name_len = (*xattr).x_name_len as usize;
+        let val_len = (*xattr).x_val_len as usize;
+
+        if name_len == 0 || name_len > 255 {
+            return None;
+        }
+
+        let name_ptr = (*xattr).x_name.as_ptr();
+        let name = std::slice::from_raw_parts(name_ptr, name_len);
+        let value = std::slice::from_raw_parts(name_ptr.add(name_len), val_len);
+
+        Some((
+            String::from_utf8_lossy(name).into_owned(),
+            String::from_utf8_lossy(value).into_owned(),
+        ))
+    }
+}
+
+/// Scan `BTREE_ID_xattrs` once, collecting the `{name, value}` pairs set on
+/// each of the `needed` inums.
+fn build_xattr_map(fs: &Fs, needed: &HashSet<u64>) -> Result<HashMap<u64, Vec<(String, String)>>> {
+    let trans = BtreeTrans::new(fs);
+    let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+
+    let mut iter = BtreeIter::new(
+        &trans,
+        bcachefs::btree_id::BTREE_ID_xattrs,
+        bch_bindgen::POS_MIN,
+        flags,
+    );
+
+    let mut xattr_map: HashMap<u64, Vec<(String, String)>> = HashMap::new();
+
+    while let Some(k) = iter.peek_and_restart()? {
+        let inum = k.k.p.inode;
+
+        if needed.contains(&inum) && k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_xattr as u8 {
+            if let Some(kv) = get_xattr_kv(k.v) {
+                xattr_map.entry(inum).or_default().push(kv);
+            }
+        }
+
+        iter.advance();
+    }
+
+    Ok(xattr_map)
+}
+
 fn resolve_path(
     m: &InodeOpts,
     parent_map: &HashMap<u64, (u64, u64)>,
@@ -361,6 +383,28 @@ fn resolve_path(
     format!("/{}", parts.join("/"))
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+struct XattrJson {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct InodeOptsJson {
+    inum: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    opts: Vec<(String, u64)>,
+    xattrs: Vec<XattrJson>,
+}
+
 /// Find inodes with non-default bcachefs options
 #[derive(Parser, Debug)]
 pub struct Cli {
@@ -384,6 +428,10 @@ pub struct Cli {
     #[arg(short, long, action = clap::ArgAction::Set, default_value_t=stdout().is_terminal())]
     colorize: bool,
 
+    /// Output format
+    #[arg(long = "format", value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     /// Mount path or device(s). If a directory is given, devices are looked up from mountinfo.
     #[arg(required(true))]
     paths: Vec<PathBuf>,
@@ -419,7 +467,7 @@ fn cmd_inode_opts_inner(opt: &Cli) -> Result<()> {
 
     let fs = Fs::open(&devices, fs_opts)?;
 
-    let matches = collect_inode_opts(&fs, opt.dirs, opt.verbose > 0)?;
+    let (matches, mut parent_map) = collect_inode_opts(&fs, opt.dirs, opt.verbose > 0)?;
 
     if matches.is_empty() {
         if !opt.quiet {
@@ -428,17 +476,21 @@ fn cmd_inode_opts_inner(opt: &Cli) -> Result<()> {
         return Ok(());
     }
 
-    if opt.resolve_paths {
-        // Build parent map
-        let initial_parents: HashMap<u64, (u64, u64)> = matches
-            .iter()
-            .filter(|m| m.bi_dir != 0)
-            .map(|m| (m.inum, (m.bi_dir, m.bi_dir_offset)))
+    let paths: Option<HashMap<u64, String>> = if opt.resolve_paths {
+        // `parent_map` already holds the full parent graph built during the
+        // single scan above; only fall back to re-scanning the btree for
+        // ancestors that scan happened to skip (e.g. non-inode_v3 keys).
+        let missing: HashSet<u64> = collect_needed_parents(&matches)
+            .into_iter()
+            .filter(|inum| !parent_map.contains_key(inum))
             .collect();
 
-        let needed = collect_needed_parents(&matches);
-        let mut parent_map = build_parent_map(&fs, needed)?;
-        parent_map.extend(initial_parents);
+        if !missing.is_empty() {
+            if !opt.quiet {
+                eprintln!("parent map missing {} inums, falling back to btree scan", missing.len());
+            }
+            parent_map.extend(build_parent_map(&fs, missing)?);
+        }
 
         if !opt.quiet {
             eprintln!("parent_map entries: {}", parent_map.len());
@@ -456,21 +508,43 @@ fn cmd_inode_opts_inner(opt: &Cli) -> Result<()> {
             eprintln!("resolved {} dirents", dirent_map.len());
         }
 
-        // Output with paths
-        for m in &matches {
-            let path = resolve_path(m, &parent_map, &dirent_map);
-            let opts_str: Vec<String> = m.opts.iter()
-                .map(|(k, v)| format!("{}={}", k, v))
-                .collect();
-            println!("{}\t{}\t{}", m.inum, path, opts_str.join(" "));
-        }
+        Some(matches.iter().map(|m| (m.inum, resolve_path(m, &parent_map, &dirent_map))).collect())
+    } else {
+        None
+    };
+
+    let xattr_map = if opt.format == OutputFormat::Json {
+        let needed: HashSet<u64> = matches.iter().map(|m| m.inum).collect();
+        build_xattr_map(&fs, &needed)?
     } else {
-        // Output without paths
-        for m in &matches {
-            let opts_str: Vec<String> = m.opts.iter()
-                .map(|(k, v)| format!("{}={}", k, v))
-                .collect();
-            println!("{}\t{}", m.inum, opts_str.join(" "));
+        HashMap::new()
+    };
+
+    match opt.format {
+        OutputFormat::Text => {
+            for m in &matches {
+                let opts_str: Vec<String> = m.opts.iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                match &paths {
+                    Some(paths) => println!("{}\t{}\t{}", m.inum, paths.get(&m.inum).map(String::as_str).unwrap_or("?"), opts_str.join(" ")),
+                    None => println!("{}\t{}", m.inum, opts_str.join(" ")),
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let entries: Vec<InodeOptsJson> = matches.iter().map(|m| InodeOptsJson {
+                inum: m.inum,
+                path: paths.as_ref().and_then(|p| p.get(&m.inum).cloned()),
+                opts: m.opts.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+                xattrs: xattr_map.get(&m.inum)
+                    .cloned()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, value)| XattrJson { name, value })
+                    .collect(),
+            }).collect();
+            println!("{}", serde_json::to_string(&entries)?);
         }
     }
 
@@ -0,0 +1,291 @@
+//! Read-only FUSE view of `inode-opts` scan results, grouped by option value.
+//!
+//! Reuses the scan/resolve machinery from [`inode_opts_mounted`] to build an
+//! in-memory catalog at mount time: one directory per distinct option value
+//! (e.g. `compression=zstd/`), containing a symlink back to the real path of
+//! each matching inode. This lets an admin browse "which files have which
+//! non-inherited option" with `find`/`ls` instead of reading a flat dump.
+//!
+//! [`inode_opts_mounted`]: crate::commands::inode_opts_mounted
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+use clap::Parser;
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request};
+use libc::ENOENT;
+
+use crate::commands::inode_opts_mounted::{
+    build_dirent_map, build_parent_map, collect_needed_dirents, format_opt, get_fs_info,
+    parse_inodes, resolve_path, InodeMatch,
+};
+use crate::logging;
+use crate::wrappers::sysfs;
+
+const TTL: Duration = Duration::from_secs(1);
+const ROOT_INO: u64 = 1;
+
+/// A single matched inode, surfaced as one symlink entry per category it
+/// belongs to (a file with two non-default options appears in both
+/// directories).
+struct CatalogEntry {
+    ino: u64,
+    name: String,
+    target: PathBuf,
+}
+
+/// A top-level directory, one per distinct `option=value` pairing seen
+/// across the scan.
+struct Category {
+    ino: u64,
+    name: String,
+    entries: Vec<usize>,
+}
+
+struct Catalog {
+    categories: Vec<Category>,
+    entries: Vec<CatalogEntry>,
+    ino_to_category: HashMap<u64, usize>,
+    ino_to_entry: HashMap<u64, usize>,
+    uid: u32,
+    gid: u32,
+}
+
+fn build_catalog(opt: &Cli) -> Result<Catalog> {
+    let fs_info = get_fs_info(&opt.source_path)?;
+    let target_labels =
+        sysfs::read_target_labels(Path::new(&format!("/sys/fs/bcachefs/{}", fs_info.uuid)));
+
+    let inode_keys = fs_info.debugfs.join("btrees/inodes/keys");
+    let file = File::open(&inode_keys)?;
+    let reader = BufReader::new(file);
+
+    let (matches, parent_cache) = parse_inodes(reader, None, false, false, opt.quiet, true, None)?;
+
+    let parent_map: HashMap<u64, (u64, u64)> = match parent_cache {
+        Some(cache) => cache.data.iter().map(|&(i, d, o)| (i, (d, o))).collect(),
+        None => build_parent_map(&fs_info.debugfs, None, opt.quiet)?,
+    };
+
+    let needed_dirents = collect_needed_dirents(&matches, &parent_map);
+    let dirent_map = build_dirent_map(&fs_info.debugfs, &needed_dirents, None, opt.quiet)?;
+
+    let mut categories: Vec<Category> = Vec::new();
+    let mut category_idx: HashMap<String, usize> = HashMap::new();
+    let mut entries: Vec<CatalogEntry> = Vec::new();
+
+    // Entries start right after the root and all category directories, so
+    // their ino numbers don't need to be known up front.
+    let entry_ino_base = 2 + matches.iter().flat_map(|m| m.opts.iter()).count() as u64;
+
+    for m in &matches {
+        let path = resolve_path(m, &parent_map, &dirent_map);
+        let target = opt.source_path.join(path.trim_start_matches('/'));
+        let filename = path.rsplit('/').next().filter(|s| !s.is_empty()).unwrap_or("root");
+        let name = format!("{}@{}:{}", filename, m.subvol, m.inum);
+
+        let entry_idx = entries.len();
+        entries.push(CatalogEntry { ino: entry_ino_base + entry_idx as u64, name, target });
+
+        for (opt_name, opt_val) in &m.opts {
+            let category_name = format_opt(opt_name, *opt_val, &target_labels);
+            let idx = *category_idx.entry(category_name.clone()).or_insert_with(|| {
+                categories.push(Category {
+                    ino: 2 + categories.len() as u64,
+                    name: category_name,
+                    entries: Vec::new(),
+                });
+                categories.len() - 1
+            });
+            categories[idx].entries.push(entry_idx);
+        }
+    }
+
+    let ino_to_category = categories.iter().enumerate().map(|(i, c)| (c.ino, i)).collect();
+    let ino_to_entry = entries.iter().enumerate().map(|(i, e)| (e.ino, i)).collect();
+
+    if !opt.quiet {
+        eprintln!("catalog: {} categories, {} matched inodes", categories.len(), entries.len());
+    }
+
+    Ok(Catalog {
+        categories,
+        entries,
+        ino_to_category,
+        ino_to_entry,
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    })
+}
+
+impl Catalog {
+    fn dir_attr(&self, ino: u64, nlink: u32) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o555,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    fn symlink_attr(&self, entry: &CatalogEntry) -> FileAttr {
+        FileAttr {
+            ino: entry.ino,
+            size: entry.target.as_os_str().len() as u64,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::Symlink,
+            perm: 0o777,
+            nlink: 1,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    fn attr_for_ino(&self, ino: u64) -> Option<FileAttr> {
+        if ino == ROOT_INO {
+            return Some(self.dir_attr(ROOT_INO, 2));
+        }
+        if self.ino_to_category.contains_key(&ino) {
+            return Some(self.dir_attr(ino, 2));
+        }
+        self.ino_to_entry.get(&ino).map(|&idx| self.symlink_attr(&self.entries[idx]))
+    }
+}
+
+struct CatalogFs {
+    catalog: Catalog,
+}
+
+impl Filesystem for CatalogFs {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let Some(name) = name.to_str() else {
+            reply.error(ENOENT);
+            return;
+        };
+
+        if parent == ROOT_INO {
+            if let Some(category) = self.catalog.categories.iter().find(|c| c.name == name) {
+                reply.entry(&TTL, &self.catalog.dir_attr(category.ino, 2), 0);
+            } else {
+                reply.error(ENOENT);
+            }
+            return;
+        }
+
+        if let Some(&cat_idx) = self.catalog.ino_to_category.get(&parent) {
+            let category = &self.catalog.categories[cat_idx];
+            let found = category
+                .entries
+                .iter()
+                .map(|&idx| &self.catalog.entries[idx])
+                .find(|e| e.name == name);
+            match found {
+                Some(entry) => reply.entry(&TTL, &self.catalog.symlink_attr(entry), 0),
+                None => reply.error(ENOENT),
+            }
+            return;
+        }
+
+        reply.error(ENOENT);
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.catalog.attr_for_ino(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        match self.catalog.ino_to_entry.get(&ino) {
+            Some(&idx) => reply.data(self.catalog.entries[idx].target.as_os_str().as_encoded_bytes()),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let children: Vec<(u64, FileType, String)> = if ino == ROOT_INO {
+            self.catalog
+                .categories
+                .iter()
+                .map(|c| (c.ino, FileType::Directory, c.name.clone()))
+                .collect()
+        } else if let Some(&cat_idx) = self.catalog.ino_to_category.get(&ino) {
+            self.catalog.categories[cat_idx]
+                .entries
+                .iter()
+                .map(|&idx| (self.catalog.entries[idx].ino, FileType::Symlink, self.catalog.entries[idx].name.clone()))
+                .collect()
+        } else {
+            reply.error(ENOENT);
+            return;
+        };
+
+        let mut dots = vec![(ino, FileType::Directory, ".".to_string()), (ROOT_INO, FileType::Directory, "..".to_string())];
+        dots.extend(children);
+
+        for (i, (child_ino, kind, name)) in dots.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(child_ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Mount a read-only synthetic catalog of `inode-opts` matches.
+#[derive(Parser, Debug)]
+pub struct Cli {
+    /// Mounted bcachefs filesystem to scan (debugfs-backed, like `inode-opts`)
+    source_path: PathBuf,
+
+    /// Where to mount the synthetic catalog filesystem
+    mountpoint: PathBuf,
+
+    /// Quiet mode (no progress output)
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Verbose mode
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+pub fn inode_opts_fuse(argv: Vec<String>) -> Result<()> {
+    let opt = Cli::parse_from(argv);
+    logging::setup(opt.verbose, false);
+
+    let catalog = build_catalog(&opt)?;
+    let fs = CatalogFs { catalog };
+
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("bcachefs-opts".to_string()),
+        MountOption::AutoUnmount,
+    ];
+    fuser::mount2(fs, &opt.mountpoint, &options)?;
+    Ok(())
+}
@@ -1,11 +1,15 @@
-use anyhow::{bail, Result};
-use clap::Parser;
-use std::collections::{HashMap, HashSet};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{stdout, BufRead, BufReader, IsTerminal, Write};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
+use crate::commands::inode_opts_cache::{CacheToken, ResolutionCache};
 use crate::logging;
+use crate::wrappers::sysfs;
 
 /// Iterator over lines with lossy UTF-8 handling
 struct LossyLines<R> {
@@ -49,14 +53,13 @@ const INODE_OPTS: &[&str] = &[
     "bi_project",
 ];
 
-fn format_opt(name: &str, val: u64) -> String {
-    let short_name = name.strip_prefix("bi_").unwrap_or(name);
-
+/// Decode a raw stored inode option value into its symbolic display string.
+fn decode_opt_value(name: &str, val: u64, labels: &HashMap<u16, String>) -> String {
     // inode opts have +1 bias: stored 0=inherit, 1=actual 0, 2=actual 1, etc.
     // Subtract 1 to get actual value (we only show non-zero stored values)
     let actual = val.saturating_sub(1);
 
-    let val_str = match name {
+    match name {
         "bi_compression" | "bi_background_compression" => match actual {
             0 => "none".into(),
             1 => "lz4".into(),
@@ -72,27 +75,213 @@ fn format_opt(name: &str, val: u64) -> String {
             _ => format!("{}", actual),
         },
         "bi_data_replicas" => format!("{}", actual),
-        "bi_promote_target" | "bi_foreground_target" | "bi_background_target" => {
-            // TODO: resolve target ID to label from sysfs
-            format!("{}", actual)
-        }
+        "bi_promote_target" | "bi_foreground_target" | "bi_background_target" => labels
+            .get(&(actual as u16))
+            .cloned()
+            .unwrap_or_else(|| format!("{}", actual)),
         _ => format!("{}", actual),
-    };
+    }
+}
+
+pub(crate) fn format_opt(name: &str, val: u64, labels: &HashMap<u16, String>) -> String {
+    let short_name = name.strip_prefix("bi_").unwrap_or(name);
+    format!("{}={}", short_name, decode_opt_value(name, val, labels))
+}
+
+fn resolve_opt_name(short: &str) -> Option<&'static str> {
+    INODE_OPTS.iter().copied().find(|full| full.strip_prefix("bi_").unwrap_or(full) == short)
+}
+
+/// Reverse of [`decode_opt_value`]'s symbolic tables: turn a filter's
+/// right-hand side (`zstd`, or its raw actual value `3`) into the actual
+/// numeric value to compare against.
+fn symbolic_to_actual(name: &str, token: &str) -> Option<u64> {
+    if let Ok(n) = token.parse::<u64>() {
+        return Some(n);
+    }
+
+    match name {
+        "bi_compression" | "bi_background_compression" => match token {
+            "none" => Some(0),
+            "lz4" => Some(1),
+            "gzip" => Some(2),
+            "zstd" => Some(3),
+            _ => None,
+        },
+        "bi_data_checksum" => match token {
+            "none" => Some(0),
+            "crc32c" => Some(1),
+            "crc64" => Some(2),
+            "xxhash" => Some(3),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A parsed `--filter` expression, e.g. `compression=zstd` or
+/// `data_replicas>=2`.
+struct FilterPredicate {
+    opt_name: &'static str,
+    op: CompareOp,
+    value: u64,
+}
+
+impl FilterPredicate {
+    /// Order matters: multi-char ops must be tried before the single-char
+    /// ops they contain (`>=` before `=`/`>`).
+    const OPS: &'static [(&'static str, CompareOp)] = &[
+        (">=", CompareOp::Ge),
+        ("<=", CompareOp::Le),
+        ("!=", CompareOp::Ne),
+        ("=", CompareOp::Eq),
+        (">", CompareOp::Gt),
+        ("<", CompareOp::Lt),
+    ];
+
+    fn parse(expr: &str) -> Result<Self> {
+        let (name_part, op, value_part) = Self::OPS
+            .iter()
+            .find_map(|&(sym, op)| expr.split_once(sym).map(|(n, v)| (n, op, v)))
+            .with_context(|| format!("invalid filter expression: {expr:?}"))?;
+
+        let opt_name = resolve_opt_name(name_part.trim())
+            .with_context(|| format!("unknown option in filter: {:?}", name_part.trim()))?;
+        let value = symbolic_to_actual(opt_name, value_part.trim())
+            .with_context(|| format!("invalid value in filter: {:?}", value_part.trim()))?;
+
+        Ok(Self { opt_name, op, value })
+    }
+
+    fn matches(&self, opts: &[(&'static str, u64)]) -> bool {
+        opts.iter().any(|&(name, raw)| {
+            if name != self.opt_name {
+                return false;
+            }
+            let actual = raw.saturating_sub(1);
+            match self.op {
+                CompareOp::Eq => actual == self.value,
+                CompareOp::Ne => actual != self.value,
+                CompareOp::Lt => actual < self.value,
+                CompareOp::Le => actual <= self.value,
+                CompareOp::Gt => actual > self.value,
+                CompareOp::Ge => actual >= self.value,
+            }
+        })
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SortKey {
+    Inum,
+    Path,
+    #[value(name = "option")]
+    Opt,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Type,
+    Subvol,
+    Inum,
+    Opts,
+    Path,
+}
+
+fn parse_fields(s: &str) -> Result<Vec<Field>> {
+    s.split(',')
+        .map(|tok| match tok.trim() {
+            "type" => Ok(Field::Type),
+            "subvol" => Ok(Field::Subvol),
+            "inum" => Ok(Field::Inum),
+            "opts" => Ok(Field::Opts),
+            "path" => Ok(Field::Path),
+            other => bail!("unknown field: {:?}", other),
+        })
+        .collect()
+}
 
-    format!("{}={}", short_name, val_str)
+fn format_row(
+    m: &InodeMatch,
+    path: Option<&str>,
+    fields: &[Field],
+    labels: &HashMap<u16, String>,
+) -> String {
+    fields
+        .iter()
+        .map(|f| match f {
+            Field::Type => mode_to_type_char(m.mode).to_string(),
+            Field::Subvol => m.subvol.to_string(),
+            Field::Inum => m.inum.to_string(),
+            Field::Opts => m
+                .opts
+                .iter()
+                .map(|(k, v)| format_opt(k, *v, labels))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Field::Path => path.unwrap_or("").to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// Order matches for output. `paths` must be provided (aligned by index with
+/// `matches`) when sorting by [`SortKey::Path`].
+fn build_order(
+    matches: &[InodeMatch],
+    paths: Option<&[String]>,
+    sort_by: Option<SortKey>,
+    descending: bool,
+    labels: &HashMap<u16, String>,
+) -> Result<Vec<usize>> {
+    let mut order: Vec<usize> = (0..matches.len()).collect();
+
+    if let Some(key) = sort_by {
+        match key {
+            SortKey::Inum => order.sort_by_key(|&i| matches[i].inum),
+            SortKey::Path => {
+                let paths = paths.context("--sort-by path requires -P/--resolve-paths")?;
+                order.sort_by(|&a, &b| paths[a].cmp(&paths[b]));
+            }
+            SortKey::Opt => {
+                let opt_key = |i: usize| {
+                    let mut parts: Vec<String> =
+                        matches[i].opts.iter().map(|(n, v)| format_opt(n, *v, labels)).collect();
+                    parts.sort();
+                    parts.join(",")
+                };
+                order.sort_by(|&a, &b| opt_key(a).cmp(&opt_key(b)));
+            }
+        }
+        if descending {
+            order.reverse();
+        }
+    }
+
+    Ok(order)
 }
 
 #[derive(Debug)]
-struct InodeMatch {
-    inum: u64,
-    subvol: u32,
-    mode: u16,
-    bi_dir: u64,
-    bi_dir_offset: u64,
-    opts: Vec<(&'static str, u64)>,
+pub(crate) struct InodeMatch {
+    pub(crate) inum: u64,
+    pub(crate) subvol: u32,
+    pub(crate) mode: u16,
+    pub(crate) bi_dir: u64,
+    pub(crate) bi_dir_offset: u64,
+    pub(crate) opts: Vec<(&'static str, u64)>,
 }
 
-fn mode_to_type_char(mode: u16) -> char {
+pub(crate) fn mode_to_type_char(mode: u16) -> char {
     match mode & 0o170000 {
         0o040000 => 'd',
         0o100000 => '-',
@@ -105,12 +294,12 @@ fn mode_to_type_char(mode: u16) -> char {
     }
 }
 
-struct FsInfo {
-    uuid: String,
-    debugfs: PathBuf,
+pub(crate) struct FsInfo {
+    pub(crate) uuid: String,
+    pub(crate) debugfs: PathBuf,
 }
 
-fn get_fs_info(mount_path: &Path) -> Result<FsInfo> {
+pub(crate) fn get_fs_info(mount_path: &Path) -> Result<FsInfo> {
     let mount_path = mount_path.canonicalize()?;
     let mount_str = mount_path.to_string_lossy();
 
@@ -190,13 +379,14 @@ impl ParentCache {
     }
 }
 
-fn parse_inodes(
+pub(crate) fn parse_inodes(
     reader: impl BufRead,
     total_bytes: Option<u64>,
     dirs_only: bool,
     verbose: bool,
     quiet: bool,
     build_cache: bool,
+    filter: Option<&FilterPredicate>,
 ) -> Result<(Vec<InodeMatch>, Option<ParentCache>)> {
     let mut matches = Vec::new();
     let mut cache = if build_cache { Some(ParentCache::new()) } else { None };
@@ -229,7 +419,8 @@ fn parse_inodes(
             }
 
             let is_dir = (mode & 0o170000) == 0o040000;
-            if *last != Some(inum) && !opts.is_empty() && (!dirs_only || is_dir) {
+            let passes_filter = filter.map_or(true, |f| f.matches(opts));
+            if *last != Some(inum) && !opts.is_empty() && (!dirs_only || is_dir) && passes_filter {
                 if verbose {
                     eprintln!("inum={} opts={:?}", inum, opts);
                 }
@@ -365,124 +556,193 @@ fn parse_inodes(
     Ok((matches, cache))
 }
 
-fn collect_needed_parents(matches: &[InodeMatch]) -> HashSet<u64> {
-    matches
-        .iter()
-        .filter_map(|m| if m.bi_dir != 0 { Some(m.bi_dir) } else { None })
-        .collect()
-}
-
-fn build_parent_map(
+/// Build a complete `inum -> (bi_dir, bi_dir_offset)` map of every directory
+/// inode, in a single pass over the inode btree dump.
+///
+/// Path components are always directories, so restricting the map to
+/// directory inodes is enough to resolve any match's full ancestor chain in
+/// memory afterwards, while keeping memory proportional to the number of
+/// directories rather than all inodes (the full `ParentCache`) or rescanning
+/// once per BFS layer of the chain (the old `needed`-driven approach this
+/// replaces).
+pub(crate) fn build_parent_map(
     debugfs: &Path,
-    mut needed: HashSet<u64>,
     total_bytes: Option<u64>,
     quiet: bool,
 ) -> Result<HashMap<u64, (u64, u64)>> {
-    let mut parent_map = HashMap::new();
     let inode_keys = debugfs.join("btrees/inodes/keys");
+    let file = File::open(&inode_keys)?;
+    let reader = BufReader::new(file);
 
-    while !needed.is_empty() {
-        if !quiet {
-            eprintln!("resolving {} parent inums...", needed.len());
-        }
-
-        let file = File::open(&inode_keys)?;
-        let reader = BufReader::new(file);
-
-        let mut current_inum: Option<u64> = None;
-        let mut bi_dir: u64 = 0;
-        let mut bi_dir_offset: u64 = 0;
-        let mut found_this_pass: HashMap<u64, (u64, u64)> = HashMap::new();
-        let mut read_bytes: u64 = 0;
-        let mut last_pct: i32 = -1;
+    let mut parent_map = HashMap::new();
+    let mut current_inum: Option<u64> = None;
+    let mut mode: u16 = 0;
+    let mut bi_dir: u64 = 0;
+    let mut bi_dir_offset: u64 = 0;
+    let mut read_bytes: u64 = 0;
+    let mut last_pct: i32 = -1;
 
-        for line in LossyLines::new(reader) {
-            // Early exit if we found all needed
-            if found_this_pass.len() == needed.len() {
-                break;
+    let flush = |inum: Option<u64>,
+                 mode: u16,
+                 bi_dir: u64,
+                 bi_dir_offset: u64,
+                 parent_map: &mut HashMap<u64, (u64, u64)>| {
+        if let Some(inum) = inum {
+            if mode & 0o170000 == 0o040000 {
+                parent_map.insert(inum, (bi_dir, bi_dir_offset));
             }
+        }
+    };
 
-            let line = line?;
+    for line in LossyLines::new(reader) {
+        let line = line?;
+        read_bytes += line.len() as u64 + 1;
 
-            read_bytes += line.len() as u64 + 1;
-            if let Some(total) = total_bytes {
-                if !quiet && total > 0 {
-                    let pct = (1000 * read_bytes / total) as i32;
-                    if pct != last_pct {
-                        eprint!("\rparents: {:.1}%", pct as f32 / 10.0);
-                        std::io::stderr().flush().ok();
-                        last_pct = pct;
-                    }
+        if let Some(total) = total_bytes {
+            if !quiet && total > 0 {
+                let pct = (1000 * read_bytes / total) as i32;
+                if pct != last_pct {
+                    eprint!("\rdirs: {:.1}%", pct as f32 / 10.0);
+                    std::io::stderr().flush().ok();
+                    last_pct = pct;
                 }
             }
-            let line = line.trim_end();
+        }
 
-            if line.starts_with("u64s ") && line.contains("inode_v3") {
-                if let Some(inum) = current_inum {
-                    if needed.contains(&inum) && bi_dir != 0 {
-                        found_this_pass.insert(inum, (bi_dir, bi_dir_offset));
-                    }
-                }
+        let line = line.trim_end();
 
-                if let Some(key) = line.split_whitespace().nth(4) {
-                    if let Some(inum_str) = key.split(':').nth(1) {
-                        current_inum = inum_str.parse().ok();
-                    } else {
-                        current_inum = None;
-                    }
-                } else {
-                    current_inum = None;
-                }
-                bi_dir = 0;
-                bi_dir_offset = 0;
-            } else if line.starts_with("u64s ") {
-                if let Some(inum) = current_inum {
-                    if needed.contains(&inum) && bi_dir != 0 {
-                        found_this_pass.insert(inum, (bi_dir, bi_dir_offset));
-                    }
-                }
+        if line.starts_with("u64s ") && line.contains("inode_v3") {
+            flush(current_inum, mode, bi_dir, bi_dir_offset, &mut parent_map);
+
+            if let Some(key) = line.split_whitespace().nth(4) {
+                current_inum = key.split(':').nth(1).and_then(|s| s.parse().ok());
+            } else {
                 current_inum = None;
-            } else if current_inum.is_some() {
-                let trimmed = line.trim();
-                if trimmed.starts_with("bi_dir=") && !trimmed.contains("offset") {
-                    if let Some(val) = trimmed.strip_prefix("bi_dir=") {
-                        bi_dir = val.parse().unwrap_or(0);
-                    }
-                } else if trimmed.starts_with("bi_dir_offset=") {
-                    if let Some(val) = trimmed.strip_prefix("bi_dir_offset=") {
-                        bi_dir_offset = val.parse().unwrap_or(0);
-                    }
+            }
+            mode = 0;
+            bi_dir = 0;
+            bi_dir_offset = 0;
+        } else if line.starts_with("u64s ") {
+            flush(current_inum, mode, bi_dir, bi_dir_offset, &mut parent_map);
+            current_inum = None;
+        } else if current_inum.is_some() {
+            let trimmed = line.trim();
+            if let Some((key, val)) = trimmed.split_once('=') {
+                match key {
+                    "mode" => mode = u16::from_str_radix(val, 8).unwrap_or(0),
+                    "bi_dir" => bi_dir = val.parse().unwrap_or(0),
+                    "bi_dir_offset" => bi_dir_offset = val.parse().unwrap_or(0),
+                    _ => {}
                 }
             }
         }
+    }
+
+    flush(current_inum, mode, bi_dir, bi_dir_offset, &mut parent_map);
+
+    if !quiet {
+        eprintln!("\rdirs: done, {} directory parents", parent_map.len());
+    }
+
+    Ok(parent_map)
+}
+
+/// Build a `dir inum -> explicitly-set options` map of every directory
+/// inode, for resolving inherited inode options up the ancestor chain.
+///
+/// Separate pass from [`build_parent_map`] (rather than folding into it)
+/// because it's only needed for `--format json`'s explicit/inherited
+/// reporting, not the common path-resolution case.
+fn build_dir_opts_map(
+    debugfs: &Path,
+    total_bytes: Option<u64>,
+    quiet: bool,
+) -> Result<HashMap<u64, Vec<(&'static str, u64)>>> {
+    let inode_keys = debugfs.join("btrees/inodes/keys");
+    let file = File::open(&inode_keys)?;
+    let reader = BufReader::new(file);
 
-        if let Some(inum) = current_inum {
-            if needed.contains(&inum) && bi_dir != 0 {
-                found_this_pass.insert(inum, (bi_dir, bi_dir_offset));
+    let mut dir_opts = HashMap::new();
+    let mut current_inum: Option<u64> = None;
+    let mut mode: u16 = 0;
+    let mut opts: Vec<(&'static str, u64)> = Vec::new();
+    let mut read_bytes: u64 = 0;
+    let mut last_pct: i32 = -1;
+
+    let flush = |inum: Option<u64>,
+                 mode: u16,
+                 opts: &[(&'static str, u64)],
+                 dir_opts: &mut HashMap<u64, Vec<(&'static str, u64)>>| {
+        if let Some(inum) = inum {
+            if mode & 0o170000 == 0o040000 && !opts.is_empty() {
+                dir_opts.insert(inum, opts.to_vec());
             }
         }
+    };
 
-        if !quiet && total_bytes.is_some() {
-            eprintln!("\rparents: found {}/{}", found_this_pass.len(), needed.len());
+    for line in LossyLines::new(reader) {
+        let line = line?;
+        read_bytes += line.len() as u64 + 1;
+
+        if let Some(total) = total_bytes {
+            if !quiet && total > 0 {
+                let pct = (1000 * read_bytes / total) as i32;
+                if pct != last_pct {
+                    eprint!("\rdir opts: {:.1}%", pct as f32 / 10.0);
+                    std::io::stderr().flush().ok();
+                    last_pct = pct;
+                }
+            }
         }
 
-        parent_map.extend(found_this_pass.iter());
+        let line = line.trim_end();
 
-        let mut next_needed = HashSet::new();
-        for inum in &needed {
-            if let Some((parent_dir, _)) = found_this_pass.get(inum) {
-                if *parent_dir != 0 && !parent_map.contains_key(parent_dir) {
-                    next_needed.insert(*parent_dir);
+        if line.starts_with("u64s ") && line.contains("inode_v3") {
+            flush(current_inum, mode, &opts, &mut dir_opts);
+
+            if let Some(key) = line.split_whitespace().nth(4) {
+                current_inum = key.split(':').nth(1).and_then(|s| s.parse().ok());
+            } else {
+                current_inum = None;
+            }
+            mode = 0;
+            opts.clear();
+        } else if line.starts_with("u64s ") {
+            flush(current_inum, mode, &opts, &mut dir_opts);
+            current_inum = None;
+            opts.clear();
+        } else if current_inum.is_some() {
+            let trimmed = line.trim();
+            if let Some((key, val)) = trimmed.split_once('=') {
+                match key {
+                    "mode" => mode = u16::from_str_radix(val, 8).unwrap_or(0),
+                    _ => {
+                        for opt in INODE_OPTS {
+                            if key == *opt {
+                                if let Ok(v) = val.parse::<u64>() {
+                                    if v != 0 {
+                                        opts.push((*opt, v));
+                                    }
+                                }
+                                break;
+                            }
+                        }
+                    }
                 }
             }
         }
-        needed = next_needed;
     }
 
-    Ok(parent_map)
+    flush(current_inum, mode, &opts, &mut dir_opts);
+
+    if !quiet {
+        eprintln!("\rdir opts: done, {} directories with explicit options", dir_opts.len());
+    }
+
+    Ok(dir_opts)
 }
 
-fn collect_needed_dirents(
+pub(crate) fn collect_needed_dirents(
     matches: &[InodeMatch],
     parent_map: &HashMap<u64, (u64, u64)>,
 ) -> HashSet<(u64, u64)> {
@@ -512,7 +772,7 @@ fn collect_needed_dirents(
     needed
 }
 
-fn build_dirent_map(
+pub(crate) fn build_dirent_map(
     debugfs: &Path,
     needed: &HashSet<(u64, u64)>,
     total_bytes: Option<u64>,
@@ -591,7 +851,7 @@ fn build_dirent_map(
     Ok(dirent_map)
 }
 
-fn resolve_path(
+pub(crate) fn resolve_path(
     m: &InodeMatch,
     parent_map: &HashMap<u64, (u64, u64)>,
     dirent_map: &HashMap<(u64, u64), String>,
@@ -633,6 +893,90 @@ fn resolve_path(
     format!("/{}", parts.join("/"))
 }
 
+/// Canonicalize `--recursive DIR` and turn it into the `/`-rooted prefix
+/// [`resolve_path`] produces, failing up front if `under` doesn't exist or
+/// isn't inside `mount_path`. Shared by `--dry-run` validation and the real
+/// scoping pass in [`scope_to_subtree`] so a bad `--recursive` path is
+/// rejected the same way in both.
+fn resolve_under_prefix(mount_path: &Path, under: &Path) -> Result<(PathBuf, String)> {
+    let mount_path = mount_path.canonicalize()?;
+    let under = under
+        .canonicalize()
+        .with_context(|| format!("--recursive {}: not accessible", under.display()))?;
+    let rel = under.strip_prefix(&mount_path).map_err(|_| {
+        anyhow::anyhow!("--recursive {} is not inside {}", under.display(), mount_path.display())
+    })?;
+    let prefix =
+        if rel.as_os_str().is_empty() { "/".to_string() } else { format!("/{}", rel.to_string_lossy()) };
+    Ok((under, prefix))
+}
+
+/// Restrict resolved `matches`/`paths` (aligned by index) to `under` and its
+/// descendants, the query-side equivalent of `chattr -R DIR`. `under` must
+/// canonicalize to somewhere inside `mount_path`.
+///
+/// With `one_file_system`, entries are additionally checked against a live
+/// `stat()` of `under` and silently skipped if they sit on a different
+/// device — i.e. behind a bind mount or another filesystem nested under
+/// `DIR`; an entry whose own `stat()` fails is skipped with the failure
+/// recorded. Unlike a real directory walk, this never touches
+/// paths outside what debugfs already reported as non-default, so the
+/// only per-path errors possible here are from that device check.
+fn scope_to_subtree(
+    matches: Vec<InodeMatch>,
+    paths: Vec<String>,
+    mount_path: &Path,
+    under: &Path,
+    one_file_system: bool,
+    quiet: bool,
+) -> Result<(Vec<InodeMatch>, Vec<String>)> {
+    let (under, prefix) = resolve_under_prefix(mount_path, under)?;
+    let mount_path = mount_path.canonicalize()?;
+
+    let root_dev = if one_file_system {
+        Some(std::fs::metadata(&under).with_context(|| format!("stat {}", under.display()))?.dev())
+    } else {
+        None
+    };
+
+    let mut errors: Vec<(String, String)> = Vec::new();
+    let mut kept_matches = Vec::new();
+    let mut kept_paths = Vec::new();
+
+    for (m, path) in matches.into_iter().zip(paths.into_iter()) {
+        let is_under = prefix == "/" || path == prefix || path.starts_with(&format!("{prefix}/"));
+        if !is_under {
+            continue;
+        }
+
+        if let Some(root_dev) = root_dev {
+            let live_path = mount_path.join(path.trim_start_matches('/'));
+            match std::fs::symlink_metadata(&live_path) {
+                Ok(meta) if meta.dev() == root_dev => {}
+                Ok(_) => continue, // different filesystem mounted here; silently skip, like -R does
+                Err(e) => {
+                    errors.push((path.clone(), e.to_string()));
+                    continue;
+                }
+            }
+        }
+
+        kept_matches.push(m);
+        kept_paths.push(path);
+    }
+
+    if !errors.is_empty() {
+        eprintln!("\n{} path(s) under {} had errors:", errors.len(), under.display());
+        for (path, msg) in &errors {
+            eprintln!("  {}: {}", path, msg);
+        }
+    } else if !quiet {
+        eprintln!("recursive: {} matches under {}", kept_matches.len(), under.display());
+    }
+
+    Ok((kept_matches, kept_paths))
+}
+
 fn get_btree_size(mount_path: &Path, btree: &str) -> Option<u64> {
     let output = std::process::Command::new("bcachefs")
         .args(["fs", "usage", "-f", "btree"])
@@ -656,6 +1000,125 @@ fn get_btree_size(mount_path: &Path, btree: &str) -> Option<u64> {
     None
 }
 
+#[derive(Serialize)]
+struct InodeOptJson {
+    value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<u64>,
+    /// Whether this value is stored explicitly on the inode itself (`true`)
+    /// or resolved by walking up the inheritance chain (`false`).
+    explicit: bool,
+    /// Where the effective value came from: `"inode"` (explicitly set),
+    /// `"parent"` (inherited from an ancestor directory), or `"default"`
+    /// (the filesystem-wide option default, read from sysfs). `"unknown"`
+    /// if none of the inode, its ancestors, or sysfs have an answer.
+    source: &'static str,
+}
+
+#[derive(Serialize)]
+struct InodeMatchJson {
+    subvol: u32,
+    inum: u64,
+    r#type: char,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    opts: BTreeMap<String, InodeOptJson>,
+}
+
+/// Resolve the effective value of `opt_name` for an inode that doesn't set
+/// it explicitly, by walking up `bi_dir` through `parent_map`/`dir_opts`
+/// until an ancestor directory has it set, falling back to the filesystem
+/// default exposed over sysfs.
+fn resolve_inherited(
+    opt_name: &'static str,
+    mut dir: u64,
+    parent_map: &HashMap<u64, (u64, u64)>,
+    dir_opts: &HashMap<u64, Vec<(&'static str, u64)>>,
+    fs_sysfs_path: &Path,
+    labels: &HashMap<u16, String>,
+) -> InodeOptJson {
+    let mut seen = HashSet::new();
+    while dir != 0 && seen.insert(dir) {
+        if let Some(opts) = dir_opts.get(&dir) {
+            if let Some(&(_, raw)) = opts.iter().find(|&&(n, _)| n == opt_name) {
+                return InodeOptJson {
+                    value: decode_opt_value(opt_name, raw, labels),
+                    raw: Some(raw),
+                    explicit: false,
+                    source: "parent",
+                };
+            }
+        }
+        dir = parent_map.get(&dir).map_or(0, |&(bi_dir, _)| bi_dir);
+    }
+
+    let short_name = opt_name.strip_prefix("bi_").unwrap_or(opt_name);
+    match sysfs::read_fs_option(fs_sysfs_path, short_name) {
+        Some(value) => InodeOptJson { value, raw: None, explicit: false, source: "default" },
+        None => InodeOptJson {
+            value: "?".to_string(),
+            raw: None,
+            explicit: false,
+            source: "unknown",
+        },
+    }
+}
+
+/// `inherit_ctx` is `Some` when inherited/default resolution is available
+/// (needs the directory parent chain and a directory-options map, both only
+/// built when `-P/--resolve-paths` is in effect); with it `None`, only the
+/// options explicitly set on the inode are reported.
+fn to_json_match(
+    m: &InodeMatch,
+    path: Option<String>,
+    labels: &HashMap<u16, String>,
+    inherit_ctx: Option<(&HashMap<u64, (u64, u64)>, &HashMap<u64, Vec<(&'static str, u64)>>, &Path)>,
+) -> InodeMatchJson {
+    let mut opts: BTreeMap<String, InodeOptJson> = m
+        .opts
+        .iter()
+        .map(|(name, val)| {
+            let short_name = name.strip_prefix("bi_").unwrap_or(name).to_string();
+            let opt = InodeOptJson {
+                value: decode_opt_value(name, *val, labels),
+                raw: Some(*val),
+                explicit: true,
+                source: "inode",
+            };
+            (short_name, opt)
+        })
+        .collect();
+
+    if let Some((parent_map, dir_opts, fs_sysfs_path)) = inherit_ctx {
+        for name in INODE_OPTS {
+            let short_name = name.strip_prefix("bi_").unwrap_or(name);
+            if opts.contains_key(short_name) {
+                continue;
+            }
+            let resolved =
+                resolve_inherited(name, m.bi_dir, parent_map, dir_opts, fs_sysfs_path, labels);
+            opts.insert(short_name.to_string(), resolved);
+        }
+    }
+
+    InodeMatchJson {
+        subvol: m.subvol,
+        inum: m.inum,
+        r#type: mode_to_type_char(m.mode),
+        path,
+        opts,
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    #[value(alias = "human")]
+    Text,
+    Json,
+    Ndjson,
+}
+
 /// Find inodes with non-default bcachefs options (mounted filesystem via debugfs)
 #[derive(Parser, Debug)]
 pub struct Cli {
@@ -683,17 +1146,59 @@ pub struct Cli {
     #[arg(short = 'L', long)]
     low_memory: bool,
 
+    /// Output format: human (text), json, or ndjson (one JSON object per line)
+    #[arg(long = "format", visible_alias = "output", value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Only show inodes matching this expression, e.g. "compression=zstd" or
+    /// "data_replicas>=2" (comparisons: = != < <= > >=)
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Sort results by this key
+    #[arg(long = "sort-by", value_enum)]
+    sort_by: Option<SortKey>,
+
+    /// Reverse the sort order
+    #[arg(long, requires = "sort_by")]
+    descending: bool,
+
+    /// Comma-separated columns to print in text output: type,subvol,inum,opts,path
+    #[arg(long)]
+    fields: Option<String>,
+
+    /// Restrict results to this directory and everything beneath it, rather
+    /// than the whole filesystem (requires -P/--resolve-paths)
+    #[arg(short = 'R', long = "recursive", value_name = "DIR", requires = "resolve_paths")]
+    under: Option<PathBuf>,
+
+    /// With --recursive, skip entries under another filesystem mounted
+    /// beneath DIR
+    #[arg(long, requires = "under")]
+    one_file_system: bool,
+
+    /// Validate arguments and describe what would be scanned, without
+    /// touching debugfs
+    #[arg(long)]
+    dry_run: bool,
+
     /// Mount path
     mount_path: PathBuf,
 }
 
 fn cmd_inner(opt: &Cli) -> Result<()> {
+    let filter = opt.filter.as_deref().map(FilterPredicate::parse).transpose()?;
+    let fields = opt.fields.as_deref().map(parse_fields).transpose()?;
+
     let fs_info = get_fs_info(&opt.mount_path)?;
 
     if !opt.quiet {
         eprintln!("uuid: {}", fs_info.uuid);
     }
 
+    let target_labels =
+        sysfs::read_target_labels(Path::new(&format!("/sys/fs/bcachefs/{}", fs_info.uuid)));
+
     let inode_size = get_btree_size(&opt.mount_path, "inodes");
     let dirent_size = get_btree_size(&opt.mount_path, "dirents");
 
@@ -706,12 +1211,50 @@ fn cmd_inner(opt: &Cli) -> Result<()> {
         }
     }
 
+    // Everything above this point is itself the up-front validation a
+    // --dry-run needs: the mount lookup, uuid/debugfs resolution, and
+    // --filter/--fields parsing all already bail via `?` before any
+    // (potentially large) btree scan starts. --recursive's containment
+    // check below is the last piece, so a typo'd path or filter is caught
+    // here rather than after minutes of scanning.
+    let under_prefix = opt.under.as_deref().map(|u| resolve_under_prefix(&opt.mount_path, u)).transpose()?;
+
+    if opt.dry_run {
+        eprintln!("dry run: would scan {}", opt.mount_path.display());
+        if let Some((under, prefix)) = &under_prefix {
+            eprintln!("  recursive: restricted to {} ({})", under.display(), prefix);
+            if opt.one_file_system {
+                eprintln!("  one-file-system: entries on another device under {prefix} are skipped");
+            }
+        }
+        if let Some(expr) = &opt.filter {
+            eprintln!("  filter: {}", expr);
+        }
+        eprintln!("  format: {:?}", opt.format);
+        return Ok(());
+    }
+
     let inode_keys = fs_info.debugfs.join("btrees/inodes/keys");
     let file = File::open(&inode_keys)?;
     let reader = BufReader::new(file);
 
-    // Build cache if resolving paths and not in low-memory mode
-    let build_cache = opt.resolve_paths && !opt.low_memory;
+    // If we're resolving paths and not in low-memory mode, see if a valid
+    // on-disk resolution cache already covers this filesystem so we can skip
+    // re-scanning the inode btree (and as much of the dirent btree as
+    // possible) just to resolve parents.
+    let cache_token = if opt.resolve_paths && !opt.low_memory {
+        CacheToken::current(&fs_info.debugfs, inode_size)
+    } else {
+        None
+    };
+    let disk_cache = cache_token.and_then(|t| ResolutionCache::load(&fs_info.uuid, t));
+    if disk_cache.is_some() && !opt.quiet {
+        eprintln!("found valid on-disk resolution cache");
+    }
+
+    // Only build the in-memory parent cache from this scan if we don't
+    // already have a valid on-disk one to reuse.
+    let build_cache = opt.resolve_paths && !opt.low_memory && disk_cache.is_none();
     let (matches, parent_cache) = parse_inodes(
         reader,
         inode_size,
@@ -719,6 +1262,7 @@ fn cmd_inner(opt: &Cli) -> Result<()> {
         opt.verbose > 0,
         opt.quiet,
         build_cache,
+        filter.as_ref(),
     )?;
 
     if matches.is_empty() {
@@ -729,13 +1273,20 @@ fn cmd_inner(opt: &Cli) -> Result<()> {
     }
 
     if opt.resolve_paths {
-        // Build parent_map either from cache or by rescanning
-        let parent_map: HashMap<u64, (u64, u64)> = if let Some(cache) = parent_cache {
-            // Use cached data - no rescans needed
-            if !opt.quiet {
-                eprintln!("using cached parent data");
-            }
-            cache.data.iter().map(|&(i, d, o)| (i, (d, o))).collect()
+        // Build parent_map from the on-disk cache, a fresh in-memory scan, or
+        // (low-memory mode) by rescanning the btree for every directory's parent.
+        let parent_map: HashMap<u64, (u64, u64)> = if let Some(ref disk_cache) = disk_cache {
+            disk_cache.parents.iter().map(|&(i, d, o)| (i, (d, o))).collect()
+        } else if let Some(cache) = parent_cache {
+            let initial_parents: HashMap<u64, (u64, u64)> = matches
+                .iter()
+                .filter(|m| m.bi_dir != 0)
+                .map(|m| (m.inum, (m.bi_dir, m.bi_dir_offset)))
+                .collect();
+            let mut parent_map: HashMap<u64, (u64, u64)> =
+                cache.data.iter().map(|&(i, d, o)| (i, (d, o))).collect();
+            parent_map.extend(initial_parents);
+            parent_map
         } else {
             // Low-memory mode: rescan for parents
             let initial_parents: HashMap<u64, (u64, u64)> = matches
@@ -744,9 +1295,7 @@ fn cmd_inner(opt: &Cli) -> Result<()> {
                 .map(|m| (m.inum, (m.bi_dir, m.bi_dir_offset)))
                 .collect();
 
-            let needed = collect_needed_parents(&matches);
-            let mut parent_map =
-                build_parent_map(&fs_info.debugfs, needed, inode_size, opt.quiet)?;
+            let mut parent_map = build_parent_map(&fs_info.debugfs, inode_size, opt.quiet)?;
             parent_map.extend(initial_parents);
             parent_map
         };
@@ -756,35 +1305,143 @@ fn cmd_inner(opt: &Cli) -> Result<()> {
         }
 
         let needed_dirents = collect_needed_dirents(&matches, &parent_map);
+        let mut dirent_map = disk_cache.as_ref().map(|c| c.dirents.clone()).unwrap_or_default();
+        let still_needed: HashSet<(u64, u64)> = needed_dirents
+            .iter()
+            .filter(|k| !dirent_map.contains_key(*k))
+            .copied()
+            .collect();
         if !opt.quiet {
-            eprintln!("need {} dirent lookups", needed_dirents.len());
-        }
-
-        let dirent_map =
-            build_dirent_map(&fs_info.debugfs, &needed_dirents, dirent_size, opt.quiet)?;
-
-        for m in &matches {
-            let path = resolve_path(m, &parent_map, &dirent_map);
-            let opts_str: Vec<String> = m.opts.iter().map(|(k, v)| format_opt(k, *v)).collect();
-            println!(
-                "{} {}:{}\t{}\t{}",
-                mode_to_type_char(m.mode),
-                m.subvol,
-                m.inum,
-                opts_str.join(" "),
-                path
+            eprintln!(
+                "need {} dirent lookups ({} already cached)",
+                needed_dirents.len(),
+                needed_dirents.len() - still_needed.len()
             );
         }
+        if !still_needed.is_empty() {
+            let fresh = build_dirent_map(&fs_info.debugfs, &still_needed, dirent_size, opt.quiet)?;
+            dirent_map.extend(fresh);
+        }
+
+        if let Some(token) = cache_token {
+            let mut parents: Vec<(u64, u64, u64)> =
+                parent_map.iter().map(|(&i, &(d, o))| (i, d, o)).collect();
+            parents.sort_unstable_by_key(|&(inum, _, _)| inum);
+            if let Err(e) = ResolutionCache::save(&fs_info.uuid, token, &parents, &dirent_map) {
+                if !opt.quiet {
+                    eprintln!("warning: failed to save resolution cache: {e:#}");
+                }
+            }
+        }
+
+        let paths: Vec<String> =
+            matches.iter().map(|m| resolve_path(m, &parent_map, &dirent_map)).collect();
+
+        let (matches, paths) = if let Some(under) = &opt.under {
+            scope_to_subtree(matches, paths, &opt.mount_path, under, opt.one_file_system, opt.quiet)?
+        } else {
+            (matches, paths)
+        };
+
+        let order = build_order(&matches, Some(&paths), opt.sort_by, opt.descending, &target_labels)?;
+
+        // Only worth scanning for ancestor options (a second btree pass)
+        // when we're about to report explicit-vs-inherited status in JSON.
+        let fs_sysfs_path = PathBuf::from(format!("/sys/fs/bcachefs/{}", fs_info.uuid));
+        let dir_opts = if opt.format != OutputFormat::Text {
+            Some(build_dir_opts_map(&fs_info.debugfs, inode_size, opt.quiet)?)
+        } else {
+            None
+        };
+        let inherit_ctx = dir_opts
+            .as_ref()
+            .map(|dir_opts| (&parent_map, dir_opts, fs_sysfs_path.as_path()));
+
+        match opt.format {
+            OutputFormat::Text => {
+                for &idx in &order {
+                    let m = &matches[idx];
+                    match &fields {
+                        Some(fields) => {
+                            println!("{}", format_row(m, Some(&paths[idx]), fields, &target_labels))
+                        }
+                        None => {
+                            let opts_str: Vec<String> =
+                                m.opts.iter().map(|(k, v)| format_opt(k, *v, &target_labels)).collect();
+                            println!(
+                                "{} {}:{}\t{}\t{}",
+                                mode_to_type_char(m.mode),
+                                m.subvol,
+                                m.inum,
+                                opts_str.join(" "),
+                                paths[idx]
+                            );
+                        }
+                    }
+                }
+            }
+            OutputFormat::Ndjson => {
+                for &idx in &order {
+                    let record = to_json_match(
+                        &matches[idx],
+                        Some(paths[idx].clone()),
+                        &target_labels,
+                        inherit_ctx,
+                    );
+                    println!("{}", serde_json::to_string(&record)?);
+                }
+            }
+            OutputFormat::Json => {
+                let records: Vec<InodeMatchJson> = order
+                    .iter()
+                    .map(|&idx| {
+                        to_json_match(
+                            &matches[idx],
+                            Some(paths[idx].clone()),
+                            &target_labels,
+                            inherit_ctx,
+                        )
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string(&records)?);
+            }
+        }
     } else {
-        for m in &matches {
-            let opts_str: Vec<String> = m.opts.iter().map(|(k, v)| format_opt(k, *v)).collect();
-            println!(
-                "{} {}:{}\t{}",
-                mode_to_type_char(m.mode),
-                m.subvol,
-                m.inum,
-                opts_str.join(" ")
-            );
+        let order = build_order(&matches, None, opt.sort_by, opt.descending, &target_labels)?;
+
+        match opt.format {
+            OutputFormat::Text => {
+                for &idx in &order {
+                    let m = &matches[idx];
+                    match &fields {
+                        Some(fields) => println!("{}", format_row(m, None, fields, &target_labels)),
+                        None => {
+                            let opts_str: Vec<String> =
+                                m.opts.iter().map(|(k, v)| format_opt(k, *v, &target_labels)).collect();
+                            println!(
+                                "{} {}:{}\t{}",
+                                mode_to_type_char(m.mode),
+                                m.subvol,
+                                m.inum,
+                                opts_str.join(" ")
+                            );
+                        }
+                    }
+                }
+            }
+            OutputFormat::Ndjson => {
+                for &idx in &order {
+                    let record = to_json_match(&matches[idx], None, &target_labels, None);
+                    println!("{}", serde_json::to_string(&record)?);
+                }
+            }
+            OutputFormat::Json => {
+                let records: Vec<InodeMatchJson> = order
+                    .iter()
+                    .map(|&idx| to_json_match(&matches[idx], None, &target_labels, None))
+                    .collect();
+                println!("{}", serde_json::to_string(&records)?);
+            }
         }
     }
 
@@ -9,6 +9,7 @@ use bch_bindgen::sb_io;
 use clap::Parser;
 
 use crate::key::{sb_is_encrypted, unencrypted_key, KeyHandle, Keyring, Passphrase};
+use crate::mount;
 
 // ---- unlock ----
 
@@ -92,8 +93,16 @@ fn parse_device_list(args: &[String]) -> Vec<PathBuf> {
     }
 }
 
-/// Open a filesystem with nostart for superblock modification.
+/// Open a filesystem with nostart for superblock modification. Refuses to
+/// proceed if any device is currently a mounted source — rewriting the
+/// crypt field underneath a live filesystem corrupts it.
 fn open_nostart(devs: &[PathBuf]) -> Result<Fs> {
+    for dev in devs {
+        if mount::is_source_mounted(dev)? {
+            bail!("{} is currently mounted; refusing to rewrite the superblock of a live filesystem", dev.display());
+        }
+    }
+
     let mut opts = c::bch_opts::default();
     opt_set!(opts, nostart, 1);
     Fs::open(devs, opts)
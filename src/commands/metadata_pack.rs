@@ -0,0 +1,284 @@
+//! `bcachefs metadata-pack`/`metadata-unpack`: export/import just a
+//! filesystem's btree metadata, the way `thin_metadata_pack`/
+//! `thin_metadata_unpack` let a thin-pool's metadata be shipped to a
+//! maintainer without the (much larger) backing data device. A pack file
+//! holds a copy of the superblock region (so geometry matches on restore)
+//! plus every btree node's on-disk bytes — the same buffer `ondisk_to_text`
+//! formats — tagged with its `(btree_id, level, bpos)` and first replica's
+//! physical placement. `metadata-unpack` replays those records into sparse
+//! per-device images at their original physical offsets, so a developer can
+//! run `fsck`/`dump` against the user's metadata alone.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIterFlags, BtreeNodeIter, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::Parser;
+
+const MAGIC: &[u8; 8] = b"BCHMPACK";
+const FORMAT_VERSION: u8 = 1;
+
+// bcachefs's superblock starts at sector 8 (SB_SECTOR); the region we copy
+// is sized generously rather than read from the (unparsed) `bytes` field so
+// the copy is a plain byte-range read, not a struct-layout assumption.
+const SB_OFFSET: u64 = 4096;
+const SB_CAPTURE_BYTES: u64 = 1 << 20;
+
+// Widest depth we'll ask `BtreeNodeIter` for when walking "from the root
+// down" (mirrors `explore.rs::ROOT_DEPTH`: real trees are shallow enough
+// that an over-large depth request just clamps to the actual root).
+const MAX_DEPTH: u32 = 4;
+
+// Layout of `struct bch_btree_ptr_v2`'s header, ahead of its
+// `bch_extent_ptr start[]` array: mem_ptr(8) + seq(8) + sectors_written(2)
+// + flags(2) + min_key(bpos, 2x u64 + u32 snapshot = 20) = 40 bytes.
+const BTREE_PTR_V2_HEADER_BYTES: usize = 8 + 8 + 2 + 2 + 20;
+
+fn node_bytes(fs: &Fs) -> u64 {
+    unsafe { (*fs.raw).opts.btree_node_size as u64 * 512 }
+}
+
+/// Pull the first replica's (dev, sector offset) out of a
+/// `KEY_TYPE_btree_ptr_v2` key, for placing the node in a sparse image at
+/// its original physical location. Returns `None` for any other key type
+/// (e.g. legacy `KEY_TYPE_btree_ptr`, which this doesn't handle).
+fn first_ptr(key: &c::bkey_i) -> Option<(u8, u64)> {
+    if key.k.type_ != bcachefs::bch_bkey_type::KEY_TYPE_btree_ptr_v2 as u8 {
+        return None;
+    }
+    unsafe {
+        let v = &key.v as *const c::bch_val as *const u8;
+        let ptr_bytes = std::slice::from_raw_parts(v.add(BTREE_PTR_V2_HEADER_BYTES), 8);
+        let raw = u64::from_le_bytes(ptr_bytes.try_into().unwrap());
+        // struct bch_extent_ptr: type:1, cached:1, unused:1, unwritten:1,
+        // offset:44, dev:8, gen:8 (little-endian bitfield).
+        let dev = ((raw >> 48) & 0xff) as u8;
+        let offset = (raw >> 4) & ((1u64 << 44) - 1);
+        Some((dev, offset))
+    }
+}
+
+/// Walk every btree's node tree from the root down, capturing each node's
+/// raw bytes and header. Walking one level at a time, from the highest level
+/// down, means parents are always written before their children.
+fn pack_nodes<W: Write>(fs: &Fs, out: &mut W) -> Result<u64> {
+    let bytes_per_node = node_bytes(fs);
+    let nr = bcachefs::btree_id::BTREE_ID_NR as u32;
+    let mut seen = std::collections::HashSet::new();
+    let mut count = 0u64;
+
+    for btree_id in 0..nr {
+        let btree: bcachefs::btree_id = unsafe { std::mem::transmute(btree_id) };
+
+        for depth in (0..=MAX_DEPTH).rev() {
+            let trans = BtreeTrans::new(fs);
+            let mut iter =
+                BtreeNodeIter::new(&trans, btree, bch_bindgen::POS_MIN, 0, depth, BtreeIterFlags::empty());
+
+            while let Some(b) = iter.next()? {
+                let pos = b.key.k.p;
+                let ident = (btree_id, b.c.level, pos.inode, pos.offset, pos.snapshot);
+                if !seen.insert(ident) {
+                    continue;
+                }
+
+                let data = unsafe { std::slice::from_raw_parts(b.data as *const u8, bytes_per_node as usize) };
+                let crc = crc32fast::hash(data);
+                let (ptr_dev, ptr_offset) = first_ptr(&b.key).unwrap_or((0xff, 0));
+
+                out.write_all(&[btree_id as u8, b.c.level])?;
+                out.write_all(&pos.inode.to_le_bytes())?;
+                out.write_all(&pos.offset.to_le_bytes())?;
+                out.write_all(&pos.snapshot.to_le_bytes())?;
+                out.write_all(&[ptr_dev])?;
+                out.write_all(&ptr_offset.to_le_bytes())?;
+                out.write_all(&(data.len() as u64).to_le_bytes())?;
+                out.write_all(&crc.to_le_bytes())?;
+                out.write_all(data)?;
+
+                count += 1;
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+struct NodeRecord {
+    data: Vec<u8>,
+    ptr_dev: u8,
+    ptr_offset: u64,
+}
+
+fn read_node_record<R: Read>(r: &mut R) -> io::Result<Option<NodeRecord>> {
+    let mut header = [0u8; 2];
+    match r.read(&mut header)? {
+        0 => return Ok(None),
+        n if n < 2 => r.read_exact(&mut header[n..])?,
+        _ => {}
+    }
+
+    let mut u64buf = [0u8; 8];
+    r.read_exact(&mut u64buf)?; // inode, unused on unpack
+    r.read_exact(&mut u64buf)?; // offset, unused on unpack
+    let mut u32buf = [0u8; 4];
+    r.read_exact(&mut u32buf)?; // snapshot, unused on unpack
+
+    let mut dev = [0u8; 1];
+    r.read_exact(&mut dev)?;
+    let mut ptr_offset = [0u8; 8];
+    r.read_exact(&mut ptr_offset)?;
+    let mut data_len = [0u8; 8];
+    r.read_exact(&mut data_len)?;
+    let mut crc = [0u8; 4];
+    r.read_exact(&mut crc)?;
+
+    let data_len = u64::from_le_bytes(data_len) as usize;
+    let mut data = vec![0u8; data_len];
+    r.read_exact(&mut data)?;
+
+    let expect_crc = u32::from_le_bytes(crc);
+    let got_crc = crc32fast::hash(&data);
+    if got_crc != expect_crc {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "node checksum mismatch in pack file"));
+    }
+
+    Ok(Some(NodeRecord { data, ptr_dev: dev[0], ptr_offset: u64::from_le_bytes(ptr_offset) }))
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Export a filesystem's btree metadata to a portable archive")]
+pub struct PackCli {
+    /// Device containing the filesystem
+    device: PathBuf,
+
+    /// Output pack file
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+pub fn metadata_pack(argv: Vec<String>) -> Result<()> {
+    let cli = PackCli::parse_from(argv);
+
+    let mut sb_bytes = vec![0u8; SB_CAPTURE_BYTES as usize];
+    {
+        let mut dev = File::open(&cli.device)
+            .with_context(|| format!("opening '{}'", cli.device.display()))?;
+        dev.seek(SeekFrom::Start(SB_OFFSET))?;
+        dev.read_exact(&mut sb_bytes).context("reading superblock region")?;
+    }
+
+    // Same offline-only, read-only open as `cmd_reset_counters`.
+    let mut fs_opts = c::bch_opts::default();
+    opt_set!(fs_opts, nostart, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+    let fs = Fs::open(&[cli.device.clone()], fs_opts)
+        .map_err(|e| anyhow::anyhow!("Error opening filesystem: {}", e))?;
+
+    let out_file = File::create(&cli.output)
+        .with_context(|| format!("creating '{}'", cli.output.display()))?;
+    let mut out = BufWriter::new(out_file);
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[FORMAT_VERSION])?;
+    out.write_all(&(sb_bytes.len() as u64).to_le_bytes())?;
+    out.write_all(&sb_bytes)?;
+
+    let count = pack_nodes(&fs, &mut out)?;
+    out.flush()?;
+
+    eprintln!("wrote {} btree nodes to {}", count, cli.output.display());
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Reconstruct sparse per-device images from a metadata-pack archive")]
+pub struct UnpackCli {
+    /// Pack file produced by `metadata-pack`
+    input: PathBuf,
+
+    /// Directory to write the reconstructed sparse device image(s) into
+    #[arg(short, long)]
+    output_dir: PathBuf,
+}
+
+pub fn metadata_unpack(argv: Vec<String>) -> Result<()> {
+    let cli = UnpackCli::parse_from(argv);
+
+    std::fs::create_dir_all(&cli.output_dir)
+        .with_context(|| format!("creating '{}'", cli.output_dir.display()))?;
+
+    let in_file = File::open(&cli.input).with_context(|| format!("opening '{}'", cli.input.display()))?;
+    let mut input = BufReader::new(in_file);
+
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).context("reading pack header")?;
+    anyhow::ensure!(&magic == MAGIC, "'{}' is not a metadata-pack archive", cli.input.display());
+    let mut version = [0u8; 1];
+    input.read_exact(&mut version)?;
+    anyhow::ensure!(version[0] == FORMAT_VERSION, "unsupported pack format version {}", version[0]);
+
+    let mut sb_len = [0u8; 8];
+    input.read_exact(&mut sb_len)?;
+    let sb_len = u64::from_le_bytes(sb_len) as usize;
+    let mut sb_bytes = vec![0u8; sb_len];
+    input.read_exact(&mut sb_bytes)?;
+
+    let mut images: HashMap<u8, File> = HashMap::new();
+    let mut nodes = 0u64;
+    let mut skipped = 0u64;
+
+    loop {
+        let record = match read_node_record(&mut input)? {
+            Some(r) => r,
+            None => break,
+        };
+
+        if record.ptr_dev == 0xff {
+            // No decodable physical pointer (e.g. legacy btree_ptr key);
+            // can't place this node in a sparse image.
+            skipped += 1;
+            continue;
+        }
+
+        let image = match images.get_mut(&record.ptr_dev) {
+            Some(f) => f,
+            None => {
+                let path = cli.output_dir.join(format!("dev{}.img", record.ptr_dev));
+                let f = OpenOptions::new().create(true).write(true).read(true).open(&path)
+                    .with_context(|| format!("creating '{}'", path.display()))?;
+                // Every device image also carries a copy of the captured
+                // superblock region, so geometry matches on restore.
+                let mut f = f;
+                f.seek(SeekFrom::Start(SB_OFFSET))?;
+                f.write_all(&sb_bytes)?;
+                images.insert(record.ptr_dev, f);
+                images.get_mut(&record.ptr_dev).unwrap()
+            }
+        };
+
+        image.seek(SeekFrom::Start(record.ptr_offset * 512))?;
+        image.write_all(&record.data)?;
+        nodes += 1;
+    }
+
+    for f in images.values_mut() {
+        f.flush()?;
+    }
+
+    eprintln!(
+        "reconstructed {} btree nodes into {} ({} skipped, no physical pointer)",
+        nodes,
+        cli.output_dir.display(),
+        skipped,
+    );
+    Ok(())
+}
@@ -1,20 +1,61 @@
 use clap::Subcommand;
 
+mod attr;
+pub mod browse;
+pub mod catalog;
+mod counters;
+mod data;
+mod device;
+mod device_discard;
+mod device_image;
 pub mod completions;
+pub mod explore;
+pub mod fs_usage;
+mod fsck_online;
 pub mod inode_opts;
+mod inode_opts_cache;
 mod inode_opts_device;
+pub mod inode_opts_fuse;
 mod inode_opts_mounted;
 pub mod list;
+mod metadata_pack;
 pub mod mount;
+pub(crate) mod opts;
+mod recovery_pass;
+pub mod rmap;
+mod scrub;
+mod timestats;
+mod top;
 pub mod subvolume;
+mod subvol_delta;
 pub mod subvol_diff;
 
+pub use attr::{cmd_getattr, cmd_reflink_option_propagate, cmd_setattr};
+pub use browse::browse;
+pub use catalog::catalog;
 pub use completions::completions;
+pub use counters::cmd_reset_counters;
+pub use data::data;
+pub use device::{
+    cmd_device_evacuate, cmd_device_offline, cmd_device_online, cmd_device_remove,
+    cmd_device_resize, cmd_device_resize_journal, cmd_device_set_state,
+};
+pub use device_discard::cmd_device_discard;
+pub use device_image::cmd_device_image;
+pub use explore::explore;
+pub use fsck_online::fsck_online;
 pub use inode_opts::inode_opts;
+pub use inode_opts_fuse::inode_opts_fuse;
 pub use list::list;
+pub use metadata_pack::{metadata_pack, metadata_unpack};
 pub use mount::mount;
+pub use recovery_pass::recovery_pass;
+pub use rmap::rmap;
+pub use scrub::scrub;
 pub use subvolume::subvolume;
 pub use subvol_diff::subvol_diff;
+pub use timestats::timestats;
+pub use top::top;
 
 #[derive(clap::Parser, Debug)]
 #[command(name = "bcachefs")]
@@ -34,4 +75,9 @@ enum Subcommands {
     SubvolDiff(subvol_diff::Cli),
     #[command(name = "inode-opts")]
     InodeOpts(inode_opts::Cli),
+    #[command(name = "inode-opts-fuse")]
+    InodeOptsFuse(inode_opts_fuse::Cli),
+    Catalog(catalog::Cli),
+    Browse(browse::Cli),
+    Explore(explore::Cli),
 }
@@ -1,18 +1,20 @@
 use std::{
     ffi::CString,
     io::{stdout, IsTerminal},
+    os::fd::BorrowedFd,
     path::{Path, PathBuf},
     ptr, str,
+    time::Duration,
 };
 
 use anyhow::{ensure, Result};
 use bch_bindgen::{bcachefs, bcachefs::bch_sb_handle, path_to_cstr};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use log::{debug, error, info};
 use crate::device_scan;
 
 use crate::{
-    key::{KeyHandle, Passphrase, UnlockPolicy},
+    key::{FdUnlockConfig, KeyHandle, Keyring, Passphrase, UnlockPolicy},
     logging,
 };
 
@@ -74,34 +76,56 @@ fn mount_inner(
     }
 }
 
+// Not yet exposed by the `libc` crate's mount flag constants.
+const MS_NOSYMFOLLOW: libc::c_ulong = 1 << 8;
+const MS_SILENT: libc::c_ulong = 1 << 15;
+const MS_I_VERSION: libc::c_ulong = 1 << 23;
+const MS_LAZYTIME: libc::c_ulong = 1 << 25;
+
+enum FlagOp {
+    Set(libc::c_ulong),
+    Clear(libc::c_ulong),
+}
+
 /// Parse a comma-separated mount options and split out mountflags and filesystem
 /// specific options.
 fn parse_mountflag_options(options: impl AsRef<str>) -> (Option<String>, libc::c_ulong) {
     use either::Either::{Left, Right};
+    use FlagOp::{Clear, Set};
 
     debug!("parsing mount options: {}", options.as_ref());
     let (opts, flags) = options
         .as_ref()
         .split(',')
         .map(|o| match o {
-            "dirsync" => Left(libc::MS_DIRSYNC),
-            "lazytime" => Left(1 << 25), // MS_LAZYTIME
-            "mand" => Left(libc::MS_MANDLOCK),
-            "noatime" => Left(libc::MS_NOATIME),
-            "nodev" => Left(libc::MS_NODEV),
-            "nodiratime" => Left(libc::MS_NODIRATIME),
-            "noexec" => Left(libc::MS_NOEXEC),
-            "nosuid" => Left(libc::MS_NOSUID),
-            "relatime" => Left(libc::MS_RELATIME),
-            "remount" => Left(libc::MS_REMOUNT),
-            "ro" => Left(libc::MS_RDONLY),
-            "rw" | "" => Left(0),
-            "strictatime" => Left(libc::MS_STRICTATIME),
-            "sync" => Left(libc::MS_SYNCHRONOUS),
+            "dirsync" => Left(Set(libc::MS_DIRSYNC)),
+            "iversion" => Left(Set(MS_I_VERSION)),
+            "lazytime" => Left(Set(MS_LAZYTIME)),
+            "mand" => Left(Set(libc::MS_MANDLOCK)),
+            "noatime" => Left(Set(libc::MS_NOATIME)),
+            "nodev" => Left(Set(libc::MS_NODEV)),
+            "nodiratime" => Left(Set(libc::MS_NODIRATIME)),
+            "noexec" => Left(Set(libc::MS_NOEXEC)),
+            "nosuid" => Left(Set(libc::MS_NOSUID)),
+            "nosymfollow" => Left(Set(MS_NOSYMFOLLOW)),
+            "relatime" => Left(Set(libc::MS_RELATIME)),
+            "remount" => Left(Set(libc::MS_REMOUNT)),
+            "ro" => Left(Set(libc::MS_RDONLY)),
+            "rw" | "" => Left(Set(0)),
+            "silent" => Left(Set(MS_SILENT)),
+            "strictatime" => Left(Set(libc::MS_STRICTATIME)),
+            "sync" => Left(Set(libc::MS_SYNCHRONOUS)),
+            "atime" => Left(Clear(libc::MS_NOATIME)),
+            "dev" => Left(Clear(libc::MS_NODEV)),
+            "diratime" => Left(Clear(libc::MS_NODIRATIME)),
+            "exec" => Left(Clear(libc::MS_NOEXEC)),
+            "suid" => Left(Clear(libc::MS_NOSUID)),
+            "symfollow" => Left(Clear(MS_NOSYMFOLLOW)),
             o => Right(o),
         })
         .fold((Vec::new(), 0), |(mut opts, flags), next| match next {
-            Left(f) => (opts, flags | f),
+            Left(Set(f)) => (opts, flags | f),
+            Left(Clear(f)) => (opts, flags & !f),
             Right(o) => {
                 opts.push(o);
                 (opts, flags)
@@ -118,25 +142,59 @@ fn parse_mountflag_options(options: impl AsRef<str>) -> (Option<String>, libc::c
     )
 }
 
+/// Reject anything in a filesystem-specific option string (what's left
+/// after [`parse_mountflag_options`] has pulled out the VFS mount flags)
+/// that isn't a real bcachefs option, naming the offending token — rather
+/// than letting it through to `parse_mount_opts`, which silently drops
+/// unknown options with `ignore_unknown: true` and turns typos like
+/// `noaime` into a confusing mount failure.
+fn validate_fs_opts(optstr: &str) -> Result<()> {
+    let known = crate::commands::opts::bch_option_names(bch_bindgen::c::opt_flags::OPT_MOUNT as u32);
+
+    for opt in optstr.split(',') {
+        if opt.is_empty() {
+            continue;
+        }
+        let name = opt.split('=').next().unwrap_or(opt);
+        ensure!(known.contains(&name), "unknown mount option '{}'", name);
+    }
+
+    Ok(())
+}
+
 /// If a user explicitly specifies `unlock_policy` or `passphrase_file` then use
 /// that without falling back to other mechanisms. If these options are not
 /// used, then search for the key or ask for it.
 fn handle_unlock(cli: &Cli, sb: &bch_sb_handle) -> Result<KeyHandle> {
+    let timeout = cli.key_timeout.map(Duration::from_secs);
+    let fd_unlock = cli.passphrase_fd.map(|fd| FdUnlockConfig {
+        // SAFETY: `fd` is a descriptor number handed to us on the command
+        // line by our caller (e.g. via socket activation), which owns it for
+        // our process's lifetime.
+        fd: unsafe { BorrowedFd::borrow_raw(fd) },
+        max_attempts: cli.passphrase_retries,
+        backoff: Duration::from_millis(cli.passphrase_retry_backoff_ms),
+    });
+
     if let Some(policy) = cli.unlock_policy.as_ref() {
-        return policy.apply(sb);
+        return policy.apply(sb, cli.keyring, timeout, fd_unlock);
     }
 
     if let Some(path) = cli.passphrase_file.as_deref() {
-        return Passphrase::new_from_file(path).and_then(|p| KeyHandle::new(sb, &p));
+        return Passphrase::new_from_file(path)
+            .and_then(|p| KeyHandle::new(sb, &p, cli.keyring, timeout));
     }
 
     let uuid = sb.sb().uuid();
     KeyHandle::new_from_search(&uuid)
-        .or_else(|_| Passphrase::new(&uuid).and_then(|p| KeyHandle::new(sb, &p)))
+        .or_else(|_| Passphrase::new(&uuid).and_then(|p| KeyHandle::new(sb, &p, cli.keyring, timeout)))
 }
 
 fn cmd_mount_inner(cli: &Cli) -> Result<()> {
     let (optstr, mountflags) = parse_mountflag_options(&cli.options);
+    if let Some(optstr) = optstr.as_deref() {
+        validate_fs_opts(optstr)?;
+    }
     let opts = bch_bindgen::opts::parse_mount_opts(None, optstr.as_deref(), true)
         .unwrap_or_default();
 
@@ -196,7 +254,34 @@ pub struct Cli {
     #[arg(short = 'k', long = "key_location", value_enum)]
     unlock_policy: Option<UnlockPolicy>,
 
-    /// Device, or UUID=\<UUID\>
+    /// Keyring to add the derived key to. `persistent` survives across login
+    /// sessions for the current user, which is useful for unattended mounts.
+    #[arg(long = "keyring", value_enum, default_value = "user")]
+    keyring: Keyring,
+
+    /// Expire the unlocked key out of the keyring after this many seconds,
+    /// instead of leaving it cached until manually revoked. Useful for
+    /// removable media or shared systems.
+    #[arg(long = "key-timeout")]
+    key_timeout: Option<u64>,
+
+    /// Read the passphrase from this file descriptor (a FIFO or a
+    /// socket-activation fd) instead of a file or the terminal. Used with
+    /// `-k fd`; on an incorrect line the next line is tried, up to
+    /// `--passphrase-retries` lines in all, before giving up.
+    #[arg(long = "passphrase-fd")]
+    passphrase_fd: Option<i32>,
+
+    /// Number of lines to try from `--passphrase-fd` before giving up.
+    #[arg(long = "passphrase-retries", default_value_t = 3)]
+    passphrase_retries: u32,
+
+    /// Delay between retries when reading from `--passphrase-fd`, in milliseconds.
+    #[arg(long = "passphrase-retry-backoff-ms", default_value_t = 0)]
+    passphrase_retry_backoff_ms: u64,
+
+    /// Device, or UUID=\<UUID\>, LABEL=\<label\>, PARTUUID=\<UUID\>, or
+    /// PARTLABEL=\<label\> (as in /etc/fstab)
     dev: String,
 
     /// Where the filesystem should be mounted. If not set, then the filesystem
@@ -208,16 +293,45 @@ pub struct Cli {
     #[arg(short, default_value = "")]
     options: String,
 
-    // FIXME: would be nicer to have `--color[=WHEN]` like diff or ls?
-    /// Force color on/off. Autodetect tty is used to define default:
-    #[arg(short, long, action = clap::ArgAction::Set, default_value_t=stdout().is_terminal())]
-    colorize: bool,
+    /// Colorize output: auto (default, colors only when stdout is a tty),
+    /// always, or never. Overridden by `NO_COLOR`/`CLICOLOR_FORCE` when
+    /// `auto` is in effect.
+    #[arg(long, value_enum, default_value_t = ColorWhen::Auto)]
+    color: ColorWhen,
 
     /// Verbose mode
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum ColorWhen {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Resolve `--color` against `NO_COLOR`/`CLICOLOR_FORCE`, the way `ls`/`diff`
+/// do: an explicit `always`/`never` wins outright, otherwise `auto` defers
+/// to `CLICOLOR_FORCE` (force on), `NO_COLOR` (force off), then whether
+/// stdout is a tty.
+fn resolve_color(when: ColorWhen) -> bool {
+    match when {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => {
+            if std::env::var_os("CLICOLOR_FORCE").is_some_and(|v| v != "0") {
+                true
+            } else if std::env::var_os("NO_COLOR").is_some() {
+                false
+            } else {
+                stdout().is_terminal()
+            }
+        }
+    }
+}
+
 fn check_bcachefs_module() -> bool {
     let path = Path::new("/sys/module/bcachefs");
 
@@ -242,7 +356,7 @@ pub fn mount(mut argv: Vec<String>, symlink_cmd: Option<&str>) -> std::process::
     let cli = Cli::parse_from(argv);
 
     // TODO: centralize this on the top level CLI
-    logging::setup(cli.verbose, cli.colorize);
+    logging::setup(cli.verbose, resolve_color(cli.color));
 
     match cmd_mount_inner(&cli) {
         Ok(_)   => std::process::ExitCode::SUCCESS,
@@ -255,3 +369,47 @@ pub fn mount(mut argv: Vec<String>, symlink_cmd: Option<&str>) -> std::process::
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mountflag_options_splits_flags_from_fs_opts() {
+        let (optstr, flags) = parse_mountflag_options("noatime,nodev,foo,bar=2");
+        assert_eq!(optstr.as_deref(), Some("foo,bar=2"));
+        assert_eq!(flags, libc::MS_NOATIME | libc::MS_NODEV);
+    }
+
+    #[test]
+    fn parse_mountflag_options_clear_overrides_earlier_set() {
+        let (optstr, flags) = parse_mountflag_options("noatime,atime");
+        assert_eq!(optstr, None);
+        assert_eq!(flags, 0);
+    }
+
+    #[test]
+    fn parse_mountflag_options_empty_and_rw_set_no_flags() {
+        let (optstr, flags) = parse_mountflag_options("rw,");
+        assert_eq!(optstr, None);
+        assert_eq!(flags, 0);
+    }
+
+    #[test]
+    fn parse_mountflag_options_ro_sets_rdonly() {
+        let (optstr, flags) = parse_mountflag_options("ro");
+        assert_eq!(optstr, None);
+        assert_eq!(flags, libc::MS_RDONLY);
+    }
+
+    #[test]
+    fn validate_fs_opts_rejects_unknown_option() {
+        let err = validate_fs_opts("not_a_real_opt").unwrap_err();
+        assert!(err.to_string().contains("not_a_real_opt"));
+    }
+
+    #[test]
+    fn validate_fs_opts_ignores_empty_segments() {
+        assert!(validate_fs_opts("").is_ok());
+    }
+}
@@ -0,0 +1,109 @@
+//! Run specific online recovery passes against an already-mounted
+//! filesystem via `BCH_IOCTL_FSCK_ONLINE`'s `recovery_passes` machinery,
+//! without the full fsck scope `fsck_online` runs.
+
+use std::io::Read;
+
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+
+use crate::commands::device::open_dev_by_path_or_index;
+use crate::wrappers::handle::BcachefsHandle;
+
+/// Human pass names mapped to their bit position in the `flags` mask
+/// `BCH_IOCTL_FSCK_ONLINE` expects. Mirrors the kernel's
+/// `BCH_RECOVERY_PASS_*` ordering.
+const RECOVERY_PASSES: &[(&str, u32)] = &[
+    ("alloc_read", 0),
+    ("alloc_write", 1),
+    ("extents_read", 2),
+    ("journal_replay", 3),
+    ("check_alloc_info", 4),
+    ("check_lrus", 5),
+    ("check_btree_backpointers", 6),
+    ("check_extents_to_backpointers", 7),
+    ("check_alloc_to_lru_refs", 8),
+    ("fs_freespace", 9),
+    ("check_snapshot_trees", 10),
+    ("check_snapshots", 11),
+    ("check_subvols", 12),
+    ("check_subvol_children", 13),
+    ("delete_dead_snapshots", 14),
+    ("check_root", 15),
+    ("check_unreachable_inodes", 16),
+    ("check_directory_structure", 17),
+    ("check_nlinks", 18),
+    ("resume_logged_ops", 19),
+];
+
+fn pass_mask(name: &str) -> Result<u64> {
+    RECOVERY_PASSES
+        .iter()
+        .find(|(pass, _)| *pass == name)
+        .map(|(_, bit)| 1u64 << bit)
+        .ok_or_else(|| {
+            let names: Vec<&str> = RECOVERY_PASSES.iter().map(|(pass, _)| *pass).collect();
+            anyhow!("unknown recovery pass '{}' (expected one of: {})", name, names.join(", "))
+        })
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Run specific online recovery passes against a mounted filesystem")]
+pub struct Cli {
+    /// Recovery pass to run; may be repeated to run several passes in one call
+    #[arg(long = "pass", required = true)]
+    passes: Vec<String>,
+
+    /// Device path or numeric device index
+    device: String,
+
+    /// Filesystem path (required when specifying device by index)
+    path: Option<String>,
+}
+
+pub fn recovery_pass(argv: Vec<String>) -> Result<()> {
+    let cli = Cli::parse_from(argv);
+
+    let mut flags = 0u64;
+    for pass in &cli.passes {
+        flags |= pass_mask(pass)?;
+    }
+
+    let (handle, _dev_idx) = open_dev_by_path_or_index(&cli.device, cli.path.as_deref())?;
+
+    run(&handle, flags)
+}
+
+fn run(handle: &BcachefsHandle, flags: u64) -> Result<()> {
+    let mut fd = handle.recovery_pass_online(flags)
+        .map_err(|e| anyhow!("BCH_IOCTL_FSCK_ONLINE failed: {}", e))?;
+
+    let mut pending = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let n = fd.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        pending.extend_from_slice(&chunk[..n]);
+
+        while let Some(pos) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            print!("{}", String::from_utf8_lossy(&line));
+        }
+    }
+
+    let exit_code = if pending.len() >= 4 {
+        let start = pending.len() - 4;
+        i32::from_le_bytes(pending[start..].try_into().unwrap())
+    } else {
+        0
+    };
+
+    if exit_code != 0 {
+        bail!("recovery pass run found errors (exit code {})", exit_code);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+//! `bcachefs rmap`: answer "what owns these physical blocks", in the spirit
+//! of `thin_rmap`. Scans `BTREE_ID_backpointers` for the bucket range
+//! backing a sector range on a device, then for each backpointer pointing at
+//! an extent, chases into the owning btree to recover the inode and
+//! logical offset.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use bch_bindgen::bcachefs;
+use bch_bindgen::btree::{BtreeIter, BtreeIterFlags, BtreeTrans};
+use bch_bindgen::c;
+use bch_bindgen::c::bch_degraded_actions;
+use bch_bindgen::fs::Fs;
+use bch_bindgen::opt_set;
+use clap::Parser;
+
+use crate::wrappers::handle::BcachefsHandle;
+
+/// Split a sector offset on a device into (bucket number, offset within
+/// the bucket), given that device's bucket size in sectors.
+fn sector_to_bucket(bucket_size: u64, sector: u64) -> (u64, u64) {
+    (sector / bucket_size, sector % bucket_size)
+}
+
+/// A resolved extent target: which inode and logical offset a backpointer's
+/// physical extent belongs to. A single physical extent can be visible from
+/// more than one snapshot, so a backpointer may chase to several of these.
+struct ExtentTarget {
+    inode: u64,
+    logical_offset: u64,
+    length: u32,
+}
+
+/// Chase a backpointer's target position into its owning btree to recover
+/// the inode/logical-offset a physical extent backs, across every snapshot
+/// that references it.
+fn resolve_extent_targets(
+    fs: &Fs,
+    btree_id: bcachefs::btree_id,
+    pos: c::bpos,
+) -> Result<Vec<ExtentTarget>> {
+    let trans = BtreeTrans::new(fs);
+    let mut iter = BtreeIter::new(&trans, btree_id, pos, BtreeIterFlags::ALL_SNAPSHOTS);
+
+    let mut out = Vec::new();
+    while let Some(k) = iter.peek_and_restart()? {
+        if k.k.p.inode != pos.inode || k.k.p.offset != pos.offset {
+            break;
+        }
+        if k.k.type_ != bcachefs::bch_bkey_type::KEY_TYPE_deleted as u8
+            && k.k.type_ != bcachefs::bch_bkey_type::KEY_TYPE_whiteout as u8
+        {
+            out.push(ExtentTarget {
+                inode: k.k.p.inode,
+                logical_offset: k.k.p.offset.saturating_sub(k.k.size as u64),
+                length: k.k.size,
+            });
+        }
+        iter.advance();
+    }
+    Ok(out)
+}
+
+/// Find and print what owns the backing store for a physical device range.
+#[derive(Parser, Debug)]
+pub struct Cli {
+    /// Device to query
+    device: PathBuf,
+
+    /// Start of the range, in 512-byte sectors
+    #[arg(long)]
+    offset: u64,
+
+    /// Length of the range, in 512-byte sectors
+    #[arg(long)]
+    length: u64,
+}
+
+pub fn rmap(argv: Vec<String>) -> Result<()> {
+    let cli = Cli::parse_from(argv);
+
+    let handle = BcachefsHandle::open(&cli.device)
+        .with_context(|| format!("opening '{}'", cli.device.display()))?;
+    let dev_idx = handle.dev_idx();
+    if dev_idx < 0 {
+        anyhow::bail!("'{}' does not appear to be a block device member", cli.device.display());
+    }
+    let dev_idx = dev_idx as u32;
+    let bucket_size = handle.dev_usage(dev_idx).context("querying device usage")?.bucket_size as u64;
+
+    let mut fs_opts = bcachefs::bch_opts::default();
+    opt_set!(fs_opts, noexcl, 1);
+    opt_set!(fs_opts, nochanges, 1);
+    opt_set!(fs_opts, read_only, 1);
+    opt_set!(fs_opts, norecovery, 1);
+    opt_set!(fs_opts, degraded, bch_degraded_actions::BCH_DEGRADED_very as u8);
+    opt_set!(fs_opts, errors, bcachefs::bch_error_actions::BCH_ON_ERROR_continue as u8);
+
+    let fs = Fs::open(&[cli.device.clone()], fs_opts)?;
+
+    let (start_bucket, start_bucket_off) = sector_to_bucket(bucket_size, cli.offset);
+    let (end_bucket, end_bucket_off) = sector_to_bucket(bucket_size, cli.offset + cli.length);
+
+    let start_pos = unsafe {
+        c::bch2_bucket_pos_to_bp(
+            fs.raw,
+            bch_bindgen::spos(dev_idx as u64, start_bucket, 0),
+            start_bucket_off,
+        )
+    };
+    let end_pos = unsafe {
+        c::bch2_bucket_pos_to_bp(
+            fs.raw,
+            bch_bindgen::spos(dev_idx as u64, end_bucket, 0),
+            end_bucket_off,
+        )
+    };
+
+    let trans = BtreeTrans::new(&fs);
+    let mut iter =
+        BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_backpointers, start_pos, BtreeIterFlags::empty());
+
+    let mut found = 0usize;
+    while let Some(k) = iter.peek_max(end_pos)? {
+        if k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_backpointer as u8 {
+            let bp = unsafe { &*(k.v as *const c::bch_val as *const c::bch_backpointer) };
+            let btree_id: bcachefs::btree_id = unsafe { std::mem::transmute(bp.btree_id as u32) };
+
+            if bp.level > 0 {
+                println!(
+                    "{} -> (btree={}, level={}, btree node, len={})",
+                    k.k.p.offset, crate::wrappers::accounting::btree_id_str(bp.btree_id as u32), bp.level, bp.bucket_len,
+                );
+            } else {
+                let targets = resolve_extent_targets(&fs, btree_id, bp.pos)?;
+                if targets.is_empty() {
+                    println!(
+                        "{} -> (btree={}, inode=?, offset=?, len={}) [extent not found, stale backpointer?]",
+                        k.k.p.offset, crate::wrappers::accounting::btree_id_str(bp.btree_id as u32), bp.bucket_len,
+                    );
+                }
+                for t in targets {
+                    println!(
+                        "{} -> (btree={}, inode={}, offset={}, len={})",
+                        k.k.p.offset,
+                        crate::wrappers::accounting::btree_id_str(bp.btree_id as u32),
+                        t.inode,
+                        t.logical_offset,
+                        t.length,
+                    );
+                }
+            }
+            found += 1;
+        }
+        iter.advance();
+    }
+
+    if found == 0 {
+        eprintln!("no backpointers found in that range");
+    }
+
+    Ok(())
+}
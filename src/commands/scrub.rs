@@ -4,11 +4,14 @@ use std::process;
 use std::thread;
 use std::time::{Duration, Instant};
 
+use log::warn;
+
 use anyhow::{Context, Result};
 use bch_bindgen::c::{
     bch_data_type, bch_ioctl_data, bch_ioctl_data_event_ret, bch_ioctl_data_progress,
 };
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
 
 use crate::util::fmt_bytes_human;
 use crate::wrappers::handle::BcachefsHandle;
@@ -37,6 +40,29 @@ fn read_data_event(fd: &mut std::fs::File) -> io::Result<(u8, u8, bch_ioctl_data
     Ok((event_type, event_ret, p))
 }
 
+/// Raise the soft `RLIMIT_NOFILE` limit up to the hard limit, so that
+/// opening one progress fd per device doesn't run into EMFILE on
+/// large multi-device arrays. No-op if the soft limit already matches
+/// the hard limit; logs but does not abort on failure (e.g. non-root
+/// callers can't exceed `rlim_max`).
+fn raise_nofile_limit() {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        warn!("getrlimit(RLIMIT_NOFILE) failed: {}", io::Error::last_os_error());
+        return;
+    }
+
+    if limit.rlim_cur >= limit.rlim_max {
+        return;
+    }
+
+    limit.rlim_cur = limit.rlim_max;
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+        warn!("setrlimit(RLIMIT_NOFILE, {}) failed: {}", limit.rlim_cur, io::Error::last_os_error());
+    }
+}
+
 fn start_scrub(ioctl_fd: i32, dev_idx: u32, data_types: u32) -> Result<std::fs::File> {
     let mut cmd = bch_ioctl_data::default();
     cmd.op = bch_bindgen::c::bch_data_ops::BCH_DATA_OP_scrub as u16;
@@ -86,6 +112,61 @@ impl ScrubDev {
             pct,
             status)
     }
+
+    fn status_str(&self) -> &'static str {
+        if self.progress_fd.is_some() {
+            "running"
+        } else if self.ret_status == bch_ioctl_data_event_ret::BCH_IOCTL_DATA_EVENT_RET_device_offline as u8 {
+            "offline"
+        } else {
+            "complete"
+        }
+    }
+
+    fn to_json_line(&self, rate: u64) -> ScrubDevJson {
+        let percent = if self.total > 0 {
+            (self.done * 100 / self.total) as u32
+        } else {
+            0
+        };
+
+        ScrubDevJson {
+            device: self.name.clone(),
+            checked_bytes: self.done << 9,
+            corrected_bytes: self.corrected << 9,
+            uncorrected_bytes: self.uncorrected << 9,
+            total_bytes: self.total << 9,
+            percent,
+            rate_bytes_per_sec: rate,
+            status: self.status_str(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ScrubDevJson {
+    device:                 String,
+    checked_bytes:          u64,
+    corrected_bytes:        u64,
+    uncorrected_bytes:      u64,
+    total_bytes:            u64,
+    percent:                u32,
+    rate_bytes_per_sec:     u64,
+    status:                 &'static str,
+}
+
+#[derive(Serialize)]
+struct ScrubSummaryJson {
+    exit_code:      i32,
+    corrected:      bool,
+    uncorrected:    bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
 }
 
 #[derive(Parser, Debug)]
@@ -95,6 +176,10 @@ pub struct Cli {
     #[arg(short, long)]
     metadata: bool,
 
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     /// Filesystem path or device
     filesystem: String,
 }
@@ -102,6 +187,8 @@ pub struct Cli {
 pub fn scrub(argv: Vec<String>) -> Result<()> {
     let cli = Cli::parse_from(argv);
 
+    raise_nofile_limit();
+
     let data_types: u32 = if cli.metadata {
         1 << (bch_data_type::BCH_DATA_btree as u32)
     } else {
@@ -140,12 +227,16 @@ pub fn scrub(argv: Vec<String>) -> Result<()> {
         }
     }
 
-    let dev_names: Vec<&str> = scrub_devs.iter().map(|d| d.name.as_str()).collect();
-    println!("Starting scrub on {} devices: {}",
-        scrub_devs.len(), dev_names.join(" "));
+    let json = cli.format == OutputFormat::Json;
+
+    if !json {
+        let dev_names: Vec<&str> = scrub_devs.iter().map(|d| d.name.as_str()).collect();
+        println!("Starting scrub on {} devices: {}",
+            scrub_devs.len(), dev_names.join(" "));
 
-    println!("{:<16} {:>12} {:>12} {:>12} {:>12} {:>6}",
-        "device", "checked", "corrected", "uncorrected", "total", "");
+        println!("{:<16} {:>12} {:>12} {:>12} {:>12} {:>6}",
+            "device", "checked", "corrected", "uncorrected", "total", "");
+    }
 
     let mut exit_code = 0i32;
     let mut last = Instant::now();
@@ -157,9 +248,11 @@ pub fn scrub(argv: Vec<String>) -> Result<()> {
 
         let mut all_done = true;
         let mut lines: Vec<String> = Vec::new();
+        let mut json_lines: Vec<ScrubDevJson> = Vec::new();
 
         for dev in &mut scrub_devs {
             let mut rate = 0u64;
+            let mut skip_event = false;
 
             if let Some(ref mut fd) = dev.progress_fd {
                 match read_data_event(fd) {
@@ -167,28 +260,27 @@ pub fn scrub(argv: Vec<String>) -> Result<()> {
                         // Skip non-progress events
                         if event_type != 0 {
                             all_done = false;
-                            lines.push(dev.format_line(0));
-                            continue;
-                        }
-
-                        if ns_elapsed > 0 {
-                            rate = p.sectors_done.wrapping_sub(dev.done)
-                                .checked_shl(9).unwrap_or(0)
-                                .saturating_mul(1_000_000_000)
-                                .checked_div(ns_elapsed).unwrap_or(0);
-                        }
-
-                        dev.done = p.sectors_done;
-                        dev.corrected = p.sectors_error_corrected;
-                        dev.uncorrected = p.sectors_error_uncorrected;
-                        dev.total = p.sectors_total;
-
-                        if dev.corrected > 0 { exit_code |= 2; }
-                        if dev.uncorrected > 0 { exit_code |= 4; }
-
-                        if event_ret != 0 {
-                            dev.ret_status = event_ret;
-                            dev.progress_fd = None;
+                            skip_event = true;
+                        } else {
+                            if ns_elapsed > 0 {
+                                rate = p.sectors_done.wrapping_sub(dev.done)
+                                    .checked_shl(9).unwrap_or(0)
+                                    .saturating_mul(1_000_000_000)
+                                    .checked_div(ns_elapsed).unwrap_or(0);
+                            }
+
+                            dev.done = p.sectors_done;
+                            dev.corrected = p.sectors_error_corrected;
+                            dev.uncorrected = p.sectors_error_uncorrected;
+                            dev.total = p.sectors_total;
+
+                            if dev.corrected > 0 { exit_code |= 2; }
+                            if dev.uncorrected > 0 { exit_code |= 4; }
+
+                            if event_ret != 0 {
+                                dev.ret_status = event_ret;
+                                dev.progress_fd = None;
+                            }
                         }
                     }
                     Err(_) => {
@@ -197,31 +289,46 @@ pub fn scrub(argv: Vec<String>) -> Result<()> {
                 }
             }
 
-            lines.push(dev.format_line(rate));
+            if json {
+                json_lines.push(dev.to_json_line(if skip_event { 0 } else { rate }));
+            } else {
+                lines.push(dev.format_line(if skip_event { 0 } else { rate }));
+            }
 
             if dev.progress_fd.is_some() {
                 all_done = false;
             }
         }
 
-        let stdout = io::stdout();
-        let mut out = stdout.lock();
+        if json {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            for line in &json_lines {
+                writeln!(out, "{}", serde_json::to_string(line)?)?;
+            }
+            out.flush()?;
+        } else {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
 
-        if !first {
-            for i in 0..scrub_devs.len() {
-                if i > 0 { write!(out, "\x1b[1A")?; }
-                write!(out, "\x1b[2K\r")?;
+            if !first {
+                for i in 0..scrub_devs.len() {
+                    if i > 0 { write!(out, "\x1b[1A")?; }
+                    write!(out, "\x1b[2K\r")?;
+                }
             }
-        }
 
-        for (i, line) in lines.iter().enumerate() {
-            write!(out, "{}", line)?;
-            if i < lines.len() - 1 { writeln!(out)?; }
+            for (i, line) in lines.iter().enumerate() {
+                write!(out, "{}", line)?;
+                if i < lines.len() - 1 { writeln!(out)?; }
+            }
+            out.flush()?;
         }
-        out.flush()?;
 
         if all_done {
-            writeln!(io::stdout())?;
+            if !json {
+                writeln!(io::stdout())?;
+            }
             break;
         }
 
@@ -230,6 +337,15 @@ pub fn scrub(argv: Vec<String>) -> Result<()> {
         thread::sleep(Duration::from_secs(1));
     }
 
+    if json {
+        let summary = ScrubSummaryJson {
+            exit_code,
+            corrected: exit_code & 2 != 0,
+            uncorrected: exit_code & 4 != 0,
+        };
+        println!("{}", serde_json::to_string(&summary)?);
+    }
+
     if exit_code != 0 {
         process::exit(exit_code);
     }
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bch_bindgen::bcachefs;
 use bch_bindgen::btree::BtreeIter;
 use bch_bindgen::btree::BtreeIterFlags;
@@ -8,17 +8,24 @@ use bch_bindgen::fs::Fs;
 use bch_bindgen::opt_set;
 use bch_bindgen::c::bch_degraded_actions;
 use clap::Parser;
+use std::collections::{HashMap, HashSet};
 use std::io::{stdout, IsTerminal};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::logging;
+use crate::wrappers::accounting::DiskAccountingPos;
+use crate::wrappers::handle::BcachefsHandle;
+use crate::wrappers::send_format::{self, Codec};
+
+/// disk_accounting_type::BCH_DISK_ACCOUNTING_inum as a type mask bit.
+const ACCOUNTING_INUM: u32 = 1 << 8;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum ChangeKind {
-    Add,
-    Modify,
-    Delete,
+    Add = 0,
+    Modify = 1,
+    Delete = 2,
 }
 
 impl std::fmt::Display for ChangeKind {
@@ -35,6 +42,8 @@ impl std::fmt::Display for ChangeKind {
 pub struct Change {
     pub kind: ChangeKind,
     pub path: String,
+    /// Sector delta between base and child snapshot, in `--stat` mode.
+    pub size_delta: Option<i64>,
 }
 
 /// Get dirent name from a bch_dirent - the name follows the fixed struct
@@ -62,12 +71,24 @@ fn get_dirent_name(v: &c::bch_val, k: &c::bkey) -> Option<String> {
     }
 }
 
-/// Look up the original dirent name at a given position from an older snapshot
-fn lookup_dirent_name_at_pos(
-    fs: &Fs,
-    inode: u64,
-    offset: u64,
-) -> Option<String> {
+/// Get the target inode number from a bch_dirent - d_inum is the first
+/// field after bch_val (0 bytes), read directly rather than through the
+/// bindgen union name to avoid depending on its generated field layout.
+fn get_dirent_inum(v: &c::bch_val) -> u64 {
+    unsafe {
+        let ptr = v as *const c::bch_val as *const u8;
+        u64::from_le_bytes(std::slice::from_raw_parts(ptr, 8).try_into().unwrap())
+    }
+}
+
+/// Name and target inode number of the dirent at a given position.
+struct DirentInfo {
+    name: String,
+    inum: u64,
+}
+
+/// Look up the original dirent at a given position from an older snapshot.
+fn lookup_dirent_at_pos(fs: &Fs, inode: u64, offset: u64) -> Option<DirentInfo> {
     let trans = BtreeTrans::new(fs);
     let pos = bch_bindgen::spos(inode, offset, 0);
     let flags = BtreeIterFlags::ALL_SNAPSHOTS;
@@ -85,7 +106,179 @@ fn lookup_dirent_name_at_pos(
         }
 
         if k.k.type_ == bcachefs::bch_bkey_type::KEY_TYPE_dirent as u8 {
-            return get_dirent_name(k.v, k.k);
+            return Some(DirentInfo {
+                name: get_dirent_name(k.v, k.k)?,
+                inum: get_dirent_inum(k.v),
+            });
+        }
+
+        iter.advance();
+    }
+
+    None
+}
+
+/// Look up the original dirent name at a given position from an older snapshot
+fn lookup_dirent_name_at_pos(fs: &Fs, inode: u64, offset: u64) -> Option<String> {
+    lookup_dirent_at_pos(fs, inode, offset).map(|d| d.name)
+}
+
+/// True if `key_snap` falls within the snapshot range this diff covers:
+/// exactly `child_snapshot` when there's no base, or `(base, child]`
+/// (remembering higher snapshot ID = older) when there is.
+fn snapshot_in_range(key_snap: u32, child_snapshot: u32, base_snapshot: Option<u32>) -> bool {
+    match base_snapshot {
+        None => key_snap == child_snapshot,
+        Some(base) => key_snap >= child_snapshot && key_snap < base,
+    }
+}
+
+/// Unpack a `KEY_TYPE_inode_v3` key into its backpointer (bi_dir, bi_dir_offset).
+fn inode_backpointer(k: &c::bkey, v: &c::bch_val) -> Option<(u64, u64)> {
+    if k.type_ != bcachefs::bch_bkey_type::KEY_TYPE_inode_v3 as u8 {
+        return None;
+    }
+
+    let mut unpacked: c::bch_inode_unpacked = unsafe { std::mem::zeroed() };
+    let bkey_s_c = c::bkey_s_c { k, v };
+
+    if unsafe { c::bch2_inode_unpack(bkey_s_c, &mut unpacked) } != 0 {
+        return None;
+    }
+
+    if unpacked.bi_dir == 0 {
+        None
+    } else {
+        Some((unpacked.bi_dir, unpacked.bi_dir_offset))
+    }
+}
+
+/// True if `inum` already existed outside the diffed range, i.e. it's an
+/// edit to a pre-existing file rather than a brand new one (which the
+/// dirent-level scan already reports as an `Add`).
+fn inode_existed_before(
+    fs: &Fs,
+    inum: u64,
+    child_snapshot: u32,
+    base_snapshot: Option<u32>,
+) -> bool {
+    let trans = BtreeTrans::new(fs);
+    let pos = bch_bindgen::spos(inum, 0, 0);
+    let flags = BtreeIterFlags::ALL_SNAPSHOTS;
+
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_inodes, pos, flags);
+
+    while let Ok(Some(k)) = iter.peek_and_restart() {
+        if k.k.p.inode != inum {
+            break;
+        }
+
+        if !snapshot_in_range(k.k.p.snapshot, child_snapshot, base_snapshot) {
+            return true;
+        }
+
+        iter.advance();
+    }
+
+    false
+}
+
+/// Look up the (bi_dir, bi_dir_offset) backpointer stored on `inum`'s inode,
+/// regardless of which snapshot it's visible in (mirrors the snapshot-agnostic
+/// lookup already done by `lookup_dirent_name_at_pos`).
+fn lookup_inode_backpointer(fs: &Fs, inum: u64) -> Option<(u64, u64)> {
+    let trans = BtreeTrans::new(fs);
+    let pos = bch_bindgen::spos(inum, 0, 0);
+    let flags = BtreeIterFlags::ALL_SNAPSHOTS;
+
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_inodes, pos, flags);
+
+    while let Some(k) = iter.peek_and_restart().ok()? {
+        if k.k.p.inode != inum {
+            break;
+        }
+
+        if let Some(bp) = inode_backpointer(k.k, k.v) {
+            return Some(bp);
+        }
+
+        iter.advance();
+    }
+
+    None
+}
+
+/// Resolve the fully-qualified path of a directory inode by walking its
+/// `bi_dir` backpointer chain up to `root_inode`. Resolved fragments are
+/// cached in `path_cache` so ancestors shared by many changed files are
+/// only walked once.
+fn resolve_dir_path(
+    fs: &Fs,
+    path_cache: &mut HashMap<u64, String>,
+    root_inode: u64,
+    dir_inode: u64,
+) -> String {
+    if dir_inode == root_inode {
+        return String::new();
+    }
+
+    if let Some(cached) = path_cache.get(&dir_inode) {
+        return cached.clone();
+    }
+
+    let resolved = match lookup_inode_backpointer(fs, dir_inode) {
+        Some((parent_dir, parent_offset)) => match lookup_dirent_name_at_pos(fs, parent_dir, parent_offset) {
+            Some(name) => {
+                let parent_path = resolve_dir_path(fs, path_cache, root_inode, parent_dir);
+                format!("{}/{}", parent_path, name)
+            }
+            // Backpointer exists but its dirent doesn't resolve (deleted, out
+            // of range): fall back to something nameable rather than dropping it.
+            None => format!("/?{}", dir_inode),
+        },
+        // No backpointer: treat as an (unexpected) root.
+        None => String::new(),
+    };
+
+    path_cache.insert(dir_inode, resolved.clone());
+    resolved
+}
+
+/// Resolve the full path of a dirent named `name` inside directory `dir_inode`.
+fn resolve_full_path(
+    fs: &Fs,
+    path_cache: &mut HashMap<u64, String>,
+    root_inode: u64,
+    dir_inode: u64,
+    name: &str,
+) -> String {
+    format!("{}/{}", resolve_dir_path(fs, path_cache, root_inode, dir_inode), name)
+}
+
+/// Resolve the path of a modified inode via its stored directory
+/// backpointer, looking the owning inode up at `child_snapshot`.
+fn resolve_modified_path(
+    fs: &Fs,
+    path_cache: &mut HashMap<u64, String>,
+    root_inode: u64,
+    inum: u64,
+    child_snapshot: u32,
+) -> Option<String> {
+    let trans = BtreeTrans::new(fs);
+    let pos = bch_bindgen::spos(inum, 0, 0);
+    let flags = BtreeIterFlags::ALL_SNAPSHOTS;
+
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_inodes, pos, flags);
+
+    while let Some(k) = iter.peek_and_restart().ok()? {
+        if k.k.p.inode != inum {
+            break;
+        }
+
+        if k.k.p.snapshot == child_snapshot {
+            let (dir, offset) = inode_backpointer(k.k, k.v)?;
+            let name = lookup_dirent_name_at_pos(fs, dir, offset)?;
+            return Some(resolve_full_path(fs, path_cache, root_inode, dir, &name));
         }
 
         iter.advance();
@@ -94,16 +287,118 @@ fn lookup_dirent_name_at_pos(
     None
 }
 
+/// Collect the set of inode numbers touched by the inodes/extents btrees
+/// within the diffed snapshot range, excluding inodes created fresh in
+/// that same range (those are already reported as `Add`).
+fn find_modified_inodes(
+    fs: &Fs,
+    child_snapshot: u32,
+    base_snapshot: Option<u32>,
+) -> Result<HashSet<u64>> {
+    let mut touched = HashSet::new();
+
+    for btree in [bcachefs::btree_id::BTREE_ID_inodes, bcachefs::btree_id::BTREE_ID_extents] {
+        let trans = BtreeTrans::new(fs);
+        let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+        let mut iter = BtreeIter::new(&trans, btree, bch_bindgen::POS_MIN, flags);
+
+        while let Some(k) = iter.peek_and_restart()? {
+            if snapshot_in_range(k.k.p.snapshot, child_snapshot, base_snapshot) {
+                touched.insert(k.k.p.inode);
+            }
+            iter.advance();
+        }
+    }
+
+    let mut modified = HashSet::new();
+    for inum in touched {
+        if inode_existed_before(fs, inum, child_snapshot, base_snapshot) {
+            modified.insert(inum);
+        }
+    }
+
+    Ok(modified)
+}
+
+/// Per-inode sector totals behind `--stat`'s size deltas: current totals
+/// come from the accounting subsystem (`BCH_DISK_ACCOUNTING_inum`), which is
+/// fs-wide rather than scoped to a snapshot range, so there's no ioctl
+/// equivalent for "as of the base snapshot" — that side is reconstructed by
+/// summing extents that existed outside the diffed range directly.
+struct SectorDeltas {
+    current: HashMap<u64, u64>,
+    before: HashMap<u64, u64>,
+}
+
+impl SectorDeltas {
+    fn delta(&self, inum: u64) -> i64 {
+        self.current.get(&inum).copied().unwrap_or(0) as i64
+            - self.before.get(&inum).copied().unwrap_or(0) as i64
+    }
+}
+
+/// Sum extent sectors per inode for keys outside the diffed snapshot range,
+/// i.e. the "before" picture `inode_existed_before` already checks for.
+fn inode_sectors_before(
+    fs: &Fs,
+    child_snapshot: u32,
+    base_snapshot: Option<u32>,
+) -> Result<HashMap<u64, u64>> {
+    let mut sectors: HashMap<u64, u64> = HashMap::new();
+    let trans = BtreeTrans::new(fs);
+    let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
+    let mut iter = BtreeIter::new(&trans, bcachefs::btree_id::BTREE_ID_extents, bch_bindgen::POS_MIN, flags);
+
+    while let Some(k) = iter.peek_and_restart()? {
+        if !snapshot_in_range(k.k.p.snapshot, child_snapshot, base_snapshot) {
+            *sectors.entry(k.k.p.inode).or_insert(0) += k.k.size as u64;
+        }
+        iter.advance();
+    }
+
+    Ok(sectors)
+}
+
+/// Build the sector-count maps behind `--stat`, querying current per-inode
+/// totals from `device`'s accounting subsystem and the pre-diff totals from
+/// a direct extents scan.
+fn collect_sector_deltas(
+    fs: &Fs,
+    device: &Path,
+    child_snapshot: u32,
+    base_snapshot: Option<u32>,
+) -> Result<SectorDeltas> {
+    let handle = BcachefsHandle::open(device)
+        .map_err(|e| anyhow!("opening '{}' for accounting: {}", device.display(), e))?;
+    let result = handle
+        .query_accounting(ACCOUNTING_INUM)
+        .map_err(|e| anyhow!("query_accounting failed: {}", e))?;
+
+    let mut current = HashMap::new();
+    for entry in &result.entries {
+        if let DiskAccountingPos::Inum { inum } = entry.pos {
+            current.insert(inum, entry.counter(0));
+        }
+    }
+
+    let before = inode_sectors_before(fs, child_snapshot, base_snapshot)?;
+
+    Ok(SectorDeltas { current, before })
+}
+
 /// Find all changes made in snapshots between base_snapshot and child_snapshot
 /// If base_snapshot is None, only finds changes at exactly child_snapshot (immediate parent diff)
 /// If base_snapshot is Some, finds changes in range (base_snapshot, child_snapshot]
 fn find_snapshot_changes(
     fs: &Fs,
+    root_inode: u64,
     child_snapshot: u32,
     base_snapshot: Option<u32>,
+    sizes: Option<&SectorDeltas>,
 ) -> Result<Vec<Change>> {
     let trans = BtreeTrans::new(fs);
     let mut changes = Vec::new();
+    let mut path_cache: HashMap<u64, String> = HashMap::new();
 
     let flags = BtreeIterFlags::PREFETCH | BtreeIterFlags::ALL_SNAPSHOTS;
 
@@ -117,17 +412,7 @@ fn find_snapshot_changes(
     while let Some(k) = iter.peek_and_restart()? {
         let key_snap = k.k.p.snapshot;
 
-        // Filter based on whether we have a base or not
-        let dominated = match base_snapshot {
-            // No base: only keys at exactly child's snapshot ID
-            None => key_snap != child_snapshot,
-            // With base: keys where base < snap <= child
-            // In bcachefs, higher snapshot ID = older, so:
-            // child_snapshot <= key_snap < base_snapshot
-            Some(base) => key_snap < child_snapshot || key_snap >= base,
-        };
-
-        if dominated {
+        if !snapshot_in_range(key_snap, child_snapshot, base_snapshot) {
             iter.advance();
             continue;
         }
@@ -137,18 +422,17 @@ fn find_snapshot_changes(
         // Whiteouts = deletions
         if key_type == bcachefs::bch_bkey_type::KEY_TYPE_whiteout as u8 ||
            key_type == bcachefs::bch_bkey_type::KEY_TYPE_hash_whiteout as u8 {
-            let name = if key_type == bcachefs::bch_bkey_type::KEY_TYPE_hash_whiteout as u8 {
-                get_dirent_name(k.v, k.k)
+            let dirent = if key_type == bcachefs::bch_bkey_type::KEY_TYPE_hash_whiteout as u8 {
+                get_dirent_name(k.v, k.k).map(|name| (name, get_dirent_inum(k.v)))
             } else {
-                lookup_dirent_name_at_pos(fs, k.k.p.inode, k.k.p.offset)
+                lookup_dirent_at_pos(fs, k.k.p.inode, k.k.p.offset).map(|d| (d.name, d.inum))
             };
 
-            if let Some(name) = name {
+            if let Some((name, inum)) = dirent {
                 if name != "." && name != ".." {
-                    changes.push(Change {
-                        kind: ChangeKind::Delete,
-                        path: format!("/{}", name),
-                    });
+                    let path = resolve_full_path(fs, &mut path_cache, root_inode, k.k.p.inode, &name);
+                    let size_delta = sizes.map(|s| s.delta(inum));
+                    changes.push(Change { kind: ChangeKind::Delete, path, size_delta });
                 }
             }
             iter.advance();
@@ -159,10 +443,9 @@ fn find_snapshot_changes(
         if key_type == bcachefs::bch_bkey_type::KEY_TYPE_dirent as u8 {
             if let Some(name) = get_dirent_name(k.v, k.k) {
                 if name != "." && name != ".." {
-                    changes.push(Change {
-                        kind: ChangeKind::Add,
-                        path: format!("/{}", name),
-                    });
+                    let path = resolve_full_path(fs, &mut path_cache, root_inode, k.k.p.inode, &name);
+                    let size_delta = sizes.map(|s| s.delta(get_dirent_inum(k.v)));
+                    changes.push(Change { kind: ChangeKind::Add, path, size_delta });
                 }
             }
         }
@@ -170,6 +453,16 @@ fn find_snapshot_changes(
         iter.advance();
     }
 
+    // Inodes/extents whose data or metadata changed in-range, but whose
+    // dirent wasn't touched (rewrites, truncates, appends) show up as
+    // Modify rather than Add/Delete.
+    for inum in find_modified_inodes(fs, child_snapshot, base_snapshot)? {
+        if let Some(path) = resolve_modified_path(fs, &mut path_cache, root_inode, inum, child_snapshot) {
+            let size_delta = sizes.map(|s| s.delta(inum));
+            changes.push(Change { kind: ChangeKind::Modify, path, size_delta });
+        }
+    }
+
     Ok(changes)
 }
 
@@ -242,13 +535,25 @@ fn get_subvolume_info(fs: &Fs, subvol_id: u32) -> Result<(u64, u32)> {
     anyhow::bail!("Subvolume {} not found", subvol_id)
 }
 
+/// Read a regular file's current contents for embedding in a `--send`
+/// record. This needs the snapshot to be reachable via a mounted path
+/// (`--path`); `--id`-only invocations have no mountpoint to read file data
+/// through, so those records ship with an empty payload.
+fn read_file_contents(mount_path: Option<&Path>, relative_path: &str) -> Vec<u8> {
+    mount_path
+        .and_then(|mount| std::fs::read(mount.join(relative_path.trim_start_matches('/'))).ok())
+        .unwrap_or_default()
+}
+
 /// Compare two subvolumes and return list of changes
 fn diff_subvolumes(
     fs: &Fs,
+    root_inode: u64,
     child_snapshot: u32,
     base_snapshot: Option<u32>,
+    sizes: Option<&SectorDeltas>,
 ) -> Result<Vec<Change>> {
-    let mut changes = find_snapshot_changes(fs, child_snapshot, base_snapshot)?;
+    let mut changes = find_snapshot_changes(fs, root_inode, child_snapshot, base_snapshot, sizes)?;
     changes.sort_by(|a, b| a.path.cmp(&b.path));
     Ok(changes)
 }
@@ -273,6 +578,20 @@ pub struct Cli {
     #[arg(long, short)]
     json: bool,
 
+    /// Annotate each change with its sector-size delta (`git diff --stat`-style),
+    /// using filesystem accounting rather than reading extent data
+    #[arg(long)]
+    stat: bool,
+
+    /// Emit a self-describing binary change stream (for replication) to
+    /// stdout instead of the usual text/JSON diff
+    #[arg(long, conflicts_with = "json")]
+    send: bool,
+
+    /// Compression codec for --send records
+    #[arg(long, default_value = "zstd")]
+    codec: String,
+
     /// Verbose output
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
@@ -314,7 +633,7 @@ fn cmd_diff_inner(opt: &Cli) -> Result<()> {
 
     let fs = Fs::open(&opt.devices, fs_opts)?;
 
-    let (_root, snap_id) = get_subvolume_info(&fs, subvol_id)?;
+    let (root, snap_id) = get_subvolume_info(&fs, subvol_id)?;
 
     // Get base snapshot ID if specified
     let base_snap = match opt.base {
@@ -325,7 +644,34 @@ fn cmd_diff_inner(opt: &Cli) -> Result<()> {
         None => None,
     };
 
-    let changes = diff_subvolumes(&fs, snap_id, base_snap)?;
+    let sizes = if opt.stat {
+        Some(collect_sector_deltas(&fs, &opt.devices[0], snap_id, base_snap)?)
+    } else {
+        None
+    };
+
+    let changes = diff_subvolumes(&fs, root, snap_id, base_snap, sizes.as_ref())?;
+
+    if opt.send {
+        let codec = Codec::from_name(&opt.codec)
+            .ok_or_else(|| anyhow!("unknown --send codec '{}'", opt.codec))?;
+
+        let records: Vec<send_format::Record> = changes
+            .iter()
+            .map(|change| {
+                let data = match change.kind {
+                    ChangeKind::Add | ChangeKind::Modify => {
+                        read_file_contents(opt.path.as_deref(), &change.path)
+                    }
+                    ChangeKind::Delete => Vec::new(),
+                };
+                send_format::Record { kind: change.kind as u8, path: change.path.clone(), data }
+            })
+            .collect();
+
+        send_format::write_stream(&mut stdout().lock(), codec, snap_id, base_snap, &records)?;
+        return Ok(());
+    }
 
     if opt.json {
         print!("{{\"changes\":[");
@@ -338,12 +684,19 @@ fn cmd_diff_inner(opt: &Cli) -> Result<()> {
                 ChangeKind::Modify => "modify",
                 ChangeKind::Delete => "delete",
             };
-            print!("{{\"kind\":\"{}\",\"path\":{:?}}}", kind_str, change.path);
+            print!("{{\"kind\":\"{}\",\"path\":{:?}", kind_str, change.path);
+            if let Some(delta) = change.size_delta {
+                print!(",\"size_delta\":{}", delta);
+            }
+            print!("}}");
         }
         println!("]}}");
     } else {
         for change in &changes {
-            println!("{} {}", change.kind, change.path);
+            match change.size_delta {
+                Some(delta) => println!("{} {} ({:+})", change.kind, change.path, delta),
+                None => println!("{} {}", change.kind, change.path),
+            }
         }
     }
 
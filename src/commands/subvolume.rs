@@ -4,6 +4,7 @@ use anyhow::{Context, Result};
 use bch_bindgen::c::BCH_SUBVOL_SNAPSHOT_RO;
 use clap::{Parser, Subcommand};
 
+use crate::commands::subvol_delta;
 use crate::wrappers::handle::BcachefsHandle;
 
 #[derive(Parser, Debug)]
@@ -37,6 +38,9 @@ enum Subcommands {
         source:    Option<PathBuf>,
         dest:      PathBuf,
     },
+
+    /// Report which extents differ between two snapshots
+    Delta(subvol_delta::Cli),
 }
 
 pub fn subvolume(argv: Vec<String>) -> Result<()> {
@@ -101,6 +105,7 @@ pub fn subvolume(argv: Vec<String>) -> Result<()> {
                 .context("Failed to snapshot the subvolume")?;
             }
         }
+        Subcommands::Delta(opt) => subvol_delta::cmd_delta(&opt)?,
     }
 
     Ok(())
@@ -1,19 +1,30 @@
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::{self, IsTerminal, Write as IoWrite};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, ensure, Context, Result};
 use clap::{Parser, ValueEnum};
 use crossterm::{
-    cursor,
-    event::{self, Event, KeyCode, KeyModifiers},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers, MouseButton,
+        MouseEvent, MouseEventKind,
+    },
     execute,
-    terminal::{self, ClearType},
+    terminal,
+};
+use log::debug;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    widgets::{Cell as RtCell, Paragraph, Row, Table, TableState},
+    Frame, Terminal,
 };
-use owo_colors::OwoColorize;
 use serde::{Deserialize, Serialize};
 
 use crate::wrappers::handle::BcachefsHandle;
@@ -48,6 +59,7 @@ struct TimeStats {
     between_ewma_ns:    EwmaStats,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 struct StatEntry {
     name:   String,
     stats:  TimeStats,
@@ -94,6 +106,17 @@ impl Cell<'_> {
             _ => Ordering::Equal,
         }
     }
+
+    /// Unpadded value, for feeding into a ratatui `Row`/`Cell` — column
+    /// alignment there comes from the table's own `Constraint`s, not string
+    /// padding.
+    fn raw(&self) -> String {
+        match self {
+            Cell::Name(s)      => s.to_string(),
+            Cell::Count(n)     => n.to_string(),
+            Cell::Duration(ns) => fmt_duration(*ns),
+        }
+    }
 }
 
 // Column definitions
@@ -143,16 +166,150 @@ fn stat_columns(entry: &StatEntry) -> Vec<String> {
     (0..NUM_COLS).map(|i| entry.cell(i).format()).collect()
 }
 
+/// Fixed column widths for the TUI table (name column, then one per stat).
+/// `column_spacing(1)` is added between columns by the `Table` widget itself.
+fn column_widths() -> Vec<u16> {
+    std::iter::once(NAME_WIDTH as u16)
+        .chain(std::iter::repeat(COL_WIDTH as u16).take(NUM_COLS - 1))
+        .collect()
+}
+
+fn column_constraints() -> Vec<Constraint> {
+    column_widths().into_iter().map(Constraint::Length).collect()
+}
+
 // Structured data: sections within a filesystem snapshot
 
+#[derive(Serialize, Deserialize, Clone)]
 struct Section {
-    label:   &'static str,
+    label:   String,
     entries: Vec<StatEntry>,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 struct FsSnapshot {
-    label:    String,
-    sections: Vec<Section>,
+    label:        String,
+    sections:     Vec<Section>,
+    // "dev-N" -> backing block device name (e.g. "sda"), resolved via the
+    // sysfs `block` symlink under each device's directory. Empty unless
+    // per-device stats were collected, and not recorded by `--record`
+    // (there's no sysfs to resolve it from on replay).
+    #[serde(skip)]
+    dev_backing:  HashMap<String, String>,
+}
+
+// Host-level load, for correlating a bcachefs latency spike against
+// saturated underlying storage (systemstat takes the same diffed-/proc
+// approach for its Linux platform backend). Gated behind `--host-stats`
+// since /proc may be inaccessible (e.g. in a container).
+
+struct CpuSample {
+    idle:  u64,
+    total: u64,
+}
+
+struct DiskSample {
+    io_ticks: u64,
+}
+
+struct DiskLoad {
+    util_pct:  f64,
+    in_flight: u64,
+}
+
+struct HostStats {
+    cpu_pct:      Option<f64>,
+    mem_used_pct: Option<f64>,
+    disks:        HashMap<String, DiskLoad>,
+}
+
+fn read_cpu_sample() -> Result<CpuSample> {
+    let stat = fs::read_to_string("/proc/stat").context("reading /proc/stat")?;
+    let line = stat.lines().next().context("/proc/stat is empty")?;
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1) // "cpu"
+        .filter_map(|f| f.parse().ok())
+        .collect();
+    // user nice system idle iowait irq softirq steal [guest guest_nice]
+    let idle = fields.get(3).copied().unwrap_or(0) + fields.get(4).copied().unwrap_or(0);
+    let total = fields.iter().sum();
+    Ok(CpuSample { idle, total })
+}
+
+fn read_mem_used_pct() -> Result<f64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").context("reading /proc/meminfo")?;
+    let mut total = None;
+    let mut available = None;
+    for line in meminfo.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("MemTotal:") => total = fields.next().and_then(|v| v.parse::<u64>().ok()),
+            Some("MemAvailable:") => available = fields.next().and_then(|v| v.parse::<u64>().ok()),
+            _ => {}
+        }
+    }
+    let (total, available) = (
+        total.context("MemTotal not found in /proc/meminfo")?,
+        available.context("MemAvailable not found in /proc/meminfo")?,
+    );
+    Ok(if total > 0 { (total - available) as f64 / total as f64 * 100.0 } else { 0.0 })
+}
+
+/// Raw per-device samples from `/proc/diskstats`, keyed by device name
+/// (e.g. "sda"). `in_flight` (field 12) needs no diffing; `io_ticks`
+/// (field 13, ms spent doing I/Os) is diffed across intervals to get a
+/// util% the same way `iostat` does.
+fn read_disk_samples() -> Result<HashMap<String, (DiskSample, u64)>> {
+    let diskstats = fs::read_to_string("/proc/diskstats").context("reading /proc/diskstats")?;
+    let mut out = HashMap::new();
+    for line in diskstats.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 14 { continue }
+        let name = fields[2].to_string();
+        let in_flight: u64 = fields[11].parse().unwrap_or(0);
+        let io_ticks: u64 = fields[12].parse().unwrap_or(0);
+        out.insert(name, (DiskSample { io_ticks }, in_flight));
+    }
+    Ok(out)
+}
+
+/// Diff this interval's `/proc/stat` and `/proc/diskstats` samples against
+/// the ones stored in `state` to get a CPU%, memory%, and per-device
+/// util%/queue-depth snapshot. Returns `None` (rather than erroring out the
+/// whole TUI) if `/proc` isn't readable.
+fn collect_host_stats(state: &mut TuiState) -> Option<HostStats> {
+    let mem_used_pct = read_mem_used_pct().ok();
+
+    let cpu_pct = read_cpu_sample().ok().and_then(|cur| {
+        let pct = state.prev_cpu.as_ref().and_then(|prev| {
+            let delta_total = cur.total.saturating_sub(prev.total);
+            (delta_total > 0)
+                .then(|| 100.0 * (1.0 - cur.idle.saturating_sub(prev.idle) as f64 / delta_total as f64))
+        });
+        state.prev_cpu = Some(cur);
+        pct
+    });
+
+    let disks = read_disk_samples().ok().map(|cur| {
+        cur.iter()
+            .filter_map(|(name, (sample, in_flight))| {
+                let prev = state.prev_disks.get(name)?;
+                let delta_ticks = sample.io_ticks.saturating_sub(prev.io_ticks);
+                let util_pct = delta_ticks as f64 / state.interval.as_millis().max(1) as f64 * 100.0;
+                Some((name.clone(), DiskLoad { util_pct, in_flight: *in_flight }))
+            })
+            .collect::<HashMap<_, _>>()
+    });
+    state.prev_disks = read_disk_samples().map_or(HashMap::new(), |m| {
+        m.into_iter().map(|(k, (s, _))| (k, s)).collect()
+    });
+
+    if mem_used_pct.is_none() && cpu_pct.is_none() && disks.is_none() {
+        return None;
+    }
+
+    Some(HostStats { cpu_pct, mem_used_pct, disks: disks.unwrap_or_default() })
 }
 
 // Sysfs reading
@@ -202,9 +359,18 @@ fn read_time_stats(sysfs_path: &Path) -> Result<Vec<StatEntry>> {
     Ok(entries)
 }
 
-fn read_device_latency_stats(sysfs_path: &Path) -> Result<Vec<StatEntry>> {
+/// Resolve the block device backing a bcachefs `dev-N` sysfs entry, via the
+/// `block` symlink sysfs exposes under the device directory (the same
+/// indirection `lsblk`/udev use to map a holder back to its disk).
+fn resolve_backing_device(dev_path: &Path) -> Option<String> {
+    let link = fs::read_link(dev_path.join("block")).ok()?;
+    link.file_name().map(|n| n.to_string_lossy().to_string())
+}
+
+fn read_device_latency_stats(sysfs_path: &Path) -> Result<(Vec<StatEntry>, HashMap<String, String>)> {
     let mut entries = Vec::new();
-    let Ok(dir) = fs::read_dir(sysfs_path) else { return Ok(entries) };
+    let mut backing = HashMap::new();
+    let Ok(dir) = fs::read_dir(sysfs_path) else { return Ok((entries, backing)) };
 
     for entry in dir {
         let entry = entry?;
@@ -212,6 +378,10 @@ fn read_device_latency_stats(sysfs_path: &Path) -> Result<Vec<StatEntry>> {
         if !name.starts_with("dev-") { continue }
 
         let dev_path = entry.path();
+        if let Some(dev) = resolve_backing_device(&dev_path) {
+            backing.insert(name.clone(), dev);
+        }
+
         for (suffix, label) in [("io_latency_stats_read_json", "read"),
                                  ("io_latency_stats_write_json", "write")] {
             let stat_path = dev_path.join(suffix);
@@ -228,7 +398,7 @@ fn read_device_latency_stats(sysfs_path: &Path) -> Result<Vec<StatEntry>> {
         }
     }
     entries.sort_by(|a, b| a.name.cmp(&b.name));
-    Ok(entries)
+    Ok((entries, backing))
 }
 
 // Data collection
@@ -241,14 +411,14 @@ fn collect_stats(sysfs_paths: &[PathBuf], show_devices: bool) -> Result<Vec<FsSn
             .partition(|e: &StatEntry| !e.name.starts_with("blocked_"));
 
         let mut sections = vec![
-            Section { label: "Operations",  entries: ops },
-            Section { label: "Slowpath",    entries: blocked },
+            Section { label: "Operations".to_string(),  entries: ops },
+            Section { label: "Slowpath".to_string(),    entries: blocked },
         ];
+        let mut dev_backing = HashMap::new();
         if show_devices {
-            sections.push(Section {
-                label:   "Per-device IO latency",
-                entries: read_device_latency_stats(path)?,
-            });
+            let (entries, backing) = read_device_latency_stats(path)?;
+            dev_backing = backing;
+            sections.push(Section { label: "Per-device IO latency".to_string(), entries });
         }
 
         snaps.push(FsSnapshot {
@@ -256,6 +426,7 @@ fn collect_stats(sysfs_paths: &[PathBuf], show_devices: bool) -> Result<Vec<FsSn
                 .map(|n| n.to_string_lossy().to_string())
                 .unwrap_or_default(),
             sections,
+            dev_backing,
         });
     }
     Ok(snaps)
@@ -276,6 +447,63 @@ fn print_json(snaps: &[FsSnapshot]) -> Result<()> {
     Ok(())
 }
 
+// Recording and replay
+
+/// One `--record`ed line: a timestamp plus the same `FsSnapshot` set
+/// `print_json` would emit.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    ts:        f64,
+    snapshots: Vec<FsSnapshot>,
+}
+
+fn snapshot_counts(snaps: &[FsSnapshot]) -> HashMap<(String, String, String), u64> {
+    snaps
+        .iter()
+        .flat_map(|snap| snap.sections.iter().map(move |sec| (snap, sec)))
+        .flat_map(|(snap, sec)| sec.entries.iter().map(move |e| (snap, sec, e)))
+        .map(|(snap, sec, e)| ((snap.label.clone(), sec.label.clone(), e.name.clone()), e.stats.count))
+        .collect()
+}
+
+/// Append `snaps` to `path` as one newline-delimited JSON `Record`, unless
+/// every entry's count is unchanged from `prev_counts` — the same "don't
+/// rewrite if unchanged" discipline decomp-toolkit applies to its config
+/// handling, so idle periods don't bloat the log.
+fn record_snapshots(
+    path: &Path,
+    snaps: &[FsSnapshot],
+    prev_counts: &mut Option<HashMap<(String, String, String), u64>>,
+) -> Result<()> {
+    let counts = snapshot_counts(snaps);
+    if prev_counts.as_ref() == Some(&counts) {
+        return Ok(());
+    }
+    *prev_counts = Some(counts);
+
+    let ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let line = serde_json::to_string(&Record { ts, snapshots: snaps.to_vec() })?;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening {}", path.display()))?;
+    writeln!(file, "{line}").with_context(|| format!("writing {}", path.display()))
+}
+
+fn load_replay(path: &Path) -> Result<Vec<Record>> {
+    let content = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str::<Record>(line).context("parsing recorded snapshot"))
+        .collect()
+}
+
 // CLI
 
 #[derive(ValueEnum, Clone, Copy, Debug, Default)]
@@ -332,6 +560,26 @@ pub struct Cli {
     #[arg(short = 'i', long, default_value = "1")]
     interval: f64,
 
+    /// Show a host load panel (CPU%, memory%, per-disk util%/queue depth
+    /// from /proc) alongside bcachefs latency, to help tell a latency spike
+    /// in MEAN_RECENT apart from saturated underlying storage. Requires
+    /// /proc to be readable, which isn't always true in a container.
+    #[arg(long)]
+    host_stats: bool,
+
+    /// Append a newline-delimited JSON snapshot to this file on every
+    /// refresh, for offline analysis later with `--replay`. A sample is
+    /// skipped when every entry's count is identical to the last one
+    /// recorded, so idle periods don't bloat the log.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Replay a `--record`ed log instead of reading live kernel counters.
+    /// Drives the normal one-shot/TUI renderers over the recorded timeline;
+    /// in the TUI, n/b step forward/back and p pauses/resumes auto-advance.
+    #[arg(long, conflicts_with = "filesystem")]
+    replay: Option<PathBuf>,
+
     /// Filesystem UUID, device, or mount point (default: all)
     filesystem: Option<String>,
 }
@@ -367,28 +615,89 @@ fn display_stats(snaps: Vec<FsSnapshot>, cli: &Cli) -> Result<()> {
 // Interactive TUI
 
 struct TuiState {
-    sort_col:       usize,
-    reverse:        bool,
-    show_all:       bool,
-    show_devices:   bool,
-    paused:         bool,
-    interval:       Duration,
-    cursor:         usize,
-    scroll_offset:  usize,
+    sort_col:     usize,
+    reverse:      bool,
+    show_all:     bool,
+    show_devices: bool,
+    paused:       bool,
+    interval:     Duration,
+    cursor:       usize,
+    // Header row rects from the last frame, for mapping a mouse click back to
+    // a column (and so a `sort_col`). Rebuilt every `draw`.
+    header_hits:  Vec<HeaderHit>,
+    // Rate/derivative mode: shows ops/sec and the true mean latency of the
+    // last interval instead of the kernel's cumulative counters, the same
+    // way `top` diffs two `/proc/stat` samples to get CPU load.
+    rate_mode:    bool,
+    prev_stats:   HashMap<(String, String), PrevStat>,
+    prev_instant: Option<Instant>,
+    // Host load panel (`--host-stats`): diffed across intervals the same
+    // way `prev_stats` above diffs bcachefs counters.
+    host_stats:   bool,
+    prev_cpu:     Option<CpuSample>,
+    prev_disks:   HashMap<String, DiskSample>,
 }
 
-fn format_tui_header(sort_col: usize, reverse: bool) -> String {
-    let arrow = if reverse { "\u{25b2}" } else { "\u{25bc}" };
-    let mut out = String::from("  ");
-    for (i, col) in header_columns().iter().enumerate() {
-        if i > 0 { out.push(' '); }
-        if i == sort_col {
-            out.push_str(&format!("{}{}", col.reversed(), arrow.reversed()));
-        } else {
-            out.push_str(col);
+struct HeaderHit {
+    rect:   Rect,
+    widths: Vec<u16>,
+}
+
+#[derive(Clone, Copy)]
+struct PrevStat {
+    count: u64,
+    total: u64,
+}
+
+enum Delta {
+    /// No prior sample for this entry yet (just toggled rate mode, or the
+    /// entry just appeared).
+    NoPrev,
+    /// `count` or `total` went backwards since the prior sample (kernel
+    /// remount reset the counters).
+    Reset,
+    Value { ops_per_sec: f64, interval_mean_ns: Option<u64> },
+}
+
+fn compute_delta(entry: &StatEntry, section_label: &str, state: &TuiState, elapsed_secs: f64) -> Delta {
+    let key = (section_label.to_string(), entry.name.clone());
+    let Some(prev) = state.prev_stats.get(&key) else { return Delta::NoPrev };
+
+    let count = entry.stats.count;
+    let total = entry.stats.duration_ns.total;
+    if count < prev.count || total < prev.total {
+        return Delta::Reset;
+    }
+
+    let delta_count = count - prev.count;
+    let delta_total = total - prev.total;
+
+    Delta::Value {
+        ops_per_sec:       if elapsed_secs > 0.0 { delta_count as f64 / elapsed_secs } else { 0.0 },
+        interval_mean_ns:  (delta_count > 0).then(|| delta_total / delta_count),
+    }
+}
+
+/// Column text for the TUI table: in rate mode, COUNT becomes an ops/sec
+/// figure and MEAN becomes the true mean latency of the interval since the
+/// last refresh; every other column (and both of these, outside rate mode)
+/// falls back to the plain cumulative value.
+fn tui_cell_text(entry: &StatEntry, section_label: &str, col: usize, state: &TuiState, elapsed_secs: f64) -> String {
+    if !state.rate_mode || (col != 1 && col != 5) {
+        return entry.cell(col).raw();
+    }
+
+    match compute_delta(entry, section_label, state, elapsed_secs) {
+        Delta::NoPrev => entry.cell(col).raw(),
+        Delta::Reset => if col == 1 { "0".to_string() } else { "\u{2014}".to_string() },
+        Delta::Value { ops_per_sec, interval_mean_ns } => {
+            if col == 1 {
+                format!("{:.1}/s", ops_per_sec)
+            } else {
+                interval_mean_ns.map(fmt_duration).unwrap_or_else(|| "\u{2014}".to_string())
+            }
         }
     }
-    out
 }
 
 fn prepare_snaps(snaps: &mut [FsSnapshot], state: &TuiState) {
@@ -402,76 +711,156 @@ fn prepare_snaps(snaps: &mut [FsSnapshot], state: &TuiState) {
     }
 }
 
-fn build_frame(snaps: &[FsSnapshot], state: &TuiState, multi: bool) -> (Vec<String>, Option<usize>) {
-    let mut lines = Vec::new();
-    let mut cursor_line = None;
+fn count_total_rows(snaps: &[FsSnapshot]) -> usize {
+    snaps.iter().flat_map(|s| &s.sections).map(|sec| sec.entries.len()).sum()
+}
+
+/// Map a flat row cursor to the `(snapshot, section, row-within-section)` it
+/// points at, so the right per-section `Table` can highlight it.
+fn cursor_position(snaps: &[FsSnapshot], cursor: usize) -> Option<(usize, usize, usize)> {
     let mut row = 0usize;
+    for (si, snap) in snaps.iter().enumerate() {
+        for (ci, section) in snap.sections.iter().enumerate() {
+            let len = section.entries.len();
+            if cursor < row + len {
+                return Some((si, ci, cursor - row));
+            }
+            row += len;
+        }
+    }
+    None
+}
+
+fn header_row(sort_col: usize, reverse: bool, rate_mode: bool) -> Row<'static> {
+    let arrow = if reverse { "\u{25b2}" } else { "\u{25bc}" };
+    let cells: Vec<RtCell> = COLUMNS
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let name = match (rate_mode, i) {
+                (true, 1) => "OPS/S",
+                (true, 5) => "INT_MEAN",
+                _ => name,
+            };
+            if i == sort_col {
+                RtCell::from(format!("{name} {arrow}")).style(Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                RtCell::from(name)
+            }
+        })
+        .collect();
+    Row::new(cells).style(Style::default().add_modifier(Modifier::BOLD))
+}
+
+/// For a "Per-device IO latency" entry (named e.g. "dev-0/read"), look up
+/// its backing block device and that device's current load, to annotate the
+/// NAME cell with "(sda: 42% util, q=3)" right next to its latency figures.
+fn device_load_suffix(entry_name: &str, dev_backing: Option<&HashMap<String, String>>, host: Option<&HostStats>) -> Option<String> {
+    let dev_backing = dev_backing?;
+    let host = host?;
+    let dev_label = entry_name.split('/').next()?;
+    let backing = dev_backing.get(dev_label)?;
+    let load = host.disks.get(backing)?;
+    Some(format!(" ({backing}: {:.0}% util, q={})", load.util_pct, load.in_flight))
+}
 
+fn section_table<'a>(
+    section: &'a Section,
+    state: &TuiState,
+    elapsed_secs: f64,
+    dev_backing: Option<&'a HashMap<String, String>>,
+    host: Option<&HostStats>,
+) -> Table<'a> {
+    let is_device_section = section.label == "Per-device IO latency";
+    let rows = section.entries.iter().map(|e| {
+        let cells = (0..NUM_COLS).map(|c| {
+            let mut text = tui_cell_text(e, &section.label, c, state, elapsed_secs);
+            if c == 0 && is_device_section {
+                if let Some(suffix) = device_load_suffix(&e.name, dev_backing, host) {
+                    text.push_str(&suffix);
+                }
+            }
+            RtCell::from(text)
+        });
+        Row::new(cells.collect::<Vec<_>>())
+    });
+
+    Table::new(rows, column_constraints())
+        .header(header_row(state.sort_col, state.reverse, state.rate_mode))
+        .column_spacing(1)
+        .highlight_symbol("\u{25ba} ")
+}
+
+fn draw(frame: &mut Frame, snaps: &[FsSnapshot], state: &mut TuiState, multi: bool, elapsed_secs: f64, host: Option<&HostStats>) {
+    let area = frame.area();
     let pause = if state.paused { " PAUSED" } else { "" };
-    lines.push(format!(
-        "bcachefs timestats ({}s{})  q:quit  \u{2190}\u{2192}:sort column  r:reverse  a:show all  d:devices  p:pause  1-9:interval",
-        state.interval.as_secs(), pause,
-    ));
-    lines.push(String::new());
+    let rate = if state.rate_mode { " RATE" } else { "" };
+    let status = format!(
+        "bcachefs timestats ({}s{}{})  q:quit  click/\u{2190}\u{2192}:sort column  r:reverse  a:show all  d:devices  p:pause  m:rate  1-9:interval",
+        state.interval.as_secs(), pause, rate,
+    );
+
+    let mut constraints = vec![Constraint::Length(1)];
+    if host.is_some() { constraints.push(Constraint::Length(1)); }
+    constraints.extend(snaps.iter().map(|_| Constraint::Min(0)));
+    let chunks = Layout::default().direction(Direction::Vertical).constraints(constraints).split(area);
+
+    frame.render_widget(Paragraph::new(status), chunks[0]);
+
+    let mut next_chunk = 1;
+    if let Some(host) = host {
+        let cpu = host.cpu_pct.map_or("?".to_string(), |p| format!("{p:.0}%"));
+        let mem = host.mem_used_pct.map_or("?".to_string(), |p| format!("{p:.0}%"));
+        frame.render_widget(Paragraph::new(format!("host load: cpu {cpu}  mem {mem}")), chunks[next_chunk]);
+        next_chunk += 1;
+    }
 
-    let header = format_tui_header(state.sort_col, state.reverse);
+    let selected = cursor_position(snaps, state.cursor);
+    state.header_hits.clear();
 
-    for snap in snaps {
-        if multi { lines.push(format!("{}:", snap.label)); }
+    for (si, snap) in snaps.iter().enumerate() {
+        let snap_area = chunks[next_chunk + si];
 
-        let mut first = true;
+        let mut section_constraints = Vec::new();
+        if multi { section_constraints.push(Constraint::Length(1)); }
         for section in &snap.sections {
             if section.entries.is_empty() { continue }
-
-            if !first { lines.push(String::new()); }
-            first = false;
-            lines.push(format!("{}:", section.label));
-            lines.push(header.clone());
-            for entry in &section.entries {
-                let cols = stat_columns(entry).join(" ");
-                if row == state.cursor {
-                    cursor_line = Some(lines.len());
-                    lines.push(format!("{}{}", "\u{25ba} ".bold(), cols.bold()));
-                } else {
-                    lines.push(format!("  {}", cols));
-                }
-                row += 1;
-            }
+            section_constraints.push(Constraint::Length(1)); // section label
+            section_constraints.push(Constraint::Length((section.entries.len() + 1) as u16)); // header + rows
         }
-        lines.push(String::new());
-    }
+        if section_constraints.is_empty() { continue }
 
-    (lines, cursor_line)
-}
+        let sub = Layout::default().direction(Direction::Vertical).constraints(section_constraints).split(snap_area);
 
-fn render_frame(
-    stdout: &mut io::Stdout,
-    snaps: &[FsSnapshot],
-    state: &mut TuiState,
-    multi: bool,
-) -> io::Result<()> {
-    let (_, term_h) = terminal::size().unwrap_or((120, 40));
-    let visible = (term_h as usize).saturating_sub(1).max(1);
-
-    let (lines, cursor_line) = build_frame(snaps, state, multi);
-
-    if let Some(cl) = cursor_line {
-        if cl < state.scroll_offset {
-            state.scroll_offset = cl;
-        } else if cl >= state.scroll_offset + visible {
-            state.scroll_offset = cl - visible + 1;
+        let mut idx = 0usize;
+        if multi {
+            frame.render_widget(Paragraph::new(format!("{}:", snap.label)), sub[idx]);
+            idx += 1;
         }
-    }
 
-    execute!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
-    for line in lines.iter().skip(state.scroll_offset).take(visible) {
-        write!(stdout, "{}\r\n", line)?;
-    }
-    stdout.flush()
-}
+        for (ci, section) in snap.sections.iter().enumerate() {
+            if section.entries.is_empty() { continue }
 
-fn count_total_rows(snaps: &[FsSnapshot]) -> usize {
-    snaps.iter().flat_map(|s| &s.sections).map(|sec| sec.entries.len()).sum()
+            frame.render_widget(Paragraph::new(format!("{}:", section.label)), sub[idx]);
+            idx += 1;
+
+            let table_area = sub[idx];
+            idx += 1;
+
+            state.header_hits.push(HeaderHit {
+                rect:   Rect { height: 1, ..table_area },
+                widths: column_widths(),
+            });
+
+            let mut table_state = TableState::default();
+            if let Some((s2, c2, row)) = selected {
+                if s2 == si && c2 == ci { table_state.select(Some(row)); }
+            }
+
+            let table = section_table(section, state, elapsed_secs, Some(&snap.dev_backing), host);
+            frame.render_stateful_widget(table, table_area, &mut table_state);
+        }
+    }
 }
 
 fn handle_key(state: &mut TuiState, key: KeyCode, modifiers: KeyModifiers, total_rows: usize) -> bool {
@@ -486,57 +875,240 @@ fn handle_key(state: &mut TuiState, key: KeyCode, modifiers: KeyModifiers, total
         KeyCode::Char('a') => state.show_all = !state.show_all,
         KeyCode::Char('d') => state.show_devices = !state.show_devices,
         KeyCode::Char('p') => state.paused = !state.paused,
+        KeyCode::Char('m') => state.rate_mode = !state.rate_mode,
         KeyCode::Char(c @ '1'..='9') => state.interval = Duration::from_secs((c as u64) - ('0' as u64)),
         _ => {}
     }
     false
 }
 
+/// Translate a left-click on a header row into a `sort_col` change, using the
+/// rects recorded by `draw` on the previous frame.
+fn handle_mouse(state: &mut TuiState, mouse: MouseEvent) {
+    if !matches!(mouse.kind, MouseEventKind::Down(MouseButton::Left)) { return }
+
+    for hit in &state.header_hits {
+        let r = hit.rect;
+        if mouse.row != r.y || mouse.column < r.x || mouse.column >= r.x + r.width { continue }
+
+        let mut x = mouse.column - r.x;
+        for (i, w) in hit.widths.iter().enumerate() {
+            if x < *w {
+                state.sort_col = i.min(NUM_COLS - 1);
+                return;
+            }
+            x = x.saturating_sub(w + 1); // +1 for column_spacing
+        }
+    }
+}
+
+/// Watch `SYSFS_BASE` for directory create/remove (i.e. a filesystem being
+/// mounted or unmounted), the same way yazi uses `notify` to watch
+/// directories for external changes. The returned watcher must be kept
+/// alive for as long as events are wanted; events are delivered as unit
+/// values on the channel, since all we need is "something changed, rescan".
+fn watch_sysfs_base() -> Result<(RecommendedWatcher, mpsc::Receiver<()>)> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            if matches!(event.kind, notify::EventKind::Create(_) | notify::EventKind::Remove(_)) {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    watcher.watch(Path::new(SYSFS_BASE), RecursiveMode::NonRecursive)?;
+    Ok((watcher, rx))
+}
+
 fn run_interactive(cli: Cli, sysfs_paths: Vec<PathBuf>) -> Result<()> {
-    let mut stdout = io::stdout();
     let mut state = TuiState {
-        sort_col:      cli.sort.col_index(),
-        reverse:       false,
-        show_all:      cli.all,
-        show_devices:  !cli.no_device_stats,
-        paused:        false,
-        interval:      Duration::from_secs_f64(cli.interval),
-        cursor:        0,
-        scroll_offset: 0,
+        sort_col:     cli.sort.col_index(),
+        reverse:      false,
+        show_all:     cli.all,
+        show_devices: !cli.no_device_stats,
+        paused:       false,
+        interval:     Duration::from_secs_f64(cli.interval),
+        cursor:       0,
+        header_hits:  Vec::new(),
+        rate_mode:    false,
+        prev_stats:   HashMap::new(),
+        prev_instant: None,
+        host_stats:   cli.host_stats,
+        prev_cpu:     None,
+        prev_disks:   HashMap::new(),
     };
 
+    // Only "all filesystems" mode benefits from watching for mount/unmount —
+    // a single explicitly-named filesystem's sysfs path doesn't change.
+    // `_fs_watcher` just needs to live as long as this function; only the
+    // receiver is polled below.
+    let mut _fs_watcher: Option<RecommendedWatcher> = None;
+    let fs_events = if cli.filesystem.is_none() {
+        match watch_sysfs_base() {
+            Ok((watcher, rx)) => {
+                _fs_watcher = Some(watcher);
+                Some(rx)
+            }
+            Err(e) => {
+                debug!("not watching {} for mount/unmount events: {}", SYSFS_BASE, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut sysfs_paths = sysfs_paths;
+    let mut record_prev = None;
+
     terminal::enable_raw_mode()?;
-    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let mut stdout = io::stdout();
+    execute!(stdout, terminal::EnterAlternateScreen, EnableMouseCapture)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
 
     let result = (|| -> Result<()> {
         loop {
-            let mut snaps = collect_stats(&sysfs_paths, state.show_devices)
-                .unwrap_or_default();
+            if let Some(rx) = &fs_events {
+                if rx.try_recv().is_ok() {
+                    while rx.try_recv().is_ok() {} // drain the rest of this burst
+                    if let Ok(paths) = find_all_sysfs_dirs() {
+                        sysfs_paths = paths;
+                    }
+                }
+            }
+            let multi = sysfs_paths.len() > 1;
+
+            let mut snaps = collect_stats(&sysfs_paths, state.show_devices).unwrap_or_default();
+
+            if let Some(path) = cli.record.as_deref() {
+                record_snapshots(path, &snaps, &mut record_prev)?;
+            }
+
             prepare_snaps(&mut snaps, &state);
             let total_rows = count_total_rows(&snaps);
             if total_rows > 0 && state.cursor >= total_rows {
                 state.cursor = total_rows - 1;
             }
 
-            render_frame(&mut stdout, &snaps, &mut state, sysfs_paths.len() > 1)?;
+            let elapsed_secs = state.prev_instant.map_or(0.0, |i| i.elapsed().as_secs_f64());
+
+            let host = if state.host_stats { collect_host_stats(&mut state) } else { None };
+
+            terminal.draw(|frame| draw(frame, &snaps, &mut state, multi, elapsed_secs, host.as_ref()))?;
+
+            state.prev_stats = snaps
+                .iter()
+                .flat_map(|snap| &snap.sections)
+                .flat_map(|sec| sec.entries.iter().map(move |e| (sec.label.as_str(), e)))
+                .map(|(label, e)| {
+                    (
+                        (label.to_string(), e.name.clone()),
+                        PrevStat { count: e.stats.count, total: e.stats.duration_ns.total },
+                    )
+                })
+                .collect();
+            state.prev_instant = Some(Instant::now());
 
             if event::poll(state.interval)? {
-                if let Event::Key(key) = event::read()? {
-                    if handle_key(&mut state, key.code, key.modifiers, total_rows) { break }
+                match event::read()? {
+                    Event::Key(key) => if handle_key(&mut state, key.code, key.modifiers, total_rows) { break },
+                    Event::Mouse(mouse) => handle_mouse(&mut state, mouse),
+                    _ => {}
                 }
                 while event::poll(Duration::ZERO)? { let _ = event::read()?; }
             }
 
             if state.paused {
+                match event::read()? {
+                    Event::Key(key) => if handle_key(&mut state, key.code, key.modifiers, total_rows) { break },
+                    Event::Mouse(mouse) => handle_mouse(&mut state, mouse),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    let _ = execute!(terminal.backend_mut(), DisableMouseCapture, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+    result
+}
+
+/// Drive the normal TUI renderer over a `--record`ed log instead of live
+/// kernel counters, turning the tool into a post-mortem latency analyzer:
+/// `n`/`b` step forward/back through the timeline, `p` pauses/resumes
+/// auto-advance (reusing the existing pause toggle). Rate-mode (`m`) deltas
+/// and sort/filter keys all work the same as in live mode; the host load
+/// panel is unavailable since it isn't part of the recorded format.
+fn run_replay(cli: Cli, records: Vec<Record>) -> Result<()> {
+    ensure!(!records.is_empty(), "no recorded snapshots to replay");
+
+    let mut state = TuiState {
+        sort_col:     cli.sort.col_index(),
+        reverse:      false,
+        show_all:     cli.all,
+        show_devices: !cli.no_device_stats,
+        paused:       true,
+        interval:     Duration::from_secs_f64(cli.interval),
+        cursor:       0,
+        header_hits:  Vec::new(),
+        rate_mode:    false,
+        prev_stats:   HashMap::new(),
+        prev_instant: None,
+        host_stats:   false,
+        prev_cpu:     None,
+        prev_disks:   HashMap::new(),
+    };
+    let mut idx = 0usize;
+
+    terminal::enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, terminal::EnterAlternateScreen, EnableMouseCapture)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = (|| -> Result<()> {
+        loop {
+            let mut snaps = records[idx].snapshots.clone();
+            prepare_snaps(&mut snaps, &state);
+            let total_rows = count_total_rows(&snaps);
+            if total_rows > 0 && state.cursor >= total_rows {
+                state.cursor = total_rows - 1;
+            }
+
+            let elapsed_secs = if idx > 0 { records[idx].ts - records[idx - 1].ts } else { 0.0 };
+            let multi = snaps.len() > 1;
+
+            terminal.draw(|frame| draw(frame, &snaps, &mut state, multi, elapsed_secs, None))?;
+
+            state.prev_stats = records[idx]
+                .snapshots
+                .iter()
+                .flat_map(|snap| &snap.sections)
+                .flat_map(|sec| sec.entries.iter().map(move |e| (sec.label.as_str(), e)))
+                .map(|(label, e)| {
+                    (
+                        (label.to_string(), e.name.clone()),
+                        PrevStat { count: e.stats.count, total: e.stats.duration_ns.total },
+                    )
+                })
+                .collect();
+
+            if event::poll(state.interval)? {
                 if let Event::Key(key) = event::read()? {
-                    if handle_key(&mut state, key.code, key.modifiers, total_rows) { break }
+                    match key.code {
+                        KeyCode::Char('n') => idx = (idx + 1).min(records.len() - 1),
+                        KeyCode::Char('b') => idx = idx.saturating_sub(1),
+                        code => if handle_key(&mut state, code, key.modifiers, total_rows) { break },
+                    }
                 }
+                while event::poll(Duration::ZERO)? { let _ = event::read()?; }
+            } else if !state.paused {
+                idx = (idx + 1).min(records.len() - 1);
             }
         }
         Ok(())
     })();
 
-    let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = execute!(terminal.backend_mut(), DisableMouseCapture, terminal::LeaveAlternateScreen);
     let _ = terminal::disable_raw_mode();
     result
 }
@@ -546,6 +1118,18 @@ fn run_interactive(cli: Cli, sysfs_paths: Vec<PathBuf>) -> Result<()> {
 pub fn timestats(argv: Vec<String>) -> Result<()> {
     let cli = Cli::parse_from(argv);
 
+    if let Some(path) = cli.replay.clone() {
+        let records = load_replay(&path)?;
+        let last = records.last().context("no recorded snapshots to replay")?.snapshots.clone();
+        return if cli.json {
+            print_json(&last)
+        } else if cli.once || !io::stdout().is_terminal() {
+            display_stats(last, &cli)
+        } else {
+            run_replay(cli, records)
+        };
+    }
+
     let sysfs_paths: Vec<PathBuf> = if let Some(ref fs_arg) = cli.filesystem {
         let handle = BcachefsHandle::open(fs_arg)
             .map_err(|e| anyhow!("Failed to open filesystem '{}': {:?}", fs_arg, e))?;
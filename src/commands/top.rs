@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::CStr;
+use std::fmt::Write as FmtWrite;
 use std::fs;
-use std::io::{self, Write as IoWrite};
+use std::io::{self, IoSlice, Write as IoWrite};
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -13,13 +14,18 @@ use clap::Parser;
 use crossterm::{
     cursor,
     event::{self, Event, KeyCode, KeyModifiers},
-    execute,
+    execute, queue,
+    style::Print,
     terminal::{self, ClearType},
 };
+use serde::Serialize;
 
 use crate::wrappers::handle::BcachefsHandle;
+use crate::wrappers::printbuf::Printbuf;
 use crate::wrappers::sysfs::dev_name_from_sysfs;
 
+extern crate tiny_http;
+
 // ioctl constants
 
 const BCH_IOCTL_QUERY_COUNTERS_NR: u32 = 21;
@@ -192,72 +198,142 @@ fn read_device_io(sysfs_path: &Path) -> Vec<DevIoEntry> {
     entries
 }
 
-// Human-readable formatting
-
-fn fmt_bytes(bytes: u64, human_readable: bool) -> String {
-    if human_readable { fmt_bytes_human(bytes) } else { format!("{}", bytes) }
-}
+// Table rendering via Printbuf, so the `h` toggle routes byte/sector
+// counters through the same bch2_prt_units_u64 formatting bcachefs itself
+// uses, rather than a second hand-rolled human-readable formatter.
 
 fn is_sectors(flags: bch_counters_flags) -> bool {
     flags == bch_counters_flags::TYPE_SECTORS
 }
 
-fn fmt_counter(val: u64, sectors: bool, human_readable: bool) -> String {
-    if sectors {
-        let bytes = val << 9;
-        if human_readable {
-            fmt_bytes_human(bytes)
+// name column, then three right-justified value columns, then a gutter
+// before the (optional) sparkline column.
+const COUNTER_TABSTOPS: [u32; 5] = [41, 15, 15, 15, 3];
+// name column, then four right-justified value columns, then a gutter
+// before the (optional) sparkline column.
+const DEV_IO_TABSTOPS: [u32; 6] = [41, 15, 15, 15, 15, 3];
+
+// Sparkline levels, darkest/shortest to tallest, used to render a counter's
+// recent rate history scaled to that counter's own min/max window.
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const HISTORY_LEN: usize = 60;
+
+fn sparkline(history: &VecDeque<u64>) -> String {
+    let Some(&min) = history.iter().min() else { return String::new() };
+    let max = history.iter().copied().max().unwrap_or(min);
+
+    history.iter().map(|&v| {
+        if max == min {
+            SPARK_LEVELS[0]
         } else {
-            format!("{}", bytes)
+            let frac = (v - min) as f64 / (max - min) as f64;
+            let idx = (frac * (SPARK_LEVELS.len() - 1) as f64).round() as usize;
+            SPARK_LEVELS[idx.min(SPARK_LEVELS.len() - 1)]
         }
-    } else if human_readable && val >= 10_000 {
-        fmt_num_human(val)
+    }).collect()
+}
+
+fn new_row(tabstops: &[u32], human_readable: bool) -> Printbuf {
+    let mut out = Printbuf::new();
+    out.set_human_readable(human_readable);
+    for &spaces in tabstops {
+        out.tabstop_push(spaces);
+    }
+    out
+}
+
+fn fmt_counter_header(interval_secs: u32, human_readable: bool, show_sparkline: bool) -> String {
+    let mut out = new_row(&COUNTER_TABSTOPS, human_readable);
+    out.tab();
+    write!(out, "{}/s", interval_secs).unwrap();
+    out.tab_rjust();
+    write!(out, "total").unwrap();
+    out.tab_rjust();
+    write!(out, "mount").unwrap();
+    out.tab_rjust();
+    if show_sparkline {
+        out.tab();
+        write!(out, "history").unwrap();
+    }
+    out.as_str().to_string()
+}
+
+fn fmt_counter_row(name: &str, rate: u64, total: u64, mount: u64, sectors: bool, human_readable: bool, spark: Option<&str>) -> String {
+    let mut out = new_row(&COUNTER_TABSTOPS, human_readable);
+    write!(out, "{}", name).unwrap();
+    out.tab();
+
+    if sectors {
+        out.units_u64(rate << 9);
     } else {
-        format!("{}", val)
+        write!(out, "{}", rate).unwrap();
+    }
+    write!(out, "/s").unwrap();
+    out.tab_rjust();
+
+    if sectors { out.units_u64(total << 9) } else { write!(out, "{}", total).unwrap() }
+    out.tab_rjust();
+
+    if sectors { out.units_u64(mount << 9) } else { write!(out, "{}", mount).unwrap() }
+    out.tab_rjust();
+
+    if let Some(spark) = spark {
+        out.tab();
+        write!(out, "{}", spark).unwrap();
     }
+
+    out.as_str().to_string()
 }
 
-fn fmt_bytes_human(bytes: u64) -> String {
-    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
-    if bytes == 0 { return "0B".to_string() }
-    let mut val = bytes as f64;
-    for unit in UNITS {
-        if val < 1024.0 || *unit == "PiB" {
-            return if val >= 100.0 {
-                format!("{:.0}{}", val, unit)
-            } else if val >= 10.0 {
-                format!("{:.1}{}", val, unit)
-            } else {
-                format!("{:.2}{}", val, unit)
-            };
-        }
-        val /= 1024.0;
+fn fmt_dev_io_header(human_readable: bool, show_sparkline: bool) -> String {
+    let mut out = new_row(&DEV_IO_TABSTOPS, human_readable);
+    out.tab();
+    write!(out, "read/s").unwrap();
+    out.tab_rjust();
+    write!(out, "read").unwrap();
+    out.tab_rjust();
+    write!(out, "write/s").unwrap();
+    out.tab_rjust();
+    write!(out, "write").unwrap();
+    out.tab_rjust();
+    if show_sparkline {
+        out.tab();
+        write!(out, "history").unwrap();
     }
-    format!("{}B", bytes)
-}
-
-fn fmt_num_human(n: u64) -> String {
-    const UNITS: &[&str] = &["", "K", "M", "G", "T"];
-    let mut val = n as f64;
-    for unit in UNITS {
-        if val < 1000.0 || *unit == "T" {
-            return if val >= 100.0 {
-                format!("{:.0}{}", val, unit)
-            } else if val >= 10.0 {
-                format!("{:.1}{}", val, unit)
-            } else if unit.is_empty() {
-                format!("{}", n)
-            } else {
-                format!("{:.2}{}", val, unit)
-            };
-        }
-        val /= 1000.0;
+    out.as_str().to_string()
+}
+
+fn fmt_dev_io_row(label: &str, rate_r: u64, read: u64, rate_w: u64, write: u64, human_readable: bool, spark: Option<&str>) -> String {
+    let mut out = new_row(&DEV_IO_TABSTOPS, human_readable);
+    write!(out, "{}", label).unwrap();
+    out.tab();
+    out.units_u64(rate_r);
+    out.tab_rjust();
+    out.units_u64(read);
+    out.tab_rjust();
+    out.units_u64(rate_w);
+    out.tab_rjust();
+    out.units_u64(write);
+    out.tab_rjust();
+
+    if let Some(spark) = spark {
+        out.tab();
+        write!(out, "{}", spark).unwrap();
     }
-    format!("{}", n)
+
+    out.as_str().to_string()
 }
 
 // CLI
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum SampleFormat {
+    #[default]
+    Pretty,
+    Json,
+    Csv,
+}
+
 #[derive(Parser, Debug)]
 #[command(about = "Display runtime performance info")]
 pub struct Cli {
@@ -265,10 +341,50 @@ pub struct Cli {
     #[arg(short, long)]
     human_readable: bool,
 
+    /// Sample the counters this many times, then exit, instead of running
+    /// the interactive TUI
+    #[arg(long, conflicts_with = "once")]
+    count: Option<u32>,
+
+    /// Sample once and exit (shorthand for --count 1)
+    #[arg(long)]
+    once: bool,
+
+    /// Output format for non-interactive sampling (--count/--once)
+    #[arg(long, value_enum, default_value_t = SampleFormat::Pretty)]
+    format: SampleFormat,
+
+    /// Serve counters as an OpenMetrics/Prometheus exposition endpoint at
+    /// ADDR (e.g. 0.0.0.0:9100) instead of running the interactive TUI
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["count", "once"])]
+    serve: Option<String>,
+
     /// Filesystem path, device, or UUID (default: current directory)
     filesystem: Option<String>,
 }
 
+// A single counter or per-device reading, already reduced to the
+// rate/total/mount deltas the TUI and non-interactive output share.
+
+#[derive(Serialize)]
+struct CounterSample {
+    name:    String,
+    sectors: bool,
+    value:   u64,
+    rate:    u64,
+    total:   u64,
+    mount:   u64,
+}
+
+#[derive(Serialize)]
+struct DevIoSample {
+    label:      String,
+    read_bytes: u64,
+    write_bytes: u64,
+    read_rate:  u64,
+    write_rate: u64,
+}
+
 // TUI state
 
 struct TopState {
@@ -281,8 +397,12 @@ struct TopState {
     prev_dev_io:    HashMap<String, (u64, u64)>,    // label -> (read, write)
     human_readable: bool,
     show_devices:   bool,
+    show_sparkline: bool,
     sysfs_path:     PathBuf,
     interval_secs:  u32,
+    prev_frame:     Vec<String>,
+    counter_rate_history: HashMap<String, VecDeque<u64>>,  // counter name -> rate history
+    dev_rate_history:     HashMap<String, VecDeque<u64>>,  // dev label -> total rate history
 }
 
 fn sysfs_path_from_fd(fd: i32) -> Result<PathBuf> {
@@ -306,30 +426,42 @@ impl TopState {
             info, ioctl_fd, nr_stable,
             mount_vals, start_vals, prev_vals,
             prev_dev_io: HashMap::new(),
-            human_readable, show_devices: true,
+            human_readable, show_devices: true, show_sparkline: true,
             sysfs_path, interval_secs: 1,
+            prev_frame: Vec::new(),
+            counter_rate_history: HashMap::new(),
+            dev_rate_history: HashMap::new(),
         })
     }
 
+    // Pushes a rate sample onto a counter/device's ring buffer, keeping at
+    // most HISTORY_LEN of the most recent samples.
+    fn push_history(history: &mut HashMap<String, VecDeque<u64>>, key: &str, rate: u64) {
+        let hist = history.entry(key.to_string()).or_default();
+        hist.push_back(rate);
+        if hist.len() > HISTORY_LEN {
+            hist.pop_front();
+        }
+    }
+
     fn get_val(vals: &[u64], stable_id: u16) -> u64 {
         let idx = stable_id as usize;
         if idx < vals.len() { vals[idx] } else { 0 }
     }
 
-    fn render(&self, curr: &[u64], dev_io: &[DevIoEntry], stdout: &mut io::Stdout) -> io::Result<()> {
-        execute!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
-
-        write!(stdout, "All counters have a corresponding tracepoint; for more info on any given event, try e.g.\r\n")?;
-        write!(stdout, "  perf trace -e bcachefs:data_update_pred\r\n\r\n")?;
-        write!(stdout, "  q:quit  h:human-readable  d:devices  1-9:interval\r\n\r\n")?;
-
-        write!(stdout, "{:<40} {:>14} {:>14} {:>14}\r\n",
-            "", format!("{}/s", self.interval_secs), "total", "mount")?;
+    // Reads the current counters and per-device IO, reduces them to
+    // rate/total/mount deltas against the stored baselines, and advances
+    // those baselines for next time. Shared by the interactive TUI and
+    // the non-interactive --count/--once sampling path.
+    fn sample(&mut self) -> Result<(Vec<CounterSample>, Vec<DevIoSample>)> {
+        let curr = read_counters(self.ioctl_fd, 0, self.nr_stable)?;
+        let dev_io = read_device_io(&self.sysfs_path);
 
+        let mut counters = Vec::new();
         let nr = self.info.names.len();
         for i in 0..nr {
             let stable = self.info.stable_map[i];
-            let cv = Self::get_val(curr, stable);
+            let cv = Self::get_val(&curr, stable);
             let pv = Self::get_val(&self.prev_vals, stable);
             let sv = Self::get_val(&self.start_vals, stable);
             let mv = Self::get_val(&self.mount_vals, stable);
@@ -337,42 +469,287 @@ impl TopState {
             let v_mount = cv.wrapping_sub(mv);
             if v_mount == 0 { continue }
 
-            let v_rate  = cv.wrapping_sub(pv);
-            let v_total = cv.wrapping_sub(sv);
+            let name = &self.info.names[i];
+            let rate = cv.wrapping_sub(pv) / self.interval_secs as u64;
+            Self::push_history(&mut self.counter_rate_history, name, rate);
+
+            counters.push(CounterSample {
+                name:    name.clone(),
+                sectors: is_sectors(self.info.flags[i]),
+                value:   cv,
+                rate,
+                total:   cv.wrapping_sub(sv),
+                mount:   v_mount,
+            });
+        }
+
+        let mut devices = Vec::new();
+        for dev in &dev_io {
+            let (prev_r, prev_w) = self.prev_dev_io
+                .get(&dev.label)
+                .copied()
+                .unwrap_or((dev.read_bytes, dev.write_bytes));
+
+            let read_rate = dev.read_bytes.wrapping_sub(prev_r) / self.interval_secs as u64;
+            let write_rate = dev.write_bytes.wrapping_sub(prev_w) / self.interval_secs as u64;
+            Self::push_history(&mut self.dev_rate_history, &dev.label, read_rate + write_rate);
+
+            devices.push(DevIoSample {
+                label:       dev.label.clone(),
+                read_bytes:  dev.read_bytes,
+                write_bytes: dev.write_bytes,
+                read_rate,
+                write_rate,
+            });
+        }
+
+        self.prev_vals = curr;
+        self.prev_dev_io = dev_io.into_iter()
+            .map(|d| (d.label, (d.read_bytes, d.write_bytes)))
+            .collect();
+
+        Ok((counters, devices))
+    }
+
+    // Builds the full screen as one line per row, rather than writing
+    // directly to stdout, so it can be diffed against the previous frame.
+    fn build_frame(&self, counters: &[CounterSample], devices: &[DevIoSample]) -> Vec<String> {
+        let mut frame = Vec::new();
+
+        frame.push("All counters have a corresponding tracepoint; for more info on any given event, try e.g.".to_string());
+        frame.push("  perf trace -e bcachefs:data_update_pred".to_string());
+        frame.push(String::new());
+        frame.push("  q:quit  h:human-readable  d:devices  g:sparkline  1-9:interval".to_string());
+        frame.push(String::new());
 
-            let sectors = is_sectors(self.info.flags[i]);
+        frame.push(fmt_counter_header(self.interval_secs, self.human_readable, self.show_sparkline));
 
-            write!(stdout, "{:<40} {:>12}/s {:>14} {:>14}\r\n",
-                &self.info.names[i],
-                fmt_counter(v_rate / self.interval_secs as u64, sectors, self.human_readable),
-                fmt_counter(v_total, sectors, self.human_readable),
-                fmt_counter(v_mount, sectors, self.human_readable))?;
+        for c in counters {
+            let spark = self.show_sparkline.then(|| {
+                self.counter_rate_history.get(&c.name).map(sparkline).unwrap_or_default()
+            });
+            frame.push(fmt_counter_row(&c.name, c.rate, c.total, c.mount, c.sectors, self.human_readable, spark.as_deref()));
         }
 
-        if self.show_devices && !dev_io.is_empty() {
-            write!(stdout, "\r\nPer-device IO:\r\n")?;
-            write!(stdout, "{:<40} {:>14} {:>14} {:>14} {:>14}\r\n",
-                "", "read/s", "read", "write/s", "write")?;
-            for dev in dev_io {
-                let (prev_r, prev_w) = self.prev_dev_io
-                    .get(&dev.label)
-                    .copied()
-                    .unwrap_or((dev.read_bytes, dev.write_bytes));
-                let rate_r = dev.read_bytes.wrapping_sub(prev_r) / self.interval_secs as u64;
-                let rate_w = dev.write_bytes.wrapping_sub(prev_w) / self.interval_secs as u64;
-
-                let h = self.human_readable;
-                write!(stdout, "{:<40} {:>14} {:>14} {:>14} {:>14}\r\n",
+        if self.show_devices && !devices.is_empty() {
+            frame.push(String::new());
+            frame.push("Per-device IO:".to_string());
+            frame.push(fmt_dev_io_header(self.human_readable, self.show_sparkline));
+            for dev in devices {
+                let spark = self.show_sparkline.then(|| {
+                    self.dev_rate_history.get(&dev.label).map(sparkline).unwrap_or_default()
+                });
+                frame.push(fmt_dev_io_row(
                     &dev.label,
-                    fmt_bytes(rate_r, h), fmt_bytes(dev.read_bytes, h),
-                    fmt_bytes(rate_w, h), fmt_bytes(dev.write_bytes, h))?;
+                    dev.read_rate, dev.read_bytes,
+                    dev.write_rate, dev.write_bytes,
+                    self.human_readable,
+                    spark.as_deref(),
+                ));
             }
         }
 
-        stdout.flush()
+        frame
+    }
+
+    // Diffs the new frame against the last drawn one and redraws only the
+    // rows that changed, as a single batched write_vectored call, instead
+    // of clearing and redrawing the whole screen every tick.
+    fn render(&mut self, counters: &[CounterSample], devices: &[DevIoSample], stdout: &mut io::Stdout) -> io::Result<()> {
+        let frame = self.build_frame(counters, devices);
+        let row_count = frame.len().max(self.prev_frame.len());
+
+        let mut rows: Vec<Vec<u8>> = Vec::new();
+        for row in 0..row_count {
+            let new_line = frame.get(row).map(String::as_str).unwrap_or("");
+            let old_line = self.prev_frame.get(row).map(String::as_str).unwrap_or("");
+            if new_line == old_line {
+                continue;
+            }
+
+            let mut buf = Vec::new();
+            queue!(
+                &mut buf,
+                cursor::MoveTo(0, row as u16),
+                Print(new_line),
+                terminal::Clear(ClearType::UntilNewLine),
+            )?;
+            rows.push(buf);
+        }
+
+        if !rows.is_empty() {
+            write_vectored_all(stdout, &rows)?;
+        }
+
+        stdout.flush()?;
+        self.prev_frame = frame;
+        Ok(())
+    }
+}
+
+// write_vectored's default implementation (used by io::Stdout) only ever
+// writes the first buffer, so retry with the remaining bytes rather than
+// assuming one call drains the whole batch.
+fn write_vectored_all(stdout: &mut io::Stdout, rows: &[Vec<u8>]) -> io::Result<()> {
+    let slices: Vec<IoSlice> = rows.iter().map(|b| IoSlice::new(b)).collect();
+    let total: usize = rows.iter().map(Vec::len).sum();
+
+    let mut written = stdout.write_vectored(&slices)?;
+    if written >= total {
+        return Ok(());
+    }
+
+    for row in rows {
+        if written >= row.len() {
+            written -= row.len();
+            continue;
+        }
+        stdout.write_all(&row[written..])?;
+        written = 0;
+    }
+    Ok(())
+}
+
+// One tick of non-interactive output: the counters and per-device IO,
+// nested together so a log shipper or `jq` can consume a whole sample
+// from a single JSON line.
+#[derive(Serialize)]
+struct Sample {
+    sample:  u32,
+    counters: Vec<CounterSample>,
+    devices:  Vec<DevIoSample>,
+}
+
+fn print_csv_header() {
+    println!("sample,kind,name,sectors,value,rate,total,mount,read_bytes,write_bytes,read_rate,write_rate");
+}
+
+fn print_csv_sample(n: u32, counters: &[CounterSample], devices: &[DevIoSample]) {
+    for c in counters {
+        println!("{},counter,{},{},{},{},{},{},,,,",
+            n, c.name, c.sectors, c.value, c.rate, c.total, c.mount);
+    }
+    for d in devices {
+        println!("{},device,{},,,,,,{},{},{},{}",
+            n, d.label, d.read_bytes, d.write_bytes, d.read_rate, d.write_rate);
     }
 }
 
+fn run_sampled(handle: BcachefsHandle, cli: &Cli) -> Result<()> {
+    let mut state = TopState::new(&handle, cli.human_readable)?;
+    let count = if cli.once { 1 } else { cli.count.unwrap() };
+
+    if cli.format == SampleFormat::Csv {
+        print_csv_header();
+    }
+
+    for n in 0..count {
+        let (counters, devices) = state.sample()?;
+
+        match cli.format {
+            SampleFormat::Pretty => {
+                println!("{}", fmt_counter_header(state.interval_secs, state.human_readable, false));
+                for c in &counters {
+                    println!("{}", fmt_counter_row(&c.name, c.rate, c.total, c.mount, c.sectors, state.human_readable, None));
+                }
+                if !devices.is_empty() {
+                    println!("{}", fmt_dev_io_header(state.human_readable, false));
+                    for d in &devices {
+                        println!("{}", fmt_dev_io_row(&d.label, d.read_rate, d.read_bytes, d.write_rate, d.write_bytes, state.human_readable, None));
+                    }
+                }
+                if n + 1 < count { println!() }
+            }
+            SampleFormat::Json => {
+                let sample = Sample { sample: n, counters, devices };
+                println!("{}", serde_json::to_string(&sample)?);
+            }
+            SampleFormat::Csv => print_csv_sample(n, &counters, &devices),
+        }
+
+        if n + 1 < count {
+            std::thread::sleep(Duration::from_secs(state.interval_secs as u64));
+        }
+    }
+
+    Ok(())
+}
+
+fn fmt_uuid(uuid: &[u8; 16]) -> String {
+    format!("{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        uuid[0], uuid[1], uuid[2], uuid[3],
+        uuid[4], uuid[5],
+        uuid[6], uuid[7],
+        uuid[8], uuid[9],
+        uuid[10], uuid[11], uuid[12], uuid[13], uuid[14], uuid[15])
+}
+
+/// Render the current counters and per-device IO in OpenMetrics text
+/// exposition format, labelled with the filesystem's UUID so a single
+/// exporter can cover a host with multiple bcachefs mounts.
+fn render_openmetrics(state: &TopState, fs_uuid: &str) -> Result<String> {
+    use std::fmt::Write as _;
+
+    let curr = read_counters(state.ioctl_fd, 0, state.nr_stable)?;
+    let mut out = String::new();
+
+    let nr = state.info.names.len();
+    for i in 0..nr {
+        let stable = state.info.stable_map[i];
+        let v = TopState::get_val(&curr, stable);
+        let name = &state.info.names[i];
+
+        if is_sectors(state.info.flags[i]) {
+            writeln!(out, "# TYPE bcachefs_{}_bytes counter", name).unwrap();
+            writeln!(out, "bcachefs_{}_bytes{{uuid=\"{}\"}} {}", name, fs_uuid, v << 9).unwrap();
+        } else {
+            writeln!(out, "# TYPE bcachefs_{} counter", name).unwrap();
+            writeln!(out, "bcachefs_{}{{uuid=\"{}\"}} {}", name, fs_uuid, v).unwrap();
+        }
+    }
+
+    let dev_io = read_device_io(&state.sysfs_path);
+    if !dev_io.is_empty() {
+        writeln!(out, "# TYPE bcachefs_dev_io_bytes counter").unwrap();
+        for dev in &dev_io {
+            let (device, data_type) = dev.label.split_once('/').unwrap_or((&dev.label, ""));
+            writeln!(out, "bcachefs_dev_io_bytes{{uuid=\"{}\",device=\"{}\",data_type=\"{}\",direction=\"read\"}} {}",
+                fs_uuid, device, data_type, dev.read_bytes).unwrap();
+            writeln!(out, "bcachefs_dev_io_bytes{{uuid=\"{}\",device=\"{}\",data_type=\"{}\",direction=\"write\"}} {}",
+                fs_uuid, device, data_type, dev.write_bytes).unwrap();
+        }
+    }
+
+    Ok(out)
+}
+
+fn run_serve(handle: BcachefsHandle, listen: String) -> Result<()> {
+    use tiny_http::{Response, Server};
+
+    let state = TopState::new(&handle, false)?;
+    let fs_uuid = fmt_uuid(&handle.uuid());
+
+    let server = Server::http(&listen)
+        .map_err(|e| anyhow!("failed to bind {}: {}", listen, e))?;
+
+    for request in server.incoming_requests() {
+        match render_openmetrics(&state, &fs_uuid) {
+            Ok(body) => {
+                let response = Response::from_string(body).with_header(
+                    tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..]).unwrap(),
+                );
+                request.respond(response).expect("Responded");
+            }
+            Err(e) => {
+                let response = Response::from_string(format!("Error: {:#}", e)).with_status_code(500);
+                request.respond(response).expect("Responded");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn run_interactive(handle: BcachefsHandle, human_readable: bool) -> Result<()> {
     let mut state = TopState::new(&handle, human_readable)?;
     let mut stdout = io::stdout();
@@ -382,13 +759,8 @@ fn run_interactive(handle: BcachefsHandle, human_readable: bool) -> Result<()> {
 
     let result = (|| -> Result<()> {
         loop {
-            let curr = read_counters(state.ioctl_fd, 0, state.nr_stable)?;
-            let dev_io = read_device_io(&state.sysfs_path);
-            state.render(&curr, &dev_io, &mut stdout)?;
-            state.prev_vals = curr;
-            state.prev_dev_io = dev_io.into_iter()
-                .map(|d| (d.label, (d.read_bytes, d.write_bytes)))
-                .collect();
+            let (counters, devices) = state.sample()?;
+            state.render(&counters, &devices, &mut stdout)?;
 
             // Wait for interval or keypress
             if event::poll(Duration::from_secs(state.interval_secs as u64))? {
@@ -398,6 +770,7 @@ fn run_interactive(handle: BcachefsHandle, human_readable: bool) -> Result<()> {
                         KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
                         KeyCode::Char('h') => state.human_readable = !state.human_readable,
                         KeyCode::Char('d') => state.show_devices = !state.show_devices,
+                        KeyCode::Char('g') => state.show_sparkline = !state.show_sparkline,
                         KeyCode::Char(c @ '1'..='9') => {
                             state.interval_secs = (c as u32) - ('0' as u32);
                         }
@@ -422,5 +795,11 @@ pub fn top(argv: Vec<String>) -> Result<()> {
     let handle = BcachefsHandle::open(fs_arg)
         .map_err(|e| anyhow!("Failed to open filesystem '{}': {}", fs_arg, e))?;
 
-    run_interactive(handle, cli.human_readable)
+    if let Some(listen) = cli.serve.clone() {
+        run_serve(handle, listen)
+    } else if cli.once || cli.count.is_some() {
+        run_sampled(handle, &cli)
+    } else {
+        run_interactive(handle, cli.human_readable)
+    }
 }
@@ -7,7 +7,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bch_bindgen::{bcachefs, opt_set};
 use bcachefs::{
     bch_sb_handle,
@@ -38,12 +38,15 @@ fn device_property_map(dev: &udev::Device) -> HashMap<String, String> {
     rc
 }
 
-fn udev_bcachefs_info() -> anyhow::Result<HashMap<String, Vec<String>>> {
+/// Returns (devnode -> [its fs UUID] and fs UUID -> devnodes, `ID_FS_LABEL`
+/// -> distinct UUIDs carrying that label).
+fn udev_bcachefs_info() -> anyhow::Result<(HashMap<String, Vec<String>>, HashMap<String, Vec<String>>)> {
     let mut info = HashMap::new();
+    let mut labels: HashMap<String, Vec<String>> = HashMap::new();
 
     if env::var("BCACHEFS_BLOCK_SCAN").is_ok() {
         debug!("Checking all block devices for bcachefs super block!");
-        return Ok(info);
+        return Ok((info, labels));
     }
 
     let mut udev = udev::Enumerator::new()?;
@@ -61,10 +64,17 @@ fn udev_bcachefs_info() -> anyhow::Result<HashMap<String, Vec<String>>> {
         let fs_uuid = m["ID_FS_UUID"].clone();
         let dev_node = m["DEVNAME"].clone();
         info.insert(dev_node.clone(), vec![fs_uuid.clone()]);
-        info.entry(fs_uuid).or_insert(vec![]).push(dev_node.clone());
+        info.entry(fs_uuid.clone()).or_insert(vec![]).push(dev_node.clone());
+
+        if let Some(label) = m.get("ID_FS_LABEL") {
+            let uuids = labels.entry(label.clone()).or_insert_with(Vec::new);
+            if !uuids.contains(&fs_uuid) {
+                uuids.push(fs_uuid.clone());
+            }
+        }
     }
 
-    Ok(info)
+    Ok((info, labels))
 }
 
 fn get_all_block_devnodes() -> anyhow::Result<Vec<String>> {
@@ -88,7 +98,7 @@ fn get_devices_by_uuid(
     uuid: Uuid,
     opts: &bch_opts
 ) -> anyhow::Result<Vec<(PathBuf, bch_sb_handle)>> {
-    let udev_bcachefs = udev_bcachefs_info()?;
+    let (udev_bcachefs, _) = udev_bcachefs_info()?;
 
     let devices = {
         if !udev_bcachefs.is_empty() {
@@ -106,6 +116,24 @@ fn get_devices_by_uuid(
     Ok(get_super_blocks(uuid, &devices, opts))
 }
 
+/// Resolve a `ID_FS_LABEL` to its filesystem's devices via the udev cache.
+/// Errors (rather than picking one) if more than one filesystem shares the
+/// label.
+fn get_devices_by_label(label: &str, opts: &bch_opts) -> anyhow::Result<Vec<(PathBuf, bch_sb_handle)>> {
+    let (_, labels) = udev_bcachefs_info()?;
+    let uuids = labels.get(label).cloned().unwrap_or_default();
+
+    match uuids.len() {
+        0 => Err(anyhow::anyhow!("no bcachefs filesystem found with label '{}'", label)),
+        1 => get_devices_by_uuid(Uuid::parse_str(&uuids[0])?, opts),
+        _ => Err(anyhow::anyhow!(
+            "label '{}' is ambiguous, matches filesystems with UUIDs: {}",
+            label,
+            uuids.join(", "),
+        )),
+    }
+}
+
 fn get_super_blocks(uuid: Uuid, devices: &[String], opts: &bch_opts) -> Vec<(PathBuf, bch_sb_handle)> {
     devices
         .iter()
@@ -137,11 +165,27 @@ fn devs_str_sbs_from_device(
     }
 }
 
+/// Resolve a `by-partuuid`/`by-partlabel` style fstab specifier to the
+/// partition's block device node via its `/dev/disk/by-*` symlink.
+fn resolve_by_disk_symlink(dir: &str, id: &str) -> anyhow::Result<PathBuf> {
+    let link = Path::new("/dev/disk").join(dir).join(id);
+    fs::canonicalize(&link)
+        .with_context(|| format!("no partition found for '{}'", link.display()))
+}
+
 pub fn scan_sbs(device: &String, opts: &bch_opts) -> Result<Vec<(PathBuf, bch_sb_handle)>> {
     if let Some(("UUID" | "OLD_BLKID_UUID", uuid)) = device.split_once('=') {
         let uuid = Uuid::parse_str(uuid)?;
 
         get_devices_by_uuid(uuid, opts)
+    } else if let Some(("LABEL", label)) = device.split_once('=') {
+        get_devices_by_label(label, opts)
+    } else if let Some(("PARTUUID", id)) = device.split_once('=') {
+        let dev = resolve_by_disk_symlink("by-partuuid", id)?;
+        devs_str_sbs_from_device(&dev, opts)
+    } else if let Some(("PARTLABEL", label)) = device.split_once('=') {
+        let dev = resolve_by_disk_symlink("by-partlabel", label)?;
+        devs_str_sbs_from_device(&dev, opts)
     } else if device.contains(':') {
         let mut opts = *opts;
         opt_set!(opts, noexcl, 1);
@@ -1,19 +1,42 @@
 use std::ffi::{CString, CStr, c_char};
+use std::io::Read;
 use crate::c;
 
 extern crate tiny_http;
 
-fn http_thread(listen: String) {
+/// Map a kernel errno returned by `sysfs_write`/`sysfs_read_or_html_dirlist`
+/// to the HTTP status code it should surface as.
+fn errno_to_status(ret: i32) -> u32 {
+    match -ret {
+        libc::EPERM | libc::EACCES => 403,
+        libc::EINVAL => 400,
+        libc::ENOENT => 404,
+        _ => 500,
+    }
+}
+
+fn http_thread(listen: String, allow_write: bool) {
     use tiny_http::{Response, Server};
 
     let server = Server::http(listen).unwrap();
 
-    for request in server.incoming_requests() {
+    for mut request in server.incoming_requests() {
         let (_, path) = request.url().split_once('/').unwrap();
 
         let c_path = CString::new(path).unwrap();
 
         match request.method() {
+            tiny_http::Method::Get if path == "metrics" => {
+                let response = Response::from_string(crate::metrics::render())
+                    .with_header(
+                        tiny_http::Header::from_bytes(
+                            &b"Content-Type"[..],
+                            &b"text/plain; version=0.0.4"[..],
+                        ).unwrap(),
+                    );
+                request.respond(response).expect("Responded");
+            }
+
             tiny_http::Method::Get => {
                 let mut buf = c::printbuf::new();
 
@@ -31,6 +54,52 @@ fn http_thread(listen: String) {
                 }
             }
 
+            tiny_http::Method::Post | tiny_http::Method::Put if allow_write => {
+                let mut body = String::new();
+                if let Err(e) = request.as_reader().read_to_string(&mut body) {
+                    let response = Response::from_string(format!("Error reading body: {}", e))
+                        .with_status_code(400);
+                    request.respond(response).expect("Responded");
+                    continue;
+                }
+
+                if body.is_empty() {
+                    let response = Response::from_string("Empty body")
+                        .with_status_code(400);
+                    request.respond(response).expect("Responded");
+                    continue;
+                }
+
+                let c_body = match CString::new(body) {
+                    Ok(b) => b,
+                    Err(_) => {
+                        let response = Response::from_string("Body contains NUL byte")
+                            .with_status_code(400);
+                        request.respond(response).expect("Responded");
+                        continue;
+                    }
+                };
+
+                let ret = unsafe {
+                    c::sysfs_write(c_path.as_ptr(), c_body.as_ptr(), c_body.as_bytes().len())
+                };
+
+                if ret < 0 {
+                    let response = Response::from_string(format!("Error {}", ret))
+                        .with_status_code(errno_to_status(ret as i32));
+                    request.respond(response).expect("Responded");
+                } else {
+                    let response = Response::from_string("OK");
+                    request.respond(response).expect("Responded");
+                }
+            }
+
+            tiny_http::Method::Post | tiny_http::Method::Put => {
+                let response = Response::from_string("Write operations disabled")
+                    .with_status_code(403);
+                request.respond(response).expect("Responded");
+            }
+
             _ => {
                 let response = Response::from_string("Unsupported HTTP method")
                     .with_status_code(405);
@@ -41,9 +110,9 @@ fn http_thread(listen: String) {
 }
 
 #[no_mangle]
-pub extern "C" fn start_http(listen: *const c_char) {
+pub extern "C" fn start_http(listen: *const c_char, allow_write: bool) {
     let listen = unsafe { CStr::from_ptr(listen) };
     let listen = listen.to_str().unwrap().to_string();
 
-    std::thread::spawn(|| http_thread(listen));
+    std::thread::spawn(move || http_thread(listen, allow_write));
 }
@@ -16,15 +16,24 @@ use bch_bindgen::{
     c::{bch2_chacha20, bch_encrypted_key, bch_sb_field_crypt},
     keyutils::{self, keyctl_search},
 };
-use log::{debug, info};
+use log::{debug, info, warn};
 use rustix::termios;
 use uuid::Uuid;
 use zeroize::{ZeroizeOnDrop, Zeroizing};
 
+use crate::tpm2;
 use crate::ErrnoError;
 
 const BCH_KEY_MAGIC: &[u8; 8] = b"bch**key";
 
+fn hex_encode(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+        write!(s, "{b:02x}").unwrap();
+        s
+    })
+}
+
 /// Check if a superblock has an encrypted passphrase set.
 pub fn sb_is_encrypted(sb: &bch_sb_handle) -> bool {
     let bch_key_magic = u64::from_le_bytes(*BCH_KEY_MAGIC);
@@ -50,15 +59,28 @@ pub enum Keyring {
     #[default]
     User,
     UserSession,
+    /// A per-user keyring that survives across login sessions (and reboots,
+    /// until explicitly cleared), created on demand via `KEYCTL_GET_PERSISTENT`.
+    Persistent,
 }
 
 impl Keyring {
-    pub fn id(self) -> i32 {
-        match self {
+    fn id(self) -> Result<i32> {
+        Ok(match self {
             Keyring::Session => keyutils::KEY_SPEC_SESSION_KEYRING,
             Keyring::User => keyutils::KEY_SPEC_USER_KEYRING,
             Keyring::UserSession => keyutils::KEY_SPEC_USER_SESSION_KEYRING,
-        }
+            Keyring::Persistent => {
+                // uid -1 (as unsigned) means "the calling process's own uid"; dest
+                // 0 means just return the persistent keyring's ID without linking
+                // it anywhere.
+                let id = unsafe { keyutils::keyctl_get_persistent(u32::MAX, 0) };
+                if id < 0 {
+                    return Err(ErrnoError(errno::errno()).into());
+                }
+                id as i32
+            }
+        })
     }
 }
 
@@ -73,10 +95,36 @@ pub enum UnlockPolicy {
     Ask,
     /// Try to read the passphrase from `stdin` without prompting
     Stdin,
+    /// Unseal the passphrase from a TPM2 device, failing closed if the
+    /// current PCR state doesn't match what was recorded at enrollment
+    Tpm2,
+    /// Read passphrases from a caller-supplied descriptor (a FIFO or a
+    /// socket-activation fd), retrying subsequent lines on an incorrect
+    /// passphrase instead of aborting after the first bad line
+    Fd,
+}
+
+/// Where to read candidate passphrases from for [`UnlockPolicy::Fd`], and how
+/// many to try before giving up.
+pub struct FdUnlockConfig<'fd> {
+    pub fd: BorrowedFd<'fd>,
+    pub max_attempts: u32,
+    pub backoff: Duration,
 }
 
 impl UnlockPolicy {
-    pub fn apply(&self, sb: &bch_sb_handle) -> Result<KeyHandle> {
+    /// `keyring` selects where a freshly-derived key is stored (ignored by
+    /// `Fail`/`Wait`, which only ever search an existing key). `timeout`, if
+    /// set, expires that key out of the keyring after the given duration
+    /// instead of leaving it cached indefinitely. `fd_unlock` is required by,
+    /// and only used by, the `Fd` policy.
+    pub fn apply(
+        &self,
+        sb: &bch_sb_handle,
+        keyring: Keyring,
+        timeout: Option<Duration>,
+        fd_unlock: Option<FdUnlockConfig>,
+    ) -> Result<KeyHandle> {
         let uuid = sb.sb().uuid();
 
         info!("Using filesystem unlock policy '{self}' on {uuid}");
@@ -84,8 +132,15 @@ impl UnlockPolicy {
         match self {
             Self::Fail => KeyHandle::new_from_search(&uuid),
             Self::Wait => Ok(KeyHandle::wait_for_unlock(&uuid)?),
-            Self::Ask => Passphrase::new_from_prompt(&uuid).and_then(|p| KeyHandle::new(sb, &p, Keyring::User)),
-            Self::Stdin => Passphrase::new_from_stdin().and_then(|p| KeyHandle::new(sb, &p, Keyring::User)),
+            Self::Ask => Passphrase::new_from_prompt(&uuid).and_then(|p| KeyHandle::new(sb, &p, keyring, timeout)),
+            Self::Stdin => Passphrase::new_from_stdin().and_then(|p| KeyHandle::new(sb, &p, keyring, timeout)),
+            Self::Tpm2 => Passphrase::new_from_tpm2(&uuid).and_then(|p| KeyHandle::new(sb, &p, keyring, timeout)),
+            Self::Fd => {
+                let cfg = fd_unlock
+                    .ok_or_else(|| anyhow!("the `fd` unlock policy requires a descriptor to read from"))?;
+                Passphrase::new_from_fd_retrying(cfg.fd, sb, cfg.max_attempts, cfg.backoff)
+                    .and_then(|p| KeyHandle::new(sb, &p, keyring, timeout))
+            }
         }
     }
 }
@@ -108,7 +163,15 @@ impl KeyHandle {
         CString::new(format!("bcachefs:{uuid}")).unwrap()
     }
 
-    pub fn new(sb: &bch_sb_handle, passphrase: &Passphrase, keyring: Keyring) -> Result<Self> {
+    /// Add `passphrase`'s derived key to `keyring`. If `timeout` is set, the
+    /// key is made to expire from the keyring after that duration (via
+    /// `keyctl_set_timeout`) rather than being cached until manually revoked.
+    pub fn new(
+        sb: &bch_sb_handle,
+        passphrase: &Passphrase,
+        keyring: Keyring,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
         let key_name = Self::format_key_name(&sb.sb().uuid());
         let key_name = CStr::as_ptr(&key_name);
         let key_type = c"user";
@@ -121,12 +184,20 @@ impl KeyHandle {
                 key_name,
                 ptr::addr_of!(passphrase_key).cast(),
                 mem::size_of_val(&passphrase_key),
-                keyring.id(),
+                keyring.id()?,
             )
         };
 
         if key_id > 0 {
             info!("Added key to keyring");
+
+            if let Some(timeout) = timeout {
+                let rc = unsafe { keyutils::keyctl_set_timeout(key_id, timeout.as_secs() as u32) };
+                if rc != 0 {
+                    warn!("failed to set keyring timeout on new key: {}", errno::errno());
+                }
+            }
+
             Ok(KeyHandle {
                 _uuid: sb.sb().uuid(),
                 _id:   c_long::from(key_id),
@@ -261,6 +332,84 @@ impl Passphrase {
         Ok(Self(CString::new(line.trim_end_matches('\n'))?))
     }
 
+    /// Read a single `\n`-terminated passphrase from an arbitrary descriptor
+    /// (a FIFO, a socket-activation fd, ...). Blocks until a full line or EOF
+    /// is seen.
+    pub fn new_from_fd(fd: BorrowedFd) -> Result<Self> {
+        let mut line = Zeroizing::new(Vec::<u8>::new());
+        let mut byte = [0u8; 1];
+
+        loop {
+            match rustix::io::read(fd, &mut byte) {
+                Ok(0) => {
+                    ensure!(!line.is_empty(), "unexpected EOF reading passphrase from descriptor");
+                    break;
+                }
+                Ok(_) if byte[0] == b'\n' => break,
+                Ok(_) => line.push(byte[0]),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Self(CString::new(line.as_slice())?))
+    }
+
+    /// Read passphrases from `fd` one line at a time, trying up to
+    /// `max_attempts` lines in all (sleeping `backoff` between attempts) when a
+    /// line fails [`Self::check`], instead of aborting on the first wrong line.
+    /// Used by [`UnlockPolicy::Fd`] so orchestration tools can feed several
+    /// candidate passphrases over a single pipe.
+    pub fn new_from_fd_retrying(
+        fd: BorrowedFd,
+        sb: &bch_sb_handle,
+        max_attempts: u32,
+        backoff: Duration,
+    ) -> Result<Self> {
+        let mut last_err = None;
+
+        for attempt in 1..=max_attempts.max(1) {
+            let passphrase = Self::new_from_fd(fd)?;
+
+            match passphrase.check(sb) {
+                Ok(_) => return Ok(passphrase),
+                Err(e) => {
+                    debug!("passphrase attempt {attempt}/{max_attempts} from descriptor failed: {e}");
+                    last_err = Some(e);
+                    if attempt < max_attempts && !backoff.is_zero() {
+                        thread::sleep(backoff);
+                    }
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| anyhow!("no passphrase attempts were made")))
+    }
+
+    /// Unseal a passphrase previously sealed with [`Self::enroll_tpm2`]. Used
+    /// by [`UnlockPolicy::Tpm2`] to unlock at boot with no human present.
+    pub fn new_from_tpm2(uuid: &Uuid) -> Result<Self> {
+        info!("Unsealing passphrase from TPM2 for {uuid}...");
+
+        let secret = tpm2::unseal(uuid)?;
+        Ok(Self(CString::new(secret.as_slice())?))
+    }
+
+    /// Generate a fresh high-entropy secret, seal it to the TPM under a
+    /// policy bound to `pcrs`, and return it as a `Passphrase` ready to be
+    /// passed through the normal `check`/`derive`/`encrypt_key` path (e.g. to
+    /// re-encrypt a filesystem's key via `set-passphrase`).
+    pub fn enroll_tpm2(uuid: &Uuid, pcrs: &[u32]) -> Result<Self> {
+        // Hex-encoded so the secret can never contain an embedded NUL, which
+        // would truncate the `CString` passphrase it's stored as.
+        let mut raw = Zeroizing::new([0u8; 32]);
+        rustix::rand::getrandom(&mut *raw, rustix::rand::GetRandomFlags::empty())?;
+        let hex = Zeroizing::new(hex_encode(&*raw));
+
+        tpm2::enroll(uuid, hex.as_bytes(), pcrs)?;
+
+        Ok(Self(CString::new(hex.as_bytes())?))
+    }
+
     pub fn new_from_file(passphrase_file: impl AsRef<Path>) -> Result<Self> {
         let passphrase_file = passphrase_file.as_ref();
 
@@ -335,6 +484,34 @@ impl Passphrase {
 
         Ok((passphrase_key, sb_key))
     }
+
+    /// Like [`Self::check`], but against an arbitrary encrypted-key slot
+    /// rather than the superblock's primary crypt key. Used by the keyslot
+    /// subsystem ([`crate::keyslots`]) to try a passphrase against the extra
+    /// recovery slots kept in the sidecar keyslot file.
+    pub fn check_slot(
+        &self,
+        sb: &bch_sb_handle,
+        crypt: &bch_sb_field_crypt,
+        slot: &bch_encrypted_key,
+    ) -> Result<bch_key> {
+        let bch_key_magic = u64::from_le_bytes(*BCH_KEY_MAGIC);
+        let mut sb_key = *slot;
+
+        let mut passphrase_key: bch_key = self.derive(crypt);
+
+        unsafe {
+            bch2_chacha20(
+                ptr::addr_of_mut!(passphrase_key),
+                sb.sb().nonce(),
+                ptr::addr_of_mut!(sb_key).cast(),
+                mem::size_of_val(&sb_key),
+            )
+        };
+        ensure!(sb_key.magic == bch_key_magic, "incorrect passphrase");
+
+        Ok(sb_key.key)
+    }
 }
 
 fn is_dev_null(fd: BorrowedFd) -> io::Result<bool> {
@@ -0,0 +1,157 @@
+//! Extra recovery keyslots: independently-encrypted copies of a filesystem's
+//! master key, so any of several passphrases (e.g. a primary and a printable
+//! recovery phrase) can unlock it.
+//!
+//! bcachefs's on-disk `bch_sb_field_crypt` holds exactly one encrypted key —
+//! that's a fixed, kernel-defined on-disk layout we can't unilaterally widen
+//! to an array without a coordinated format change in the kernel driver.
+//! Instead, extra slots are kept in a sidecar file under
+//! `/etc/bcachefs/keyslots`, keyed by filesystem UUID: each slot is the same master key,
+//! independently re-encrypted under a different passphrase using the same
+//! [`Passphrase::encrypt_key`]/[`Passphrase::check_slot`] chacha20 machinery
+//! the primary crypt field uses.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use bch_bindgen::{
+    bcachefs::{bch_key, bch_sb_handle},
+    c::bch_encrypted_key,
+};
+use uuid::Uuid;
+
+use crate::key::Passphrase;
+
+const MAGIC: u32 = 0x6b73_6c74; // "kslt"
+const VERSION: u32 = 1;
+const SIDECAR_DIR: &str = "/etc/bcachefs/keyslots";
+
+fn sidecar_path(uuid: &Uuid) -> PathBuf {
+    Path::new(SIDECAR_DIR).join(format!("{uuid}.slots"))
+}
+
+/// The extra recovery keyslots recorded for one filesystem.
+struct Keyslots {
+    uuid: Uuid,
+    slots: Vec<bch_encrypted_key>,
+}
+
+impl Keyslots {
+    fn load(uuid: &Uuid) -> Self {
+        Self::try_load(uuid).unwrap_or(Self { uuid: *uuid, slots: Vec::new() })
+    }
+
+    fn try_load(uuid: &Uuid) -> Option<Self> {
+        let buf = fs::read(sidecar_path(uuid)).ok()?;
+        Self::decode(*uuid, &buf)
+    }
+
+    fn decode(uuid: Uuid, buf: &[u8]) -> Option<Self> {
+        let mut off = 0usize;
+        let mut rd_u32 = |buf: &[u8]| -> Option<u32> {
+            let v = u32::from_le_bytes(buf.get(off..off + 4)?.try_into().ok()?);
+            off += 4;
+            Some(v)
+        };
+
+        if rd_u32(buf)? != MAGIC || rd_u32(buf)? != VERSION {
+            return None;
+        }
+
+        let nr_slots = rd_u32(buf)? as usize;
+        let key_size = std::mem::size_of::<bch_encrypted_key>();
+        let mut slots = Vec::with_capacity(nr_slots);
+        for _ in 0..nr_slots {
+            let bytes = buf.get(off..off + key_size)?;
+            off += key_size;
+            // SAFETY: `bch_encrypted_key` is a plain bindgen C struct (no
+            // padding-sensitive invariants); we just wrote these bytes
+            // ourselves in `encode` below.
+            let slot = unsafe { std::ptr::read_unaligned(bytes.as_ptr().cast::<bch_encrypted_key>()) };
+            slots.push(slot);
+        }
+
+        Some(Self { uuid, slots })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MAGIC.to_le_bytes());
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&(self.slots.len() as u32).to_le_bytes());
+
+        for slot in &self.slots {
+            // SAFETY: reading `bch_encrypted_key`'s own bytes back out.
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    (slot as *const bch_encrypted_key).cast::<u8>(),
+                    std::mem::size_of::<bch_encrypted_key>(),
+                )
+            };
+            buf.extend_from_slice(bytes);
+        }
+
+        buf
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::create_dir_all(SIDECAR_DIR).with_context(|| format!("creating {}", SIDECAR_DIR))?;
+
+        let path = sidecar_path(&self.uuid);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, self.encode())?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Try `passphrase` against the filesystem's primary crypt slot, then every
+/// extra recovery keyslot, returning the decrypted master key on the first
+/// slot that produces bcachefs's `bch_key_magic`.
+pub fn check_all(sb: &bch_sb_handle, passphrase: &Passphrase) -> Result<bch_key> {
+    if let Ok((_passphrase_key, sb_key)) = passphrase.check(sb) {
+        return Ok(sb_key.key);
+    }
+
+    let crypt = sb.sb().crypt().context("filesystem is not encrypted")?;
+    let extra = Keyslots::load(&sb.sb().uuid());
+
+    for slot in &extra.slots {
+        if let Ok(key) = passphrase.check_slot(sb, crypt, slot) {
+            return Ok(key);
+        }
+    }
+
+    bail!("incorrect passphrase");
+}
+
+/// Decrypt the master key via `existing` (primary slot or any recovery
+/// slot), then add a new recovery slot encrypting that same master key
+/// under `new`.
+pub fn add_keyslot(sb: &bch_sb_handle, existing: &Passphrase, new: &Passphrase) -> Result<()> {
+    let master_key = check_all(sb, existing)?;
+    let crypt = sb.sb().crypt().context("filesystem is not encrypted")?;
+
+    let mut slots = Keyslots::load(&sb.sb().uuid());
+    slots.slots.push(new.encrypt_key(sb, crypt, &master_key));
+    slots.save()
+}
+
+/// Remove whichever recovery slot `passphrase` unlocks. This only manages
+/// the sidecar recovery slots added by [`add_keyslot`] — removing the
+/// primary on-disk passphrase is still done via `set-passphrase`/
+/// `remove-passphrase`.
+pub fn remove_keyslot(sb: &bch_sb_handle, passphrase: &Passphrase) -> Result<()> {
+    let uuid = sb.sb().uuid();
+    let mut slots = Keyslots::load(&uuid);
+
+    let before = slots.slots.len();
+    slots.slots.retain(|slot| passphrase.check_slot(sb, sb.sb().crypt().unwrap(), slot).is_err());
+
+    if slots.slots.len() == before {
+        bail!("passphrase does not match any recovery keyslot");
+    }
+
+    slots.save()
+}
@@ -0,0 +1,67 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::wrappers::sysfs::{fs_get_devices, read_sysfs_u64};
+
+const SYSFS_BASE: &str = "/sys/fs/bcachefs";
+
+/// Read the filesystem UUID directory names under `/sys/fs/bcachefs`.
+fn filesystems() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(SYSFS_BASE) else { return Vec::new() };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect()
+}
+
+/// Render Prometheus text-format exposition for every mounted bcachefs
+/// filesystem, labelled by device name and filesystem uuid.
+///
+/// Reached via `GET /metrics` on the embedded HTTP server, reusing the
+/// same device enumeration `scrub` uses.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    writeln!(out, "# TYPE bcachefs_dev_sectors gauge").unwrap();
+    writeln!(out, "# HELP bcachefs_dev_sectors Sectors read/written on a device.").unwrap();
+    writeln!(out, "# TYPE bcachefs_dev_ios counter").unwrap();
+    writeln!(out, "# HELP bcachefs_dev_ios Number of read/write IOs completed on a device.").unwrap();
+
+    for uuid in filesystems() {
+        let fs_path = Path::new(SYSFS_BASE).join(&uuid);
+        let Ok(devices) = fs_get_devices(&fs_path) else { continue };
+
+        for dev in &devices {
+            let dev_dir = fs_path.join(format!("dev-{}", dev.idx));
+            let stat_path = dev_dir.join("block").join("stat");
+
+            let Ok(content) = fs::read_to_string(&stat_path) else { continue };
+            let fields: Vec<u64> = content
+                .split_whitespace()
+                .filter_map(|s| s.parse::<u64>().ok())
+                .collect();
+
+            if fields.len() < 7 {
+                continue;
+            }
+
+            let labels = format!("uuid=\"{}\",device=\"{}\"", uuid, dev.dev);
+
+            writeln!(out, "bcachefs_dev_ios{{{},direction=\"read\"}} {}", labels, fields[0]).unwrap();
+            writeln!(out, "bcachefs_dev_sectors{{{},direction=\"read\"}} {}", labels, fields[2]).unwrap();
+            writeln!(out, "bcachefs_dev_ios{{{},direction=\"write\"}} {}", labels, fields[4]).unwrap();
+            writeln!(out, "bcachefs_dev_sectors{{{},direction=\"write\"}} {}", labels, fields[6]).unwrap();
+        }
+
+        let version_path = Path::new(SYSFS_BASE).join(&uuid).join("internal").join("version");
+        if let Ok(version) = read_sysfs_u64(&version_path) {
+            writeln!(out, "# TYPE bcachefs_version gauge").unwrap();
+            writeln!(out, "bcachefs_version{{uuid=\"{}\"}} {}", uuid, version).unwrap();
+        }
+    }
+
+    out
+}
@@ -0,0 +1,89 @@
+//! Parses `/proc/mounts` so destructive or offline-only operations (e.g.
+//! rewriting a superblock's crypt field) can refuse to run against a device
+//! or mount point that's actually in use.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+pub struct Mount {
+    // May be colon-joined (e.g. "dev1:dev2:dev3") for a multi-device
+    // bcachefs mount, so this is kept as the raw field rather than a
+    // `PathBuf`.
+    pub source: String,
+    pub target: PathBuf,
+    pub fstype: String,
+    pub options: String,
+}
+
+fn parse_mounts(content: &str) -> Vec<Mount> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Mount {
+                source: fields.next()?.to_string(),
+                target: PathBuf::from(fields.next()?),
+                fstype: fields.next()?.to_string(),
+                options: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn read_mounts() -> Result<Vec<Mount>> {
+    let content = fs::read_to_string("/proc/mounts").context("reading /proc/mounts")?;
+    Ok(parse_mounts(&content))
+}
+
+/// True if `dev` appears as a (possibly multi-device) mounted source.
+pub fn is_source_mounted(dev: &Path) -> Result<bool> {
+    let dev = dev.canonicalize().unwrap_or_else(|_| dev.to_path_buf());
+    Ok(read_mounts()?
+        .iter()
+        .any(|m| m.source.split(':').any(|s| Path::new(s).canonicalize().map(|p| p == dev).unwrap_or(false))))
+}
+
+/// True if `path` appears as a mount target.
+pub fn is_target_mounted(path: &Path) -> Result<bool> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    Ok(read_mounts()?.iter().any(|m| m.target == path))
+}
+
+/// Refuse to proceed if any of `devs` (as returned by `device_scan::scan_sbs`)
+/// is currently a mounted source, naming the mountpoint rather than just
+/// failing generically — used by offline-mutation commands like
+/// `reset-counters` that assume exclusive access to the device.
+pub fn ensure_unmounted(devs: &[PathBuf]) -> Result<()> {
+    let mounts = read_mounts()?;
+    for dev in devs {
+        let dev = dev.canonicalize().unwrap_or_else(|_| dev.clone());
+        if let Some(m) = mounts
+            .iter()
+            .find(|m| m.source.split(':').any(|s| Path::new(s).canonicalize().map(|p| p == dev).unwrap_or(false)))
+        {
+            anyhow::bail!(
+                "{} is mounted at {}; refusing to modify a live filesystem",
+                dev.display(),
+                m.target.display(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The device(s) backing a mounted bcachefs target, e.g. split from
+/// "dev1:dev2:dev3" for a multi-device filesystem.
+pub fn devices_for_mount(path: &Path) -> Result<Vec<PathBuf>> {
+    let path = path.canonicalize().with_context(|| format!("resolving {}", path.display()))?;
+    let mounts = read_mounts()?;
+    let mount = mounts
+        .iter()
+        .find(|m| m.target == path)
+        .with_context(|| format!("mount point not found: {}", path.display()))?;
+
+    anyhow::ensure!(mount.fstype == "bcachefs", "{} is not a bcachefs mount (found: {})", path.display(), mount.fstype);
+
+    Ok(mount.source.split(':').map(PathBuf::from).collect())
+}
@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use clap::{Arg, Command};
-use prometheus::{GaugeVec, Registry};
+use prometheus::{GaugeVec, HistogramVec, Registry};
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
@@ -11,8 +11,16 @@ struct BcacheFSCollector {
     base_path: String,
     metrics: HashMap<String, GaugeVec>,
     btree_metrics: HashMap<String, GaugeVec>,
+    latency_metrics: HashMap<String, HistogramVec>,
 }
 
+/// Fixed microsecond bucket boundaries for the latency histograms, which are
+/// fed the percentile values (50th through 99.99th) read from the kernel's
+/// time-stats files.
+const LATENCY_BUCKETS_USECS: &[f64] = &[
+    5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 25000.0, 50000.0, 100000.0,
+];
+
 struct DiskStats {
     read_ios: u64,
     read_sectors: u64,
@@ -26,6 +34,7 @@ impl BcacheFSCollector {
             base_path: "/sys/fs/bcachefs".to_string(),
             metrics: HashMap::new(),
             btree_metrics: HashMap::new(),
+            latency_metrics: HashMap::new(),
         }
     }
 
@@ -71,6 +80,30 @@ impl BcacheFSCollector {
             self.metrics.insert(name.to_string(), metric);
         }
 
+        let compression_metrics = vec![
+            ("compressed_bytes", "Bytes stored on disk after compression"),
+            ("uncompressed_bytes", "Bytes that would be used without compression"),
+        ];
+
+        for (name, description) in compression_metrics {
+            let opts = prometheus::opts!(name, description);
+            let metric = GaugeVec::new(opts, &["uuid"])?;
+            registry.register(Box::new(metric.clone()))?;
+            self.metrics.insert(name.to_string(), metric);
+        }
+
+        let latency_metrics = vec![
+            ("io_latency_read_usecs", "Read IO latency percentile samples, in microseconds"),
+            ("io_latency_write_usecs", "Write IO latency percentile samples, in microseconds"),
+        ];
+
+        for (name, description) in latency_metrics {
+            let opts = prometheus::histogram_opts!(name, description, LATENCY_BUCKETS_USECS.to_vec());
+            let metric = HistogramVec::new(opts, &["uuid", "device"])?;
+            registry.register(Box::new(metric.clone()))?;
+            self.latency_metrics.insert(name.to_string(), metric);
+        }
+
         Ok(())
     }
 
@@ -120,9 +153,11 @@ impl BcacheFSCollector {
             for (dev_dir, label) in &labels {
                 self.collect_alloc_debug_metrics(&uuid, dev_dir, label)?;
                 self.collect_disk_stats(&uuid, dev_dir, label)?;
+                self.collect_latency_stats(&uuid, dev_dir, label)?;
             }
 
             self.collect_btree_metrics(&uuid, registry)?;
+            self.collect_compression_stats(&uuid)?;
         }
 
         Ok(())
@@ -174,6 +209,54 @@ impl BcacheFSCollector {
         Ok(())
     }
 
+    fn collect_latency_stats(&self, uuid: &str, dev_dir: &str, label: &str) -> Result<()> {
+        let files = [
+            ("io_latency_read", "io_latency_read_usecs"),
+            ("io_latency_write", "io_latency_write_usecs"),
+        ];
+
+        for (file_name, metric_name) in files {
+            let stat_path: PathBuf = [&self.base_path, uuid, dev_dir, file_name].iter().collect();
+            let Ok(content) = fs::read_to_string(&stat_path) else { continue };
+            let Some(metric) = self.latency_metrics.get(metric_name) else { continue };
+
+            let histogram = metric.get_metric_with_label_values(&[uuid, label])?;
+            for (_percentile, usecs) in parse_latency_percentiles(&content) {
+                histogram.observe(usecs);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_compression_stats(&self, uuid: &str) -> Result<()> {
+        let accounting_file: PathBuf = [&self.base_path, uuid, "internal", "accounting"]
+            .iter()
+            .collect();
+
+        if !accounting_file.exists() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(accounting_file)?;
+        let re = Regex::new(r"^compression (compressed|uncompressed) sectors=(\d+)$")?;
+
+        for line in content.lines() {
+            if let Some(captures) = re.captures(line) {
+                let kind = captures.get(1).context("Missing compression kind")?.as_str();
+                let sectors: u64 = captures.get(2).context("Missing compression sectors")?.as_str().parse()?;
+                let metric_name = format!("{}_bytes", kind);
+                if let Some(metric) = self.metrics.get(&metric_name) {
+                    metric
+                        .get_metric_with_label_values(&[uuid])?
+                        .set((sectors * 512) as f64);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn collect_btree_metrics(&mut self, uuid: &str, registry: &Registry) -> Result<()> {
         let accounting_file: PathBuf = [&self.base_path, uuid, "internal", "accounting"]
             .iter()
@@ -235,6 +318,28 @@ fn read_disk_stats(stat_path: &Path) -> Result<DiskStats> {
     })
 }
 
+/// Parse the `duration percentiles (usecs):` block of a bcachefs time-stats
+/// sysfs file into `(percentile label, microseconds)` pairs. Returns an
+/// empty vec if the file doesn't contain that section (e.g. no samples yet).
+fn parse_latency_percentiles(content: &str) -> Vec<(String, f64)> {
+    let mut lines = content.lines();
+
+    while let Some(line) = lines.next() {
+        if line.contains("duration percentiles") {
+            let header: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+            let values: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+
+            return header
+                .iter()
+                .zip(values.iter())
+                .filter_map(|(label, value)| value.parse::<f64>().ok().map(|v| (label.to_string(), v)))
+                .collect();
+        }
+    }
+
+    Vec::new()
+}
+
 fn start_exporter(address: SocketAddr) -> Result<()> {
     let mut collector = BcacheFSCollector::new();
     let registry = Registry::new_custom(Some("bcachefs".to_string()), None)
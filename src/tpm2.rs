@@ -0,0 +1,185 @@
+//! TPM2-sealed secrets used by [`crate::key::UnlockPolicy::Tpm2`] to unlock an
+//! encrypted filesystem at boot with no interactive prompt.
+//!
+//! A sealed secret is an opaque blob produced by the TPM's owner hierarchy,
+//! wrapped in a policy that only releases the secret when the platform's PCRs
+//! match the values recorded at seal time (e.g. firmware/bootloader
+//! measurements). We never see the secret ourselves outside of enrollment and
+//! unseal: the TPM either hands it back, or fails the policy check and hands
+//! back nothing.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use tss_esapi::{
+    abstraction::pcr,
+    attributes::ObjectAttributesBuilder,
+    interface_types::{algorithm::HashingAlgorithm, resource_handles::Hierarchy},
+    structures::{Digest, PcrSelectionListBuilder, PcrSlot, SensitiveData},
+    tcti_ldr::TctiNameConf,
+    Context as TpmContext,
+};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+const MAGIC: u32 = 0x74706d32; // "tpm2"
+const VERSION: u32 = 1;
+
+const SIDECAR_DIR: &str = "/etc/bcachefs/tpm2-seal";
+
+fn sidecar_path(uuid: &Uuid) -> PathBuf {
+    Path::new(SIDECAR_DIR).join(format!("{uuid}.seal"))
+}
+
+fn pcr_slot(pcr: u32) -> Result<PcrSlot> {
+    PcrSlot::try_from(pcr).map_err(|_| anyhow!("invalid PCR index {pcr} (expected 0-23)"))
+}
+
+/// A sealed secret and the PCR set its unseal policy is bound to, as stored
+/// in the sidecar file.
+pub struct SealedSecret {
+    pcrs: Vec<u32>,
+    public: Vec<u8>,
+    private: Vec<u8>,
+}
+
+impl SealedSecret {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MAGIC.to_le_bytes());
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+
+        buf.extend_from_slice(&(self.pcrs.len() as u32).to_le_bytes());
+        for &p in &self.pcrs {
+            buf.extend_from_slice(&p.to_le_bytes());
+        }
+
+        buf.extend_from_slice(&(self.public.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.public);
+        buf.extend_from_slice(&(self.private.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.private);
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self> {
+        let off = std::cell::Cell::new(0usize);
+        let rd_u32 = |buf: &[u8]| -> Result<u32> {
+            let v = u32::from_le_bytes(
+                buf.get(off.get()..off.get() + 4).context("truncated tpm2 seal file")?.try_into()?,
+            );
+            off.set(off.get() + 4);
+            Ok(v)
+        };
+
+        if rd_u32(buf)? != MAGIC {
+            anyhow::bail!("not a tpm2 seal file");
+        }
+        if rd_u32(buf)? != VERSION {
+            anyhow::bail!("unsupported tpm2 seal file version");
+        }
+
+        let nr_pcrs = rd_u32(buf)? as usize;
+        let mut pcrs = Vec::with_capacity(nr_pcrs);
+        for _ in 0..nr_pcrs {
+            pcrs.push(rd_u32(buf)?);
+        }
+
+        let public_len = rd_u32(buf)? as usize;
+        let public = buf.get(off.get()..off.get() + public_len).context("truncated tpm2 seal file")?.to_vec();
+        off.set(off.get() + public_len);
+
+        let private_len = rd_u32(buf)? as usize;
+        let private = buf.get(off.get()..off.get() + private_len).context("truncated tpm2 seal file")?.to_vec();
+
+        Ok(Self { pcrs, public, private })
+    }
+
+    pub fn load(uuid: &Uuid) -> Result<Self> {
+        let path = sidecar_path(uuid);
+        let buf = fs::read(&path)
+            .with_context(|| format!("reading tpm2 seal file {}", path.display()))?;
+        Self::decode(&buf)
+    }
+
+    pub fn save(&self, uuid: &Uuid) -> Result<()> {
+        let path = sidecar_path(uuid);
+        fs::create_dir_all(SIDECAR_DIR)
+            .with_context(|| format!("creating {}", SIDECAR_DIR))?;
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, self.encode())?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+fn open_tpm() -> Result<TpmContext> {
+    TpmContext::new(TctiNameConf::from_environment_variable().unwrap_or_default())
+        .context("opening TPM2 device (is /dev/tpmrm0 accessible?)")
+}
+
+fn pcr_policy_digest(ctx: &mut TpmContext, pcrs: &[u32]) -> Result<Digest> {
+    let slots: Vec<PcrSlot> = pcrs.iter().map(|&p| pcr_slot(p)).collect::<Result<_>>()?;
+    let selection = PcrSelectionListBuilder::new()
+        .with_selection(HashingAlgorithm::Sha256, &slots)
+        .build()
+        .context("building PCR selection")?;
+
+    pcr::read_pcr_digest(ctx, &selection, HashingAlgorithm::Sha256)
+        .context("reading current PCR values")
+}
+
+/// Seal `secret` under a policy that requires `pcrs` to match their current
+/// values, and write the result to the per-filesystem sidecar file.
+pub fn enroll(uuid: &Uuid, secret: &[u8], pcrs: &[u32]) -> Result<()> {
+    anyhow::ensure!(!pcrs.is_empty(), "at least one PCR is required");
+
+    let mut ctx = open_tpm()?;
+    let policy_digest = pcr_policy_digest(&mut ctx, pcrs)?;
+
+    let attributes = ObjectAttributesBuilder::new()
+        .with_fixed_tpm(true)
+        .with_fixed_parent(true)
+        .with_no_da(true)
+        .with_admin_with_policy(true)
+        .build()
+        .context("building sealed-object attributes")?;
+
+    let sensitive_data = SensitiveData::try_from(secret.to_vec())
+        .context("secret too large to seal directly")?;
+
+    let (public, private) = tss_esapi::abstraction::seal::seal(
+        &mut ctx,
+        Hierarchy::Owner,
+        attributes,
+        sensitive_data,
+        Some(policy_digest),
+    )
+    .context("sealing secret to TPM")?;
+
+    SealedSecret { pcrs: pcrs.to_vec(), public: public.into(), private: private.into() }
+        .save(uuid)
+        .context("writing tpm2 seal file")
+}
+
+/// Unseal the secret previously enrolled for `uuid`. Fails closed: if the
+/// current PCR state doesn't match what the policy was bound to, this
+/// returns a plain "could not unseal" error rather than any garbled data.
+pub fn unseal(uuid: &Uuid) -> Result<Zeroizing<Vec<u8>>> {
+    let sealed = SealedSecret::load(uuid)?;
+
+    let mut ctx = open_tpm()?;
+    let policy_digest = pcr_policy_digest(&mut ctx, &sealed.pcrs)?;
+
+    let secret = tss_esapi::abstraction::seal::unseal(
+        &mut ctx,
+        Hierarchy::Owner,
+        sealed.public.into(),
+        sealed.private.into(),
+        Some(policy_digest),
+    )
+    .map_err(|_| anyhow!("could not unseal TPM2 secret: PCR state does not match enrollment"))?;
+
+    Ok(Zeroizing::new(secret.into()))
+}
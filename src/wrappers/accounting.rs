@@ -282,6 +282,27 @@ pub fn prt_reconcile_type(out: &mut Printbuf, t: bch_reconcile_accounting_type)
     unsafe { c::bch2_prt_reconcile_accounting_type(out.as_raw(), t) }
 }
 
+/// Data type name, as a standalone string.
+pub fn data_type_str(t: u8) -> String {
+    let mut out = Printbuf::new();
+    prt_data_type(&mut out, unsafe { std::mem::transmute(t as u32) });
+    out.as_str().to_string()
+}
+
+/// Compression type name, as a standalone string.
+pub fn compression_type_str(t: bch_compression_type) -> String {
+    let mut out = Printbuf::new();
+    prt_compression_type(&mut out, t);
+    out.as_str().to_string()
+}
+
+/// Reconcile accounting type name, as a standalone string.
+pub fn reconcile_type_str(t: bch_reconcile_accounting_type) -> String {
+    let mut out = Printbuf::new();
+    prt_reconcile_type(&mut out, t);
+    out.as_str().to_string()
+}
+
 /// Get a btree ID name string.
 pub fn btree_id_str(id: u32) -> String {
     // bch2_btree_id_str takes an enum btree_id; we transmute from u32
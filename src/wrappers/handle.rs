@@ -3,24 +3,30 @@ use std::mem;
 use std::os::fd::BorrowedFd;
 use std::path::Path;
 
+use bch_bindgen::bcachefs::btree_id;
 use bch_bindgen::c::{
     bcache_fs_close, bcache_fs_open_fallible,
-    bch_data_type,
+    bch_data_ops, bch_data_type,
+    bch_ioctl_data, bch_ioctl_data_progress,
+    bch_ioctl_fsck_online,
     bch_ioctl_dev_usage, bch_ioctl_dev_usage_v2,
     bch_ioctl_dev_usage_bch_ioctl_dev_usage_type,
+    bch_ioctl_fs_usage, bch_ioctl_fs_usage_v2,
     bch_ioctl_disk, bch_ioctl_disk_v2,
     bch_ioctl_disk_set_state, bch_ioctl_disk_set_state_v2,
     bch_ioctl_disk_resize, bch_ioctl_disk_resize_v2,
     bch_ioctl_disk_resize_journal, bch_ioctl_disk_resize_journal_v2,
+    bch_ioctl_disk_get_idx, bch_ioctl_query_uuid, bch_ioctl_read_super,
     bch_ioctl_subvolume, bch_ioctl_subvolume_v2,
-    bchfs_handle,
+    bchfs_handle, bpos,
     BCH_BY_INDEX, BCH_SUBVOL_SNAPSHOT_CREATE,
 };
-use crate::wrappers::ioctl::bch_ioc_wr;
+use crate::wrappers::ioctl::{bch_ioc_w, bch_ioc_wr, bch_ioc_wr_sized};
 use bch_bindgen::errcode::{BchError, ret_to_result};
 use bch_bindgen::path_to_cstr;
 use errno::Errno;
-use rustix::ioctl::{self, CompileTimeOpcode, Setter, WriteOpcode};
+use rustix::ioctl::{self, CompileTimeOpcode, ReadWriteOpcode, Setter, Updater, WriteOpcode};
+use std::os::unix::io::FromRawFd;
 
 /// Try a v2 ioctl (with error message buffer), falling back to v1 on ENOTTY.
 macro_rules! v2_v1_ioctl {
@@ -34,12 +40,12 @@ macro_rules! v2_v1_ioctl {
             Ok(()) => Ok(()),
             Err(e) if e == rustix::io::Errno::NOTTY => {
                 unsafe { ioctl::ioctl($fd, Setter::<$V1, _>::new($v1_arg)) }
-                    .map_err(|e| Errno(e.raw_os_error()))
-            }
-            Err(e) => {
-                print_errmsg(&err_buf);
-                Err(Errno(e.raw_os_error()))
+                    .map_err(|e| BcachefsIoctlError { errno: Errno(e.raw_os_error()), msg: None })
             }
+            Err(e) => Err(BcachefsIoctlError {
+                errno: Errno(e.raw_os_error()),
+                msg: read_errmsg(&err_buf),
+            }),
         }
     }};
 }
@@ -64,6 +70,13 @@ type DiskResizeV2Opcode    = WriteOpcode<0xbc, 27, bch_ioctl_disk_resize_v2>;
 type DiskResizeJournalOpcode   = WriteOpcode<0xbc, 15, bch_ioctl_disk_resize_journal>;
 type DiskResizeJournalV2Opcode = WriteOpcode<0xbc, 28, bch_ioctl_disk_resize_journal_v2>;
 
+// Typed read (_IOWR) opcodes, fixed-size arguments only — the flex-array
+// ioctls (dev_usage, fs_usage) stay on the raw-buffer path; see
+// `flex_read_ioctl`.
+type QueryUuidOpcode  = ReadWriteOpcode<0xbc, 1,  bch_ioctl_query_uuid>;
+type DiskGetIdxOpcode = ReadWriteOpcode<0xbc, 9,  bch_ioctl_disk_get_idx>;
+type ReadSuperOpcode  = ReadWriteOpcode<0xbc, 13, bch_ioctl_read_super>;
+
 /// A handle to a bcachefs filesystem
 /// This can be used to send [`libc::ioctl`] to the underlying filesystem.
 pub(crate) struct BcachefsHandle {
@@ -102,6 +115,76 @@ impl BcachefsHandle {
         unsafe { BorrowedFd::borrow_raw(self.ioctl_fd_raw()) }
     }
 
+    /// Issue a typed `_IOWR` read ioctl via rustix's `Updater`, returning the
+    /// ioctl-mutated argument on success. `Arg` must be fixed-size; any
+    /// variable-length data lives behind a pointer field the caller
+    /// allocates separately (as in [`BcachefsHandle::read_super`]).
+    fn read_ioctl<Op: CompileTimeOpcode, Arg: Copy>(&self, arg: Arg) -> Result<Arg, BcachefsIoctlError> {
+        let mut arg = arg;
+        unsafe { ioctl::ioctl(self.ioctl_fd(), Updater::<Op, _>::new(&mut arg)) }
+            .map(|()| arg)
+            .map_err(|e| BcachefsIoctlError { errno: Errno(e.raw_os_error()), msg: None })
+    }
+
+    /// Query this filesystem's UUID via `BCH_IOCTL_QUERY_UUID`.
+    pub(crate) fn query_uuid(&self) -> Result<[u8; 16], BcachefsIoctlError> {
+        let arg = bch_ioctl_query_uuid { uuid: [0; 16] };
+        self.read_ioctl::<QueryUuidOpcode, _>(arg).map(|arg| arg.uuid)
+    }
+
+    /// Resolve a device UUID to its filesystem-local device index via
+    /// `BCH_IOCTL_DISK_GET_IDX`.
+    pub(crate) fn disk_get_idx(&self, uuid: [u8; 16]) -> Result<u64, BcachefsIoctlError> {
+        let arg = bch_ioctl_disk_get_idx { uuid, idx: 0 };
+        self.read_ioctl::<DiskGetIdxOpcode, _>(arg).map(|arg| arg.idx)
+    }
+
+    /// Copy the on-disk superblock for a device into a `size`-byte buffer
+    /// via `BCH_IOCTL_READ_SUPER`.
+    pub(crate) fn read_super(&self, dev_idx: u32, size: usize) -> Result<Vec<u8>, BcachefsIoctlError> {
+        let mut buf = vec![0u8; size];
+        let arg = bch_ioctl_read_super {
+            sb_ptr: buf.as_mut_ptr() as u64,
+            size: size as u32,
+            flags: BCH_BY_INDEX,
+            dev: dev_idx as u64,
+        };
+        self.read_ioctl::<ReadSuperOpcode, _>(arg)?;
+        Ok(buf)
+    }
+
+    /// Issue a variable-length `_IOWR` read ioctl whose v2 struct trails a
+    /// flexible array. Rustix's typed `Updater` needs a fixed-size argument,
+    /// so this goes through a raw `libc::ioctl` call into heap buffers sized
+    /// by the caller, but keeps the same v2-then-v1 ENOTTY fallback as the
+    /// `v2_v1_ioctl!` setters. Returns `true` if the v2 call
+    /// succeeded (`v2_buf` populated), `false` if it fell back to v1
+    /// (`v1_buf` populated).
+    fn flex_read_ioctl(
+        &self,
+        v2_nr: u32, v2_buf: &mut [u8],
+        v1_nr: u32, v1_buf: &mut [u8],
+    ) -> Result<bool, BcachefsIoctlError> {
+        let request = bch_ioc_wr_sized(v2_nr, v2_buf.len());
+        let ret = unsafe { libc::ioctl(self.ioctl_fd_raw(), request, v2_buf.as_mut_ptr()) };
+        if ret == 0 {
+            return Ok(true);
+        }
+
+        let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        if errno != libc::ENOTTY {
+            return Err(BcachefsIoctlError { errno: Errno(errno), msg: None });
+        }
+
+        let request_v1 = bch_ioc_wr_sized(v1_nr, v1_buf.len());
+        let ret = unsafe { libc::ioctl(self.ioctl_fd_raw(), request_v1, v1_buf.as_mut_ptr()) };
+        if ret < 0 {
+            let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+            return Err(BcachefsIoctlError { errno: Errno(errno), msg: None });
+        }
+        Ok(false)
+    }
+
     fn subvol_ioctl<V2: CompileTimeOpcode, V1: CompileTimeOpcode>(
         &self,
         flags: u32,
@@ -109,7 +192,7 @@ impl BcachefsHandle {
         mode: u16,
         dst_ptr: u64,
         src_ptr: u64,
-    ) -> Result<(), Errno> {
+    ) -> Result<(), BcachefsIoctlError> {
         v2_v1_ioctl!(
             self.ioctl_fd(), V2, V1,
             bch_ioctl_subvolume_v2 { flags, dirfd, mode, dst_ptr, src_ptr, ..Default::default() },
@@ -119,7 +202,7 @@ impl BcachefsHandle {
 
     /// Create a subvolume for this bcachefs filesystem
     /// at the given path
-    pub fn create_subvolume<P: AsRef<Path>>(&self, dst: P) -> Result<(), Errno> {
+    pub fn create_subvolume<P: AsRef<Path>>(&self, dst: P) -> Result<(), BcachefsIoctlError> {
         let dst = path_to_cstr(dst);
         self.subvol_ioctl::<SubvolCreateV2Opcode, SubvolCreateOpcode>(
             0,
@@ -132,7 +215,7 @@ impl BcachefsHandle {
 
     /// Delete the subvolume at the given path
     /// for this bcachefs filesystem
-    pub fn delete_subvolume<P: AsRef<Path>>(&self, dst: P) -> Result<(), Errno> {
+    pub fn delete_subvolume<P: AsRef<Path>>(&self, dst: P) -> Result<(), BcachefsIoctlError> {
         let dst = path_to_cstr(dst);
         self.subvol_ioctl::<SubvolDestroyV2Opcode, SubvolDestroyOpcode>(
             0,
@@ -150,7 +233,7 @@ impl BcachefsHandle {
         extra_flags: u32,
         src: Option<P>,
         dst: P,
-    ) -> Result<(), Errno> {
+    ) -> Result<(), BcachefsIoctlError> {
         let src = src.map(|src| path_to_cstr(src));
         let dst = path_to_cstr(dst);
         self.subvol_ioctl::<SubvolCreateV2Opcode, SubvolCreateOpcode>(
@@ -164,7 +247,7 @@ impl BcachefsHandle {
 
     fn disk_ioctl<V2: CompileTimeOpcode, V1: CompileTimeOpcode>(
         &self, flags: u32, dev: u64,
-    ) -> Result<(), Errno> {
+    ) -> Result<(), BcachefsIoctlError> {
         v2_v1_ioctl!(
             self.ioctl_fd(), V2, V1,
             bch_ioctl_disk_v2 { flags, dev, ..Default::default() },
@@ -173,28 +256,28 @@ impl BcachefsHandle {
     }
 
     /// Remove a device (by index) from this filesystem.
-    pub(crate) fn disk_remove(&self, dev_idx: u32, flags: u32) -> Result<(), Errno> {
+    pub(crate) fn disk_remove(&self, dev_idx: u32, flags: u32) -> Result<(), BcachefsIoctlError> {
         self.disk_ioctl::<DiskRemoveV2Opcode, DiskRemoveOpcode>(
             flags | BCH_BY_INDEX, dev_idx as u64,
         )
     }
 
     /// Re-add an offline device to this filesystem.
-    pub(crate) fn disk_online(&self, dev_path: &CStr) -> Result<(), Errno> {
+    pub(crate) fn disk_online(&self, dev_path: &CStr) -> Result<(), BcachefsIoctlError> {
         self.disk_ioctl::<DiskOnlineV2Opcode, DiskOnlineOpcode>(
             0, dev_path.as_ptr() as u64,
         )
     }
 
     /// Take a device offline without removing it.
-    pub(crate) fn disk_offline(&self, dev_idx: u32, flags: u32) -> Result<(), Errno> {
+    pub(crate) fn disk_offline(&self, dev_idx: u32, flags: u32) -> Result<(), BcachefsIoctlError> {
         self.disk_ioctl::<DiskOfflineV2Opcode, DiskOfflineOpcode>(
             flags | BCH_BY_INDEX, dev_idx as u64,
         )
     }
 
     /// Change device state (rw, ro, evacuating, spare).
-    pub(crate) fn disk_set_state(&self, dev_idx: u32, new_state: u32, flags: u32) -> Result<(), Errno> {
+    pub(crate) fn disk_set_state(&self, dev_idx: u32, new_state: u32, flags: u32) -> Result<(), BcachefsIoctlError> {
         v2_v1_ioctl!(
             self.ioctl_fd(), DiskSetStateV2Opcode, DiskSetStateOpcode,
             bch_ioctl_disk_set_state_v2 { flags: flags | BCH_BY_INDEX, new_state: new_state as u8, dev: dev_idx as u64, ..Default::default() },
@@ -203,7 +286,7 @@ impl BcachefsHandle {
     }
 
     /// Resize filesystem on a device.
-    pub(crate) fn disk_resize(&self, dev_idx: u32, nbuckets: u64) -> Result<(), Errno> {
+    pub(crate) fn disk_resize(&self, dev_idx: u32, nbuckets: u64) -> Result<(), BcachefsIoctlError> {
         v2_v1_ioctl!(
             self.ioctl_fd(), DiskResizeV2Opcode, DiskResizeOpcode,
             bch_ioctl_disk_resize_v2 { flags: BCH_BY_INDEX, dev: dev_idx as u64, nbuckets, ..Default::default() },
@@ -212,7 +295,7 @@ impl BcachefsHandle {
     }
 
     /// Resize journal on a device.
-    pub(crate) fn disk_resize_journal(&self, dev_idx: u32, nbuckets: u64) -> Result<(), Errno> {
+    pub(crate) fn disk_resize_journal(&self, dev_idx: u32, nbuckets: u64) -> Result<(), BcachefsIoctlError> {
         v2_v1_ioctl!(
             self.ioctl_fd(), DiskResizeJournalV2Opcode, DiskResizeJournalOpcode,
             bch_ioctl_disk_resize_journal_v2 { flags: BCH_BY_INDEX, dev: dev_idx as u64, nbuckets, ..Default::default() },
@@ -220,74 +303,294 @@ impl BcachefsHandle {
         )
     }
 
-    /// Query device usage (v2 with flex array, v1 fallback).
-    pub(crate) fn dev_usage(&self, dev_idx: u32) -> Result<DevUsage, Errno> {
+    /// Query device usage (v2 with flex array, v1 fallback) via
+    /// [`flex_read_ioctl`](Self::flex_read_ioctl).
+    pub(crate) fn dev_usage(&self, dev_idx: u32) -> Result<DevUsage, BcachefsIoctlError> {
         let nr_data_types = bch_data_type::BCH_DATA_NR as usize;
         let entry_size = mem::size_of::<bch_ioctl_dev_usage_bch_ioctl_dev_usage_type>();
         let hdr_size = mem::size_of::<bch_ioctl_dev_usage_v2>();
-        let buf_size = hdr_size + nr_data_types * entry_size;
-        let mut buf = vec![0u8; buf_size];
-
-        // Fill header
+        let mut v2_buf = vec![0u8; hdr_size + nr_data_types * entry_size];
         unsafe {
-            let hdr = &mut *(buf.as_mut_ptr() as *mut bch_ioctl_dev_usage_v2);
+            let hdr = &mut *(v2_buf.as_mut_ptr() as *mut bch_ioctl_dev_usage_v2);
             hdr.dev = dev_idx as u64;
             hdr.flags = BCH_BY_INDEX;
             hdr.nr_data_types = nr_data_types as u8;
         }
 
-        let request = bch_ioc_wr::<bch_ioctl_dev_usage_v2>(18);
-        let ret = unsafe { libc::ioctl(self.ioctl_fd_raw(), request, buf.as_mut_ptr()) };
+        let mut v1_buf = vec![0u8; mem::size_of::<bch_ioctl_dev_usage>()];
+        unsafe {
+            let hdr = &mut *(v1_buf.as_mut_ptr() as *mut bch_ioctl_dev_usage);
+            hdr.dev = dev_idx as u64;
+            hdr.flags = BCH_BY_INDEX;
+        }
+
+        let used_v2 = self.flex_read_ioctl(18, &mut v2_buf, 11, &mut v1_buf)?;
 
-        if ret == 0 {
-            // v2 succeeded â€” parse result
-            let hdr = unsafe { &*(buf.as_ptr() as *const bch_ioctl_dev_usage_v2) };
+        if used_v2 {
+            let hdr = unsafe { &*(v2_buf.as_ptr() as *const bch_ioctl_dev_usage_v2) };
             let actual_nr = hdr.nr_data_types as usize;
-            let data_ptr = unsafe { buf.as_ptr().add(hdr_size) }
+            let data_ptr = unsafe { v2_buf.as_ptr().add(hdr_size) }
                 as *const bch_ioctl_dev_usage_bch_ioctl_dev_usage_type;
 
             let mut data_types = Vec::with_capacity(actual_nr);
             for i in 0..actual_nr {
                 let d = unsafe { std::ptr::read_unaligned(data_ptr.add(i)) };
-                data_types.push(DevUsageType { sectors: d.sectors });
+                data_types.push(DevUsageType { buckets: d.buckets, sectors: d.sectors, fragmented: d.fragmented });
             }
 
-            return Ok(DevUsage {
+            Ok(DevUsage {
                 state: hdr.state,
                 bucket_size: hdr.bucket_size,
                 nr_buckets: hdr.nr_buckets,
                 data_types,
-            });
+            })
+        } else {
+            let hdr = unsafe { &*(v1_buf.as_ptr() as *const bch_ioctl_dev_usage) };
+            let mut data_types = Vec::new();
+            for d in &hdr.d {
+                data_types.push(DevUsageType { buckets: d.buckets, sectors: d.sectors, fragmented: d.fragmented });
+            }
+
+            Ok(DevUsage {
+                state: hdr.state,
+                bucket_size: hdr.bucket_size,
+                nr_buckets: hdr.nr_buckets,
+                data_types,
+            })
         }
+    }
 
-        let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
-        if errno != libc::ENOTTY {
-            return Err(Errno(errno));
+    /// Query whole-filesystem usage (v2 with a flexible per-replica-set
+    /// array, v1 fallback with a fixed replica cap) via `BCH_IOCTL_FS_USAGE`.
+    pub(crate) fn fs_usage(&self) -> Result<FsUsage, BcachefsIoctlError> {
+        const FS_USAGE_V1_MAX_REPLICAS: usize = 16;
+        const FS_USAGE_V1_ENTRY_SIZE: usize = 17; // sectors: u64, data_type: u8, devs_mask: u64
+
+        let hdr_size = mem::size_of::<bch_ioctl_fs_usage_v2>();
+        let mut v2_buf = vec![0u8; hdr_size + FS_USAGE_V1_MAX_REPLICAS * (11 + 8)];
+        let mut v1_buf = vec![0u8; mem::size_of::<bch_ioctl_fs_usage>()
+            + FS_USAGE_V1_MAX_REPLICAS * FS_USAGE_V1_ENTRY_SIZE];
+
+        let used_v2 = self.flex_read_ioctl(20, &mut v2_buf, 3, &mut v1_buf)?;
+
+        if used_v2 {
+            let hdr = unsafe { &*(v2_buf.as_ptr() as *const bch_ioctl_fs_usage_v2) };
+            let total = hdr.replica_entries_bytes as usize;
+            let mut replicas = Vec::new();
+            let mut off = 0usize;
+            while off + 11 <= total {
+                let base = hdr_size + off;
+                let sectors = u64::from_ne_bytes(v2_buf[base..base + 8].try_into().unwrap());
+                let data_type = v2_buf[base + 8];
+                let nr_devs = v2_buf[base + 9] as usize;
+                let devs_start = base + 11;
+                let devs = v2_buf[devs_start..devs_start + nr_devs].to_vec();
+                replicas.push(FsUsageReplica { sectors, data_type, devs });
+                off += 11 + nr_devs;
+            }
+
+            Ok(FsUsage {
+                capacity: hdr.capacity,
+                used: hdr.used,
+                online_reserved: hdr.online_reserved,
+                replicas,
+            })
+        } else {
+            let hdr = unsafe { &*(v1_buf.as_ptr() as *const bch_ioctl_fs_usage) };
+            let nr_replicas = (hdr.nr_replicas as usize).min(FS_USAGE_V1_MAX_REPLICAS);
+            let entries_base = mem::size_of::<bch_ioctl_fs_usage>();
+            let mut replicas = Vec::with_capacity(nr_replicas);
+            for i in 0..nr_replicas {
+                let base = entries_base + i * FS_USAGE_V1_ENTRY_SIZE;
+                let sectors = u64::from_ne_bytes(v1_buf[base..base + 8].try_into().unwrap());
+                let data_type = v1_buf[base + 8];
+                let devs_mask = u64::from_ne_bytes(v1_buf[base + 9..base + 17].try_into().unwrap());
+                let devs = (0..64).filter(|bit| devs_mask & (1 << bit) != 0).collect();
+                replicas.push(FsUsageReplica { sectors, data_type, devs });
+            }
+
+            Ok(FsUsage {
+                capacity: hdr.capacity,
+                used: hdr.used,
+                online_reserved: hdr.online_reserved,
+                replicas,
+            })
         }
+    }
 
-        // v1 fallback
-        let mut u_v1 = bch_ioctl_dev_usage {
-            dev: dev_idx as u64,
-            flags: BCH_BY_INDEX,
+    /// Start a whole-(or bounded-)filesystem data job (rereplicate, migrate,
+    /// rewrite_old_nodes, drop_extra_replicas, ...) via `BCH_IOCTL_DATA`.
+    /// Unlike the `v2_v1_ioctl!` write ioctls, this one hands back a
+    /// thread_with_file descriptor rather than completing synchronously;
+    /// pass `(BTREE_ID_NR, POS_MIN, BTREE_ID_NR, POS_MAX)` for "whole
+    /// filesystem". Progress is then read from the returned [`DataJob`].
+    pub(crate) fn start_data_job(
+        &self,
+        op: bch_data_ops,
+        start_btree: btree_id,
+        start_pos: bpos,
+        end_btree: btree_id,
+        end_pos: bpos,
+    ) -> Result<DataJob, Errno> {
+        let mut cmd = bch_ioctl_data {
+            op: op as u16,
+            start_btree: start_btree as u8,
+            start_pos,
+            end_btree: end_btree as u8,
+            end_pos,
+            ..unsafe { mem::zeroed() }
+        };
+
+        self.fd_ioctl(BCH_IOCTL_DATA_NR, &mut cmd).map(|fd| DataJob { fd, exit_code: None })
+    }
+
+    /// Like [`start_data_job`](Self::start_data_job), but for
+    /// `BCH_DATA_OP_migrate` scoped to a single device (e.g. moving a
+    /// device's data off it before shrinking it).
+    pub(crate) fn start_migrate_dev_job(
+        &self,
+        dev_idx: u32,
+        start_btree: btree_id,
+        start_pos: bpos,
+        end_btree: btree_id,
+        end_pos: bpos,
+    ) -> Result<DataJob, Errno> {
+        let mut cmd = bch_ioctl_data {
+            op: bch_data_ops::BCH_DATA_OP_migrate as u16,
+            start_btree: start_btree as u8,
+            start_pos,
+            end_btree: end_btree as u8,
+            end_pos,
             ..unsafe { mem::zeroed() }
         };
-        let request_v1 = bch_ioc_wr::<bch_ioctl_dev_usage>(11);
-        let ret = unsafe { libc::ioctl(self.ioctl_fd_raw(), request_v1, &mut u_v1 as *mut _) };
+        cmd.__bindgen_anon_1.migrate.dev = dev_idx;
+
+        self.fd_ioctl(BCH_IOCTL_DATA_NR, &mut cmd).map(|fd| DataJob { fd, exit_code: None })
+    }
+
+    /// Run fsck against this already-mounted filesystem via
+    /// `BCH_IOCTL_FSCK_ONLINE`, rather than the offline fsck path. `opts` is
+    /// a comma-separated fsck options string, same as the `fsck` command
+    /// line. Like [`start_data_job`](Self::start_data_job) this hands back a
+    /// thread_with_file descriptor; the caller reads log lines from it until
+    /// EOF, after which the last byte read is the fsck exit code.
+    pub(crate) fn fsck_online(&self, opts: &CStr, flags: u64) -> Result<std::fs::File, Errno> {
+        let mut cmd = bch_ioctl_fsck_online {
+            flags,
+            opts: opts.as_ptr() as u64,
+        };
+
+        self.fd_ioctl(BCH_IOCTL_FSCK_ONLINE_NR, &mut cmd)
+    }
+
+    /// Run a specific set of online recovery passes against this
+    /// already-mounted filesystem, reusing `BCH_IOCTL_FSCK_ONLINE`'s
+    /// thread-with-file log channel but scoped by `pass_mask` (a bitmask of
+    /// `BCH_RECOVERY_PASS_*` values) instead of a full fsck. The ioctl
+    /// number is built with `bch_ioc_wr` rather than `fd_ioctl`'s `bch_ioc_w`,
+    /// since the kernel handler here also writes the accepted pass mask
+    /// back into the argument.
+    pub(crate) fn recovery_pass_online(&self, pass_mask: u64) -> Result<std::fs::File, Errno> {
+        let mut cmd = bch_ioctl_fsck_online { flags: pass_mask, opts: 0 };
+
+        let request = bch_ioc_wr::<bch_ioctl_fsck_online>(BCH_IOCTL_FSCK_ONLINE_NR);
+        let ret = unsafe {
+            libc::ioctl(self.ioctl_fd_raw(), request, &mut cmd as *mut _ as *mut libc::c_void)
+        };
+        if ret < 0 {
+            return Err(Errno(std::io::Error::last_os_error().raw_os_error().unwrap_or(0)));
+        }
+        Ok(unsafe { std::fs::File::from_raw_fd(ret) })
+    }
+
+    /// Issue a "write" ioctl that, unlike the `v2_v1_ioctl!` setters, hands
+    /// back a thread_with_file descriptor instead of completing
+    /// synchronously (`BCH_IOCTL_DATA`, `BCH_IOCTL_FSCK_ONLINE`).
+    fn fd_ioctl<T>(&self, nr: u32, arg: &mut T) -> Result<std::fs::File, Errno> {
+        let request = bch_ioc_w::<T>(nr);
+        let ret = unsafe {
+            libc::ioctl(self.ioctl_fd_raw(), request, arg as *mut T as *mut libc::c_void)
+        };
         if ret < 0 {
             return Err(Errno(std::io::Error::last_os_error().raw_os_error().unwrap_or(0)));
         }
+        Ok(unsafe { std::fs::File::from_raw_fd(ret) })
+    }
+}
+
+const BCH_IOCTL_DATA_NR: u32 = 12;
+const BCH_IOCTL_FSCK_ONLINE_NR: u32 = 19;
+
+/// `bch_ioctl_data_event` is blocklisted from bindgen (packed+aligned
+/// conflict), so it's read as raw bytes: `u8 type, u8 ret, u8 pad[6],
+/// bch_ioctl_data_progress, padding to 128`.
+const DATA_EVENT_SIZE: usize = 128;
+
+fn read_data_event(fd: &mut std::fs::File) -> std::io::Result<(u8, u8, bch_ioctl_data_progress)> {
+    use std::io::Read;
+    let mut buf = [0u8; DATA_EVENT_SIZE];
+    let n = fd.read(&mut buf)?;
+    if n != DATA_EVENT_SIZE {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+            format!("short read from data job fd: {} bytes", n)));
+    }
+    let event_type = buf[0];
+    let event_ret = buf[1];
+    let progress = unsafe {
+        std::ptr::read_unaligned(buf.as_ptr().add(8) as *const bch_ioctl_data_progress)
+    };
+    Ok((event_type, event_ret, progress))
+}
+
+/// A running `BCH_IOCTL_DATA` job, returned by [`BcachefsHandle::start_data_job`].
+pub(crate) struct DataJob {
+    fd: std::fs::File,
+    exit_code: Option<u8>,
+}
+
+/// One progress sample from a [`DataJob`].
+pub(crate) struct DataProgress {
+    pub data_type: u8,
+    pub btree_id: u8,
+    pub pos: bpos,
+    pub sectors_done: u64,
+    pub sectors_total: u64,
+}
 
-        let mut data_types = Vec::new();
-        for d in &u_v1.d {
-            data_types.push(DevUsageType { sectors: d.sectors });
+impl DataJob {
+    /// Read the next progress record, skipping non-progress event types.
+    /// Returns `None` once a terminal event or EOF is seen, after which
+    /// [`DataJob::exit_code`] reports the job's result.
+    pub(crate) fn poll_progress(&mut self) -> Option<DataProgress> {
+        loop {
+            match read_data_event(&mut self.fd) {
+                Ok((event_type, event_ret, p)) => {
+                    if event_ret != 0 {
+                        self.exit_code = Some(event_ret);
+                        return None;
+                    }
+                    if event_type != 0 {
+                        continue;
+                    }
+                    return Some(DataProgress {
+                        data_type: p.data_type,
+                        btree_id: p.btree_id,
+                        pos: p.pos,
+                        sectors_done: p.sectors_done,
+                        sectors_total: p.sectors_total,
+                    });
+                }
+                Err(_) => {
+                    self.exit_code.get_or_insert(0);
+                    return None;
+                }
+            }
         }
+    }
 
-        Ok(DevUsage {
-            state: u_v1.state,
-            bucket_size: u_v1.bucket_size,
-            nr_buckets: u_v1.nr_buckets,
-            data_types,
-        })
+    /// The job's exit code, once [`DataJob::poll_progress`] has returned `None`.
+    pub(crate) fn exit_code(&self) -> Option<u8> {
+        self.exit_code
     }
 }
 
@@ -299,19 +602,57 @@ pub(crate) struct DevUsage {
     pub data_types: Vec<DevUsageType>,
 }
 
+/// Whole-filesystem usage, from [`BcachefsHandle::fs_usage`].
+pub(crate) struct FsUsage {
+    pub capacity: u64,
+    pub used: u64,
+    pub online_reserved: u64,
+    pub replicas: Vec<FsUsageReplica>,
+}
+
+/// Usage of a single replica set (a data type at a given replication
+/// target), from a [`FsUsage`].
+pub(crate) struct FsUsageReplica {
+    pub sectors: u64,
+    pub data_type: u8,
+    pub devs: Vec<u8>,
+}
+
 /// Per-data-type usage on a device.
 pub(crate) struct DevUsageType {
+    pub buckets: u64,
     pub sectors: u64,
+    pub fragmented: u64,
 }
 
-fn print_errmsg(err_buf: &[u8]) {
+fn read_errmsg(err_buf: &[u8]) -> Option<String> {
     let len = err_buf.iter().position(|&b| b == 0).unwrap_or(err_buf.len());
     if len > 0 {
-        let msg = String::from_utf8_lossy(&err_buf[..len]);
-        eprintln!("ioctl error: {}", msg);
+        Some(String::from_utf8_lossy(&err_buf[..len]).into_owned())
+    } else {
+        None
     }
 }
 
+/// Error from a bcachefs ioctl, carrying the kernel's descriptive message
+/// (from the v2 ioctl's error buffer) when one was provided.
+#[derive(Debug)]
+pub(crate) struct BcachefsIoctlError {
+    pub(crate) errno: Errno,
+    pub(crate) msg: Option<String>,
+}
+
+impl std::fmt::Display for BcachefsIoctlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.msg {
+            Some(msg) => write!(f, "{}: {}", self.errno, msg),
+            None => self.errno.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for BcachefsIoctlError {}
+
 impl Drop for BcachefsHandle {
     fn drop(&mut self) {
         unsafe { bcache_fs_close(self.inner) };
@@ -12,3 +12,10 @@ pub const fn bch_ioc_w<T>(nr: u32) -> libc::c_ulong {
 pub const fn bch_ioc_wr<T>(nr: u32) -> libc::c_ulong {
     ((3u32 << 30) | ((mem::size_of::<T>() as u32) << 16) | (0xbcu32 << 8) | nr) as libc::c_ulong
 }
+
+/// Compute a bcachefs _IOWR ioctl number from a runtime size, for ioctls
+/// whose argument is a heap buffer sized at call time (flex-array structs)
+/// rather than a fixed Rust type.
+pub const fn bch_ioc_wr_sized(nr: u32, size: usize) -> libc::c_ulong {
+    ((3u32 << 30) | ((size as u32) << 16) | (0xbcu32 << 8) | nr) as libc::c_ulong
+}
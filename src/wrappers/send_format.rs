@@ -0,0 +1,116 @@
+extern crate crc32fast;
+extern crate zstd;
+#[cfg(feature = "bz2")]
+extern crate bzip2;
+#[cfg(feature = "lzma")]
+extern crate xz2;
+
+use std::io::{self, Write};
+
+/// Stream format magic: "bcachefs subvol-diff send", v1.
+const MAGIC: &[u8; 4] = b"BSND";
+const FORMAT_VERSION: u8 = 1;
+
+/// Compression codec tag stored in the stream header. Each record's payload
+/// is compressed independently (rather than the stream as a whole) so the
+/// stream stays block-seekable and a consumer can resume mid-stream without
+/// re-reading earlier records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Zstd,
+    #[cfg(feature = "bz2")]
+    Bzip2,
+    #[cfg(feature = "lzma")]
+    Lzma,
+}
+
+impl Codec {
+    fn tag(self) -> u8 {
+        match self {
+            Codec::Zstd => 0,
+            #[cfg(feature = "bz2")]
+            Codec::Bzip2 => 1,
+            #[cfg(feature = "lzma")]
+            Codec::Lzma => 2,
+        }
+    }
+
+    /// Parse a `--codec` argument. `zstd` is always available; `bz2`/`lzma`
+    /// only exist when built with the matching cargo feature.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zstd" => Some(Codec::Zstd),
+            #[cfg(feature = "bz2")]
+            "bz2" | "bzip2" => Some(Codec::Bzip2),
+            #[cfg(feature = "lzma")]
+            "lzma" | "xz" => Some(Codec::Lzma),
+            _ => None,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Zstd => zstd::stream::encode_all(data, 0),
+            #[cfg(feature = "bz2")]
+            Codec::Bzip2 => {
+                use bzip2::write::BzEncoder;
+                let mut enc = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                enc.write_all(data)?;
+                enc.finish()
+            }
+            #[cfg(feature = "lzma")]
+            Codec::Lzma => {
+                use xz2::write::XzEncoder;
+                let mut enc = XzEncoder::new(Vec::new(), 6);
+                enc.write_all(data)?;
+                enc.finish()
+            }
+        }
+    }
+}
+
+/// One change to replicate: change kind (add/modify/delete), full path, and (for added or
+/// modified regular files) the current file contents. Directories,
+/// deletions and anything whose data couldn't be read ship with an empty
+/// payload.
+pub struct Record {
+    pub kind: u8,
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+/// Write a self-describing, seekable change stream: a small header (magic,
+/// codec, source/base snapshot IDs) followed by one length-prefixed,
+/// independently-compressed record per change. Each record's frame stores
+/// its uncompressed/compressed lengths and a crc32 of the compressed
+/// payload, so a consumer can validate, skip, or resume records without
+/// decompressing the whole stream.
+pub fn write_stream<W: Write>(
+    out: &mut W,
+    codec: Codec,
+    child_snapshot: u32,
+    base_snapshot: Option<u32>,
+    records: &[Record],
+) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[FORMAT_VERSION, codec.tag()])?;
+    out.write_all(&child_snapshot.to_le_bytes())?;
+    out.write_all(&[base_snapshot.is_some() as u8])?;
+    out.write_all(&base_snapshot.unwrap_or(0).to_le_bytes())?;
+
+    for record in records {
+        let compressed = codec.compress(&record.data)?;
+        let crc = crc32fast::hash(&compressed);
+        let path_bytes = record.path.as_bytes();
+
+        out.write_all(&[record.kind])?;
+        out.write_all(&(path_bytes.len() as u32).to_le_bytes())?;
+        out.write_all(path_bytes)?;
+        out.write_all(&(record.data.len() as u64).to_le_bytes())?;
+        out.write_all(&(compressed.len() as u64).to_le_bytes())?;
+        out.write_all(&crc.to_le_bytes())?;
+        out.write_all(&compressed)?;
+    }
+
+    Ok(())
+}
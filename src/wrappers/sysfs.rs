@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
@@ -73,3 +74,82 @@ pub fn fs_get_devices(sysfs_path: &Path) -> Result<Vec<DevInfo>> {
     devs.sort_by_key(|d| d.idx);
     Ok(devs)
 }
+
+/// Read each device's bcachefs label (sysfs `dev-N/label`, a dotted
+/// hierarchical name such as `ssd.nvme`) and map it to the "device" target ID
+/// that options like `bi_background_target` store (target encoding:
+/// `(dev_idx << 1) | 1`, target `0` meaning "none").
+///
+/// A target can also name a whole label *group* (several devices sharing a
+/// label prefix) rather than one device; resolving those needs the
+/// superblock's `disk_groups` section, which isn't exposed over sysfs, so
+/// group targets are left unresolved here (callers fall back to printing the
+/// raw target number).
+pub fn read_target_labels(sysfs_path: &Path) -> HashMap<u16, String> {
+    let mut labels = HashMap::new();
+
+    let Ok(devs) = fs_get_devices(sysfs_path) else {
+        return labels;
+    };
+
+    for dev in devs {
+        let label_path = sysfs_path.join(format!("dev-{}", dev.idx)).join("label");
+        let label = fs::read_to_string(&label_path)
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .unwrap_or(dev.dev);
+
+        let target_id = ((dev.idx as u16) << 1) | 1;
+        labels.insert(target_id, label);
+    }
+
+    labels
+}
+
+/// Read a filesystem-level option's current value from its sysfs options
+/// file (`<sysfs_path>/options/<name>`).
+///
+/// bcachefs exposes choice-type options as a space-separated list with the
+/// active choice in brackets (e.g. `none lz4 [zstd]`); other types are a
+/// plain scalar. Returns `None` if the file doesn't exist (not every inode
+/// option has a filesystem-wide counterpart exposed this way).
+pub fn read_fs_option(sysfs_path: &Path, name: &str) -> Option<String> {
+    let contents = fs::read_to_string(sysfs_path.join("options").join(name)).ok()?;
+    Some(parse_fs_option_value(&contents))
+}
+
+/// Pick out the active value from a raw sysfs options file's contents: the
+/// bracketed choice in a choice-type list (`none lz4 [zstd]` -> `zstd`), or
+/// the trimmed contents verbatim for a plain scalar.
+fn parse_fs_option_value(contents: &str) -> String {
+    let contents = contents.trim();
+
+    for tok in contents.split_whitespace() {
+        if let Some(choice) = tok.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            return choice.to_string();
+        }
+    }
+
+    contents.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fs_option_value_picks_bracketed_choice() {
+        assert_eq!(parse_fs_option_value("none lz4 [zstd] gzip\n"), "zstd");
+    }
+
+    #[test]
+    fn parse_fs_option_value_plain_scalar() {
+        assert_eq!(parse_fs_option_value("4096\n"), "4096");
+    }
+
+    #[test]
+    fn parse_fs_option_value_no_brackets_falls_back_to_trimmed_contents() {
+        assert_eq!(parse_fs_option_value("  none lz4 zstd  "), "none lz4 zstd");
+    }
+}